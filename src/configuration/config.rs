@@ -1,8 +1,7 @@
 use std::collections::HashMap;
 use java_properties::read;
 use std::fs::File;
-use std::io::BufReader;
-use crate::try_except_return_default;
+use std::io::{self, BufReader};
 
 use crate::configuration::exceptions;
 use std::path::Path;
@@ -19,18 +18,19 @@ impl Config {
             properties: Default::default()
         }
     }
-    pub fn read(&mut self) {
+    // The file must exist, properties that cannot be parsed are an error rather than an empty configuration
+    pub fn read(&mut self) -> io::Result<()> {
         let path: &Path = Path::new(self.filename.as_str());
         let file: File = match File::open(&path) {
             Err(_) => panic!("{}", exceptions::FileError{filename: self.filename.clone()}),
             Ok(file) => file,
         };
 
-        self.properties = try_except_return_default! {
-            read(BufReader::new(file)),
-            "Could not read properties",
-            HashMap::new()
-        };
+        self.properties = read(BufReader::new(file)).map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Could not read properties from {}: {}", self.filename, e)
+        ))?;
+        Ok(())
     }
 
     pub fn get(&mut self, key: String) -> Result<String, exceptions::ConfigPropertiesError> {
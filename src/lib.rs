@@ -0,0 +1,25 @@
+pub use configuration::config::Config;
+pub use servlet::proxy::{ProxyBuilder, ProxyHandle};
+pub use tools::run as run_tool;
+pub use traffic::bindingset::{
+    BandwidthRule, BindingProtocol, BindingRule, BindingSet, BindingSetBuilder, BodyComparison, CompareRule, ConnectionLimits,
+    FaultRule, FaultType, FilterRule, ForwardingMode, ForwardingRule, HarBodies, HarRule, HeaderAction, HeaderActionType,
+    HeaderMapping, HeaderMatchMode, HttpMethod, HttpVersion, InterceptExpiry, InterceptRule, LatencyDistribution, LatencyRule,
+    MirrorRule, MockResponse, OverflowPolicy, PathMatchMode, ProxyProtocolRule, ProxyProtocolVersion, RateLimitKey, RateLimitRule,
+    Rule, RuleSet, RuleType, TrafficDirection, UrlAction, UrlActionType, UrlMatch, VersionAction, WasmFilterRule,
+};
+pub use traffic::filter::{Filter, FilterAction, FilterConnection};
+pub use traffic::http::message::{HttpHeaders, HttpRequestHead, HttpResponseHead};
+pub use traffic::wasm::WasmFilter;
+
+pub(crate) mod configuration;
+pub(crate) mod servlet;
+pub(crate) mod tools;
+pub(crate) mod traffic;
+mod macros;
+
+#[macro_use]
+extern crate slog;
+extern crate lazy_static;
+extern crate regex;
+extern crate pnet;
@@ -3,19 +3,12 @@ use std::fs;
 use std::sync::Mutex;
 
 use lazy_static::lazy_static;
-use slog::{Drain, Duplicate, Fuse, Logger};
+use slog::{Drain, Duplicate, Fuse, Logger, Never, OwnedKVList, Record};
 use slog_async::{Async, OverflowStrategy};
 use slog_json::Json;
 use slog_term::{FullFormat, TermDecorator};
 
-use configuration::config;
-use servlet::proxy;
-use traffic::bindingset;
-
-mod configuration;
-mod servlet;
-mod traffic;
-mod macros;
+use tcp_proxy::{Config, ProxyBuilder, ProxyHandle};
 
 #[macro_use]
 extern crate slog;
@@ -23,8 +16,6 @@ extern crate slog_term;
 extern crate slog_async;
 extern crate slog_json;
 extern crate lazy_static;
-extern crate regex;
-extern crate pnet;
 
 fn initialize_logging() ->  slog::Logger {
     let log_path: &str = "logs/";
@@ -61,18 +52,64 @@ fn initialize_logging() ->  slog::Logger {
     log
 }
 
+// Response differences between a binding's upstream and its mirror, kept apart from the traffic log
+fn initialize_diff_logging() -> slog::Logger {
+    let log_file_path: String = format!("logs/diff_{}{}", chrono::Utc::now().to_string(), ".log");
+    let _ = fs::create_dir("logs/");
+    let file: File = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(log_file_path.as_str())
+        .unwrap();
+    let json: Fuse<Json<File>> = Json::default(file).fuse();
+    let json: Fuse<Async> = Async::new(Mutex::new(json).fuse())
+        .overflow_strategy(OverflowStrategy::Block)
+        .build()
+        .fuse();
+    Logger::root(json, o!())
+}
+
 lazy_static! {
     static ref LOGGER: Logger = initialize_logging();
+    static ref DIFF_LOGGER: Logger = initialize_diff_logging();
+}
+
+// Opens the log files on the first record, so subcommands that never log leave none behind
+struct DeferredDrain {
+    logger: fn() -> &'static Logger,
+}
+
+impl Drain for DeferredDrain {
+    type Ok = ();
+    type Err = Never;
+    fn log(&self, record: &Record, _: &OwnedKVList) -> Result<(), Never> {
+        (self.logger)().log(record);
+        Ok(())
+    }
 }
 
 fn main() {
-    let mut properties: config::Config = config::Config::new("config/config.properties");
-    properties.read();
-    let mut binding_set: bindingset::BindingSet = bindingset::BindingSet::from_file(
-        String::from("1"),
-        String::from("config/traffic.json")
-    );
-    binding_set.set_applied(true);
-    let mut tcp_proxy: proxy::Proxy = proxy::Proxy::new(properties);
-    tcp_proxy.start(binding_set);
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(code) = args.get(1).and_then(|command: &String| tcp_proxy::run_tool(command.as_str(), &args[2..])) {
+        std::process::exit(code);
+    }
+    let mut properties: Config = Config::new("config/config.properties");
+    if let Err(e) = properties.read() {
+        eprintln!("Unable to start proxy: {}", e);
+        std::process::exit(1);
+    }
+    let handle: ProxyHandle = match ProxyBuilder::new()
+        .config(properties)
+        .bindings_file("config/traffic.json")
+        .logger(Logger::root(DeferredDrain { logger: || &*LOGGER }, o!()))
+        .diff_logger(Logger::root(DeferredDrain { logger: || &*DIFF_LOGGER }, o!()))
+        .start() {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("Unable to start proxy: {}", e);
+            std::process::exit(1);
+        },
+    };
+    handle.wait();
 }
\ No newline at end of file
@@ -0,0 +1,233 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use serde_json::{json, Value};
+use slog::Logger;
+
+use crate::servlet::binding_context::BindingContext;
+use crate::servlet::reload::BindingReloader;
+use crate::servlet::shutdown::{ShutdownGuard, ShutdownSignal};
+use crate::traffic::http::har::BindingHar;
+use crate::traffic::http::intercept::BindingIntercept;
+use crate::traffic::http::message::{HttpRequestHead, HttpResponseHead};
+use crate::traffic::http::stream;
+use crate::traffic::http::url::RequestUrl;
+use crate::try_except_return;
+
+type Byte = u8;
+
+static ADMIN_READ_TIMEOUT: Duration = Duration::from_secs(5);
+static MAX_ADMIN_REQUEST_BYTES: usize = 1024 * 1024;
+
+// Serves the proxy's state to operators over HTTP/1.1, one request per connection
+pub(crate) struct AdminServer {
+    bindings: Arc<BindingReloader>,
+    logger: Logger,
+}
+
+impl AdminServer {
+    pub fn new(bindings: Arc<BindingReloader>, logger: &Logger) -> AdminServer {
+        AdminServer {
+            bindings,
+            logger: logger.clone(),
+        }
+    }
+    pub fn start(self, address: &str, shutdown: &Arc<ShutdownSignal>) -> Option<SocketAddr> {
+        let listener: TcpListener = match TcpListener::bind(address) {
+            Ok(listener) => listener,
+            Err(e) => {
+                info!(self.logger, "ADMIN Unable to bind [{}]: {}", address, e);
+                return None;
+            },
+        };
+        let bound: Option<SocketAddr> = listener.local_addr().ok();
+        info!(self.logger, "ADMIN Listening on [{}]", bound.map_or(String::from(address), |bound: SocketAddr| bound.to_string()));
+        let server: Arc<AdminServer> = Arc::new(self);
+        let shutdown: Arc<ShutdownSignal> = shutdown.clone();
+        let running: ShutdownGuard = ShutdownSignal::enter(&shutdown);
+        thread::spawn(move || {
+            loop {
+                let (stream, _) = try_except_return!{server.logger, listener.accept(), "Failed to accept admin connection"};
+                if shutdown.requested() {
+                    drop(listener);
+                    drop(running);
+                    return;
+                }
+                let server_clone: Arc<AdminServer> = server.clone();
+                thread::spawn(move || server_clone.handle(stream));
+            }
+        });
+        bound
+    }
+    fn handle(&self, mut stream: TcpStream) {
+        let _ = stream.set_read_timeout(Some(ADMIN_READ_TIMEOUT));
+        let (mut response, body): (HttpResponseHead, Vec<Byte>) = match AdminServer::read_request(&mut stream) {
+            Some((request, request_body)) => {
+                debug!(self.logger, "ADMIN {} {}", request.method, request.target);
+                self.route(&request, request_body.as_slice())
+            },
+            None => error(400, "Malformed request"),
+        };
+        response.headers.set("Connection", String::from("close"));
+        let _ = stream.write_all(response.to_bytes_with_body(body.as_slice()).as_slice());
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    fn read_request(stream: &mut TcpStream) -> Option<(HttpRequestHead, Vec<Byte>)> {
+        let mut input: Vec<Byte> = vec![];
+        let mut buffer: [Byte; 4096] = [0; 4096];
+        let head_end: usize = loop {
+            if let Some(index) = stream::find_subsequence(input.as_slice(), b"\r\n\r\n") {
+                break index;
+            }
+            let read: usize = stream.read(&mut buffer).ok().filter(|read: &usize| *read > 0)?;
+            input.extend_from_slice(&buffer[..read]);
+            if input.len() > MAX_ADMIN_REQUEST_BYTES {
+                return None;
+            }
+        };
+        let request: HttpRequestHead = HttpRequestHead::parse(&input[..head_end])?;
+        let length: usize = request.headers.get("content-length").map_or(Some(0), |length: &str| length.trim().parse::<usize>().ok())?;
+        if length > MAX_ADMIN_REQUEST_BYTES {
+            return None;
+        }
+        let mut body: Vec<Byte> = input.split_off(head_end + 4);
+        while body.len() < length {
+            let read: usize = stream.read(&mut buffer).ok().filter(|read: &usize| *read > 0)?;
+            body.extend_from_slice(&buffer[..read]);
+        }
+        body.truncate(length);
+        Some((request, body))
+    }
+    fn route(&self, request: &HttpRequestHead, body: &[Byte]) -> (HttpResponseHead, Vec<Byte>) {
+        let url: RequestUrl = RequestUrl::new(request);
+        let segments: Vec<&str> = url.path.split('/').filter(|segment: &&str| !segment.is_empty()).collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["har", binding]) => self.har(binding, &url),
+            (_, ["har", _]) => error(405, "Method not allowed"),
+            ("GET", ["bindings"]) => self.bindings(),
+            ("GET", ["connections", binding]) => self.connections(binding),
+            ("GET", ["verdicts", binding]) => self.verdicts(binding, &url),
+            (_, ["bindings"]) | (_, ["connections", _]) | (_, ["verdicts", _]) => error(405, "Method not allowed"),
+            ("GET", ["intercepts"]) => self.intercepts(),
+            ("GET", ["intercepts", id]) => self.intercepted(id),
+            ("POST", ["intercepts", id]) => self.decide(id, body),
+            (_, ["intercepts", ..]) => error(405, "Method not allowed"),
+            ("POST", ["reload"]) => self.reload(),
+            (_, ["reload"]) => error(405, "Method not allowed"),
+            _ => error(404, "Not found"),
+        }
+    }
+    fn binding(&self, name: &str) -> Option<Arc<BindingContext>> {
+        self.bindings.contexts().into_iter().find(|context: &Arc<BindingContext>| context.rule.name == name)
+    }
+    // Each binding with its traffic so far, the admin API's clients derive throughput from the byte counts
+    fn bindings(&self) -> (HttpResponseHead, Vec<Byte>) {
+        let mut bindings: Vec<Value> = self.bindings.contexts().iter().map(|context: &Arc<BindingContext>| {
+            let mut binding: Value = context.monitor.totals();
+            binding["name"] = json!(context.rule.name);
+            binding["from"] = json!(context.rule.from);
+            binding["to"] = json!(context.rule.to);
+            binding["protocol"] = json!(format!("{:?}", context.rule.protocol));
+            binding["har"] = json!(context.har.as_deref().map_or(false, |har: &BindingHar| har.rule.buffer > 0));
+            binding["intercept"] = json!(context.intercept.is_some());
+            binding
+        }).collect();
+        bindings.sort_by(|a: &Value, b: &Value| a["name"].as_str().cmp(&b["name"].as_str()));
+        json_response(200, &json!({"bindings": bindings}))
+    }
+    fn connections(&self, binding: &str) -> (HttpResponseHead, Vec<Byte>) {
+        match self.binding(binding) {
+            Some(context) => json_response(200, &json!({"connections": context.monitor.connections()})),
+            None => error(404, format!("Unknown binding [{}]", binding).as_str()),
+        }
+    }
+    // What the binding's rules decided for its latest requests, ?last=N limits how many
+    fn verdicts(&self, binding: &str, url: &RequestUrl) -> (HttpResponseHead, Vec<Byte>) {
+        let context: Arc<BindingContext> = match self.binding(binding) {
+            Some(context) => context,
+            None => return error(404, format!("Unknown binding [{}]", binding).as_str()),
+        };
+        match AdminServer::last(url) {
+            Ok(last) => json_response(200, &json!({"verdicts": context.monitor.verdicts(last)})),
+            Err(response) => response,
+        }
+    }
+    fn last(url: &RequestUrl) -> Result<usize, (HttpResponseHead, Vec<Byte>)> {
+        match url.query_values("last").first() {
+            Some(last) => last.parse::<usize>().map_err(|_| error(400, format!("Invalid last: {}", last).as_str())),
+            None => Ok(0),
+        }
+    }
+    // The binding's latest exchanges as a HAR 1.2 document, ?last=N limits how many
+    fn har(&self, binding: &str, url: &RequestUrl) -> (HttpResponseHead, Vec<Byte>) {
+        let context: Arc<BindingContext> = match self.binding(binding) {
+            Some(context) => context,
+            None => return error(404, format!("Unknown binding [{}]", binding).as_str()),
+        };
+        let har: &BindingHar = match context.har.as_deref().filter(|har: &&BindingHar| har.rule.buffer > 0) {
+            Some(har) => har,
+            None => return error(404, format!("Binding [{}] does not buffer exchanges", binding).as_str()),
+        };
+        match AdminServer::last(url) {
+            Ok(last) => json_response(200, &har.last(last)),
+            Err(response) => response,
+        }
+    }
+    fn intercepts(&self) -> (HttpResponseHead, Vec<Byte>) {
+        let mut held: Vec<Value> = self.bindings.contexts().iter()
+            .filter_map(|context: &Arc<BindingContext>| context.intercept.as_deref())
+            .flat_map(|intercept: &BindingIntercept| intercept.held())
+            .collect();
+        held.sort_by_key(|message: &Value| message["id"].as_u64());
+        json_response(200, &json!({"held": held}))
+    }
+    fn intercepted(&self, id: &str) -> (HttpResponseHead, Vec<Byte>) {
+        let id: u64 = match id.parse::<u64>() {
+            Ok(id) => id,
+            Err(_) => return error(400, format!("Invalid id: {}", id).as_str()),
+        };
+        match self.bindings.contexts().iter().filter_map(|context: &Arc<BindingContext>| context.intercept.as_deref()).find_map(|intercept: &BindingIntercept| intercept.get(id)) {
+            Some(message) => json_response(200, &message),
+            None => error(404, format!("No message held as {}", id).as_str()),
+        }
+    }
+    // The body names the action and any edits, an empty body forwards the message unchanged
+    fn decide(&self, id: &str, body: &[Byte]) -> (HttpResponseHead, Vec<Byte>) {
+        let id: u64 = match id.parse::<u64>() {
+            Ok(id) => id,
+            Err(_) => return error(400, format!("Invalid id: {}", id).as_str()),
+        };
+        let decision: Value = match body.is_empty() {
+            true => json!({}),
+            false => match serde_json::from_slice::<Value>(body) {
+                Ok(decision) if decision.is_object() => decision,
+                _ => return error(400, "Decision must be a JSON object"),
+            },
+        };
+        match self.bindings.contexts().iter().filter_map(|context: &Arc<BindingContext>| context.intercept.as_deref()).find_map(|intercept: &BindingIntercept| intercept.decide(id, &decision)) {
+            Some(Ok(action)) => json_response(200, &json!({"id": id, "action": action})),
+            Some(Err(e)) => error(400, e.as_str()),
+            None => error(404, format!("No message held as {}", id).as_str()),
+        }
+    }
+    // Reads the bindings file again, new connections get the reloaded bindings
+    fn reload(&self) -> (HttpResponseHead, Vec<Byte>) {
+        match self.bindings.reload_source() {
+            Ok(reloaded) => json_response(200, &json!({"reloaded": reloaded})),
+            Err(e) if e.kind() == ErrorKind::Unsupported => error(409, e.to_string().as_str()),
+            Err(e) => error(400, e.to_string().as_str()),
+        }
+    }
+}
+
+pub(crate) fn json_response(status: u16, value: &Value) -> (HttpResponseHead, Vec<Byte>) {
+    let mut response: HttpResponseHead = HttpResponseHead::local(status);
+    response.headers.set("Content-Type", String::from("application/json"));
+    (response, value.to_string().into_bytes())
+}
+
+pub(crate) fn error(status: u16, message: &str) -> (HttpResponseHead, Vec<Byte>) {
+    json_response(status, &json!({"error": message}))
+}
@@ -0,0 +1,118 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, PoisonError, RwLock};
+use slog::Logger;
+
+use crate::servlet::connection_tracker::ConnectionTracker;
+use crate::traffic::bandwidth::BindingBandwidth;
+use crate::traffic::bindingset::BindingRule;
+use crate::traffic::enforcer::Enforcer;
+use crate::traffic::faults::BindingFaults;
+use crate::traffic::filter::FilterChain;
+use crate::traffic::http::forwarding::ForwardingHeaders;
+use crate::traffic::http::har::BindingHar;
+use crate::traffic::http::intercept::BindingIntercept;
+use crate::traffic::mirror::BindingMirror;
+use crate::traffic::monitor::BindingMonitor;
+
+// Runtime state shared by every connection accepted on a binding
+pub(crate) struct BindingContext {
+    pub rule: BindingRule,
+    pub upstream: Option<SocketAddr>, // Resolved from the rule's "to", None when the binding answers every request itself
+    pub enforcer: Arc<Enforcer>,
+    pub forwarding: ForwardingHeaders,
+    pub connections: Arc<ConnectionTracker>,
+    pub bandwidth: Arc<BindingBandwidth>,
+    pub faults: BindingFaults,
+    pub mirror: Option<BindingMirror>,
+    pub har: Option<Arc<BindingHar>>,
+    pub intercept: Option<Arc<BindingIntercept>>,
+    pub monitor: Arc<BindingMonitor>,
+    pub filters: FilterChain,
+    pub logger: Logger, // The proxy's, so proxies sharing a process keep their logs apart
+    pub diff_logger: Logger,
+}
+
+impl BindingContext {
+    pub fn new(rule: BindingRule, upstream: Option<SocketAddr>, filters: FilterChain, logger: &Logger, diff_logger: &Logger) -> io::Result<BindingContext> {
+        let invalid = |e: io::Error| BindingContext::invalid(&rule, e);
+        Ok(BindingContext {
+            upstream,
+            enforcer: Arc::new(Enforcer::new(&rule.rules, logger).map_err(invalid)?),
+            forwarding: ForwardingHeaders::new(&rule.forwarding).map_err(invalid)?,
+            bandwidth: Arc::new(BindingBandwidth::new(&rule.bandwidth)),
+            faults: BindingFaults::new(&rule.faults, logger).map_err(invalid)?,
+            mirror: BindingMirror::new(&rule.mirror, logger).map_err(invalid)?,
+            har: BindingHar::new(rule.name.as_str(), rule.har.as_ref(), logger).map(Arc::new),
+            intercept: BindingIntercept::new(rule.name.as_str(), rule.intercept.as_ref(), logger).map_err(invalid)?.map(Arc::new),
+            monitor: Arc::new(BindingMonitor::new()),
+            filters,
+            connections: Arc::new(ConnectionTracker::new(rule.name.clone(), rule.connection_limits.clone(), logger)),
+            logger: logger.clone(),
+            diff_logger: diff_logger.clone(),
+            rule,
+        })
+    }
+    // Keeps the state of whatever the reload left unchanged, so rate limits, connection caps, buffered exchanges
+    // and held messages carry on where they were. Traffic totals always carry on
+    pub fn reloaded(previous: &BindingContext, rule: BindingRule, upstream: Option<SocketAddr>, filters: FilterChain) -> io::Result<BindingContext> {
+        let unchanged: &BindingRule = &previous.rule;
+        let invalid = |e: io::Error| BindingContext::invalid(&rule, e);
+        Ok(BindingContext {
+            upstream,
+            enforcer: match rule.rules == unchanged.rules {
+                true => previous.enforcer.clone(),
+                false => Arc::new(Enforcer::new(&rule.rules, &previous.logger).map_err(invalid)?),
+            },
+            forwarding: ForwardingHeaders::new(&rule.forwarding).map_err(invalid)?,
+            bandwidth: match rule.bandwidth == unchanged.bandwidth {
+                true => previous.bandwidth.clone(),
+                false => Arc::new(BindingBandwidth::new(&rule.bandwidth)),
+            },
+            faults: BindingFaults::new(&rule.faults, &previous.logger).map_err(invalid)?,
+            mirror: BindingMirror::new(&rule.mirror, &previous.logger).map_err(invalid)?,
+            har: match rule.har == unchanged.har {
+                true => previous.har.clone(),
+                false => BindingHar::new(rule.name.as_str(), rule.har.as_ref(), &previous.logger).map(Arc::new),
+            },
+            intercept: match rule.intercept == unchanged.intercept {
+                true => previous.intercept.clone(),
+                false => BindingIntercept::new(rule.name.as_str(), rule.intercept.as_ref(), &previous.logger).map_err(invalid)?.map(Arc::new),
+            },
+            monitor: previous.monitor.clone(),
+            filters,
+            connections: match rule.connection_limits == unchanged.connection_limits {
+                true => previous.connections.clone(),
+                false => Arc::new(ConnectionTracker::new(rule.name.clone(), rule.connection_limits.clone(), &previous.logger)),
+            },
+            logger: previous.logger.clone(),
+            diff_logger: previous.diff_logger.clone(),
+            rule,
+        })
+    }
+    fn invalid(rule: &BindingRule, e: io::Error) -> io::Error {
+        io::Error::new(e.kind(), format!("Binding [{}]: {}", rule.name, e))
+    }
+}
+
+// ---- BindingSlot ----
+
+// Where a binding's acceptor and the admin API find its context. Reloading swaps the context,
+// connections already accepted keep the one they started with
+pub(crate) struct BindingSlot {
+    context: RwLock<Arc<BindingContext>>,
+}
+
+impl BindingSlot {
+    pub fn new(context: BindingContext) -> BindingSlot {
+        BindingSlot {
+            context: RwLock::new(Arc::new(context)),
+        }
+    }
+    pub fn current(&self) -> Arc<BindingContext> {
+        self.context.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+    pub fn replace(&self, context: BindingContext) {
+        *self.context.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(context);
+    }
+}
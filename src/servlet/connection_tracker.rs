@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use slog::Logger;
+
+use crate::servlet::shutdown::ShutdownSignal;
+use crate::traffic::bindingset::{ConnectionLimits, OverflowPolicy};
+
+static SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Counts open connections against a set of caps, either for one binding or for the whole proxy
+pub(crate) struct ConnectionTracker {
+    pub name: String,
+    limits: ConnectionLimits,
+    counts: Mutex<ConnectionCounts>,
+    released: Condvar,
+    logger: Logger,
+}
+
+#[derive(Default)]
+struct ConnectionCounts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    queued: usize,
+}
+
+// Held for the lifetime of a connection, the slot is released when it is dropped.
+// Reserved before the connection is accepted, the client IP is counted once it is known
+pub(crate) struct ConnectionPermit {
+    tracker: Arc<ConnectionTracker>,
+    ip: Option<IpAddr>,
+}
+
+impl ConnectionTracker {
+    pub fn new(name: String, limits: ConnectionLimits, logger: &Logger) -> ConnectionTracker {
+        ConnectionTracker {
+            name,
+            limits,
+            counts: Mutex::new(ConnectionCounts::default()),
+            released: Condvar::new(),
+            logger: logger.clone(),
+        }
+    }
+    // None when the cap is full, after queueing for a slot if the overflow policy allows it, or on shutdown
+    pub fn reserve(tracker: &Arc<ConnectionTracker>, shutdown: &ShutdownSignal) -> Option<ConnectionPermit> {
+        let counts: MutexGuard<ConnectionCounts> = tracker.wait_for_slot(tracker.counts.lock().unwrap(), shutdown);
+        match tracker.has_capacity(&counts) {
+            true => Some(ConnectionTracker::take(tracker, counts)),
+            false => None,
+        }
+    }
+    // Waited on before accepting, so connections over the cap wait in the listen backlog rather than on a thread.
+    // The slot is only taken once a connection has been accepted, an idle listener holds nothing
+    pub fn wait_for_capacity(&self, shutdown: &ShutdownSignal) -> bool {
+        let counts: MutexGuard<ConnectionCounts> = self.wait_for_slot(self.counts.lock().unwrap(), shutdown);
+        self.has_capacity(&counts)
+    }
+    // Never waits, for a slot that freed up while the accept loop was waiting on a connection
+    pub fn try_reserve(tracker: &Arc<ConnectionTracker>) -> Option<ConnectionPermit> {
+        let counts: MutexGuard<ConnectionCounts> = tracker.counts.lock().unwrap();
+        match tracker.has_capacity(&counts) {
+            true => Some(ConnectionTracker::take(tracker, counts)),
+            false => None,
+        }
+    }
+    fn take(tracker: &Arc<ConnectionTracker>, mut counts: MutexGuard<ConnectionCounts>) -> ConnectionPermit {
+        counts.total += 1;
+        ConnectionPermit {
+            tracker: tracker.clone(),
+            ip: None,
+        }
+    }
+    fn wait_for_slot<'a>(&'a self, mut counts: MutexGuard<'a, ConnectionCounts>, shutdown: &ShutdownSignal) -> MutexGuard<'a, ConnectionCounts> {
+        if self.has_capacity(&counts) || self.limits.overflow == OverflowPolicy::REJECT {
+            return counts;
+        }
+        let deadline: Instant = Instant::now() + Duration::from_millis(self.limits.queue_timeout_ms);
+        counts.queued += 1;
+        while !self.has_capacity(&counts) {
+            let now: Instant = Instant::now();
+            if now >= deadline || shutdown.requested() {
+                break;
+            }
+            // Woken periodically to notice a shutdown, which has to get the accept loop back to its listener
+            counts = self.released.wait_timeout(counts, (deadline - now).min(SHUTDOWN_POLL_INTERVAL)).unwrap().0;
+        }
+        counts.queued -= 1;
+        counts
+    }
+    // Open connections, distinct client IPs and connections waiting in the queue
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let counts: MutexGuard<ConnectionCounts> = self.counts.lock().unwrap();
+        (counts.total, counts.per_ip.len(), counts.queued)
+    }
+    fn has_capacity(&self, counts: &ConnectionCounts) -> bool {
+        self.limits.max_connections == 0 || counts.total < self.limits.max_connections
+    }
+}
+
+impl ConnectionPermit {
+    // Never queues, QUEUE overflow is refused with per IP caps when the limits are parsed
+    pub fn admit(&mut self, ip: IpAddr) -> bool {
+        let tracker: &ConnectionTracker = &self.tracker;
+        let mut counts: MutexGuard<ConnectionCounts> = tracker.counts.lock().unwrap();
+        let open: usize = counts.per_ip.get(&ip).map_or(0, |count: &usize| *count);
+        if tracker.limits.max_connections_per_ip != 0 && open >= tracker.limits.max_connections_per_ip {
+            return false;
+        }
+        counts.per_ip.insert(ip, open + 1);
+        debug!(tracker.logger, "Connections [{}]: {} open, {} from [{}]", tracker.name, counts.total, open + 1, ip);
+        self.ip = Some(ip);
+        true
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut counts: MutexGuard<ConnectionCounts> = self.tracker.counts.lock().unwrap();
+        counts.total -= 1;
+        if let Some(ip) = self.ip {
+            if let Some(count) = counts.per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.per_ip.remove(&ip);
+                }
+            }
+        }
+        self.tracker.released.notify_all();
+    }
+}
@@ -1,3 +1,8 @@
+pub mod admin;
+pub mod binding_context;
+pub mod connection_tracker;
 pub mod proxy;
+pub mod reload;
 pub mod request_metadata;
-pub mod threading;
\ No newline at end of file
+pub mod shutdown;
+pub mod threading;
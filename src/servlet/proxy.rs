@@ -1,49 +1,116 @@
+use std::collections::HashMap;
 use std::net::{SocketAddr, ToSocketAddrs};
-use std::net::{TcpListener, TcpStream};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::io::{self, Read, Write};
+use std::time::Duration;
 use rayon::ThreadPool;
+use slog::{Discard, Logger};
 use std::thread;
 use std::sync::{Arc, Mutex};
 
 use crate::configuration::config::Config;
 use crate::traffic::bindingset;
+use crate::traffic::bindingset::{BindingProtocol, ConnectionLimits, OverflowPolicy, ProxyProtocolRule, ProxyProtocolVersion};
+use crate::traffic::enforcer;
+use crate::traffic::filter::{Filter, FilterAction, FilterChain, FilterConnection, FilteredConnection};
+use crate::traffic::http::diff::ResponseComparator;
+use crate::traffic::http::message::HttpResponseHead;
+use crate::traffic::monitor::BindingMonitor;
+use crate::traffic::proxy_protocol::{self, ProxyHeader};
+use crate::traffic::recording::recorder::{RecordingSettings, SessionRecorder};
+use crate::servlet::admin::AdminServer;
 use crate::servlet::request_metadata::RequestMetadata;
+use crate::servlet::binding_context::{BindingContext, BindingSlot};
+use crate::servlet::connection_tracker::{ConnectionTracker, ConnectionPermit};
+use crate::servlet::reload::BindingReloader;
+use crate::servlet::shutdown::{ShutdownGuard, ShutdownSignal};
 use crate::{try_except_return, inc, ternary};
 use crate::servlet::threading::thread_handler::{ThreadHandler, ThreadHandlerType, ThreadHandlerMethod};
-use crate::traffic::bindingset::BindingRule;
 use std::vec::IntoIter;
 
 pub struct ListenerBinding {
     pub id: u64,
-    pub listener: *const TcpListener,
-    pub rule: bindingset::BindingRule,
+    pub address: SocketAddr, // As bound, so a "from" port of 0 reads as the port the system chose
+    pub(crate) slot: Arc<BindingSlot>,
 }
 
 
 pub struct Proxy {
     pub(crate) thread_handler_type: ThreadHandlerType,
     pub thread_pool: ThreadPool,
-    pub listeners: Vec<ListenerBinding>
+    pub listeners: Vec<ListenerBinding>,
+    pub(crate) connections: Arc<ConnectionTracker>, // Caps across every binding
+    pub connection_stats_interval: Duration,
+    pub(crate) recorder: Option<Arc<SessionRecorder>>, // Set when recording_directory is configured
+    pub admin_address: Option<String>,
+    pub(crate) shutdown: Arc<ShutdownSignal>,
+    pub(crate) filters: HashMap<String, Arc<dyn Filter>>, // Chained onto bindings by name
+    pub bindings_file: Option<String>, // Where the admin API reloads the bindings from
+    pub(crate) logger: Logger,
+    pub(crate) diff_logger: Logger, // Response comparisons, often kept apart from the traffic log
 }
 
 static THREAD_POOL_SIZE_KEY: &'static str = "thread_pool_size";
 static HANDLER_TYPE_KEY: &'static str = "thread_handler_type";
+static MAX_CONNECTIONS_KEY: &'static str = "max_connections";
+static MAX_CONNECTIONS_PER_IP_KEY: &'static str = "max_connections_per_ip";
+static CONNECTION_OVERFLOW_KEY: &'static str = "connection_overflow";
+static CONNECTION_QUEUE_TIMEOUT_KEY: &'static str = "connection_queue_timeout_ms";
+static CONNECTION_STATS_INTERVAL_KEY: &'static str = "connection_stats_interval_secs";
+static RECORDING_DIRECTORY_KEY: &'static str = "recording_directory";
+static RECORDING_MAX_FILE_BYTES_KEY: &'static str = "recording_max_file_bytes";
+static RECORDING_MAX_TOTAL_BYTES_KEY: &'static str = "recording_max_total_bytes";
+static ADMIN_ADDRESS_KEY: &'static str = "admin_address";
+// A refusal is written on the accept loop, a client not reading it cannot hold up other connections for longer
+static REJECT_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
 
 impl Proxy {
-    pub fn new(configuration: Config) -> Proxy {
-        let thread_pool_size_str:Option<&String> = configuration.properties.get(THREAD_POOL_SIZE_KEY);
-        let thread_pool_size: usize = ternary!{
-            thread_pool_size_str.is_none(),
-            50,
-            thread_pool_size_str.unwrap().parse::<usize>().unwrap()
-        };
+    // Fails on properties that cannot be read, the error names the key
+    pub fn new(configuration: Config, logger: Logger, diff_logger: Logger) -> io::Result<Proxy> {
+        let thread_pool_size: usize = Proxy::read_number(&configuration, THREAD_POOL_SIZE_KEY, 50)? as usize;
         let thread_handler_type_str: Option<&String> = configuration.properties.get(HANDLER_TYPE_KEY);
         let thread_handler_type: ThreadHandlerType = ternary!{
             thread_handler_type_str.is_none(),
             ThreadHandlerType::PROGRESSIVE,
             thread_handler_type_str.unwrap().parse::<ThreadHandlerType>().unwrap()
         };
-        debug!{crate::LOGGER, "Creating proxy thread pool of size: {}", thread_pool_size};
-        Proxy {
+        let mut connection_limits: ConnectionLimits = ConnectionLimits::new();
+        connection_limits.max_connections = Proxy::read_number(&configuration, MAX_CONNECTIONS_KEY, 0)? as usize;
+        connection_limits.max_connections_per_ip = Proxy::read_number(&configuration, MAX_CONNECTIONS_PER_IP_KEY, 0)? as usize;
+        connection_limits.queue_timeout_ms = Proxy::read_number(&configuration, CONNECTION_QUEUE_TIMEOUT_KEY, 0)?;
+        if let Some(overflow) = configuration.properties.get(CONNECTION_OVERFLOW_KEY) {
+            connection_limits.overflow = overflow.parse::<OverflowPolicy>().map_err(|_| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown {}: {}", CONNECTION_OVERFLOW_KEY, overflow)
+            ))?;
+        }
+        if connection_limits.overflow == OverflowPolicy::QUEUE && connection_limits.queue_timeout_ms == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}=QUEUE needs a positive {}", CONNECTION_OVERFLOW_KEY, CONNECTION_QUEUE_TIMEOUT_KEY)
+            ));
+        }
+        if connection_limits.overflow == OverflowPolicy::QUEUE && connection_limits.max_connections_per_ip != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}=QUEUE cannot be used with {}", CONNECTION_OVERFLOW_KEY, MAX_CONNECTIONS_PER_IP_KEY)
+            ));
+        }
+        let connection_stats_interval: Duration = Duration::from_secs(Proxy::read_number(&configuration, CONNECTION_STATS_INTERVAL_KEY, 60)?);
+        let recorder: Option<Arc<SessionRecorder>> = match configuration.properties.get(RECORDING_DIRECTORY_KEY)
+            .filter(|directory: &&String| !directory.trim().is_empty()) {
+            Some(directory) => Some(Arc::new(SessionRecorder::new(RecordingSettings {
+                directory: String::from(directory.trim()),
+                max_file_bytes: Proxy::read_number(&configuration, RECORDING_MAX_FILE_BYTES_KEY, 64 * 1024 * 1024)?,
+                max_total_bytes: Proxy::read_number(&configuration, RECORDING_MAX_TOTAL_BYTES_KEY, 1024 * 1024 * 1024)?,
+            }, &logger))),
+            None => None,
+        };
+        let admin_address: Option<String> = configuration.properties.get(ADMIN_ADDRESS_KEY)
+            .map(|address: &String| String::from(address.trim()))
+            .filter(|address: &String| !address.is_empty());
+        debug!{logger, "Creating proxy thread pool of size: {}", thread_pool_size};
+        Ok(Proxy {
             thread_handler_type,
             thread_pool: rayon::ThreadPoolBuilder::new().num_threads(thread_pool_size)
                 .spawn_handler(|thread| {
@@ -56,94 +123,494 @@ impl Proxy {
                     b.spawn(|| thread.run())?;
                     Ok(())
                 })
-                .build().unwrap(),
+                .build()
+                .map_err(|e: rayon::ThreadPoolBuildError| io::Error::new(io::ErrorKind::Other, format!("Unable to create the thread pool: {}", e)))?,
             listeners: Vec::new(),
+            connections: Arc::new(ConnectionTracker::new(String::from("global"), connection_limits, &logger)),
+            connection_stats_interval,
+            recorder,
+            admin_address,
+            shutdown: Arc::new(ShutdownSignal::new()),
+            filters: HashMap::new(),
+            bindings_file: None,
+            logger,
+            diff_logger,
+        })
+    }
+    fn read_number(configuration: &Config, key: &str, default: u64) -> io::Result<u64> {
+        match configuration.properties.get(key) {
+            Some(value) => value.trim().parse::<u64>().map_err(|_| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid {}: {}", key, value)
+            )),
+            None => Ok(default),
         }
     }
-    pub fn initialize_bindings(&mut self, rule_set: bindingset::BindingSet) {
+    // Every listener is bound before any accepts, so a binding that fails leaves none of the others running
+    pub fn initialize_bindings(&mut self, rule_set: bindingset::BindingSet) -> io::Result<()> {
+        bindingset::check_unique_names(&rule_set.bindings)?;
+        let mut bound: Vec<(BindingContext, TcpListener)> = vec![];
+        for rule in rule_set.bindings {
+            let filters: FilterChain = FilterChain::resolve(&rule, &self.filters, &self.logger)?;
+            let proxy_addr: SocketAddr = Proxy::resolve_binding_address(rule.from.as_str(), &self.logger)?;
+            let to_addr: Option<SocketAddr> = Proxy::resolve_upstream(&rule, &self.logger)?;
+            let listener: TcpListener = TcpListener::bind(proxy_addr).map_err(|e: io::Error| io::Error::new(
+                e.kind(),
+                format!("Unable to bind proxy address [{}] for binding [{}]: {}", proxy_addr, rule.name, e)
+            ))?;
+            bound.push((BindingContext::new(rule, to_addr, filters, &self.logger, &self.diff_logger)?, listener));
+        }
         let mut incremental_listener_id: u64 = 0;
-        for mut rule in rule_set.bindings {
-            let (proxy_addr, to_addr) = (
-                Proxy::resolve_binding_address(rule.from.as_str()),
-                Proxy::resolve_binding_address(rule.to.as_str())
-            );
-            let mut listener: TcpListener = try_except_return!{TcpListener::bind(proxy_addr.to_string()), "Unable to bind proxy address"};
-            debug!{crate::LOGGER, "Binding listener [{}] to connection: {} <-> {} ", incremental_listener_id, rule.from, rule.to};
+        for (context, listener) in bound {
+            let address: SocketAddr = listener.local_addr()?;
+            debug!{self.logger, "Binding listener [{}] to connection: {} <-> {} ", incremental_listener_id, address, context.rule.to};
+            let slot: Arc<BindingSlot> = Arc::new(BindingSlot::new(context));
             self.listeners.push(ListenerBinding{
                 id: incremental_listener_id,
-                listener: &listener,
-                rule,
+                address,
+                slot: slot.clone(),
             });
-            debug!{crate::LOGGER, "Invoked acceptor thread for listener [{}] using hadler type [{}]", incremental_listener_id, self.thread_handler_type};
+            debug!{self.logger, "Invoked acceptor thread for listener [{}] using hadler type [{}]", incremental_listener_id, self.thread_handler_type};
             let handler_type: ThreadHandlerType = self.thread_handler_type;
-            self.thread_pool.spawn(move || Proxy::invoke_acceptor_handler(&mut listener, to_addr, handler_type));
+            let connections: Arc<ConnectionTracker> = self.connections.clone();
+            let recorder: Option<Arc<SessionRecorder>> = self.recorder.clone();
+            let shutdown: Arc<ShutdownSignal> = self.shutdown.clone();
+            let running: ShutdownGuard = ShutdownSignal::enter(&self.shutdown);
+            self.thread_pool.spawn(move || {
+                Proxy::invoke_acceptor_handler(listener, handler_type, slot, connections, recorder, shutdown);
+                drop(running);
+            });
             inc!{incremental_listener_id};
         }
+        Ok(())
+    }
+    // Bindings without an upstream answer every request from their rules
+    pub(crate) fn resolve_upstream(rule: &bindingset::BindingRule, logger: &Logger) -> io::Result<Option<SocketAddr>> {
+        match Some(rule.to.as_str()).filter(|to: &&str| !to.is_empty()) {
+            Some(to) => Ok(Some(Proxy::resolve_binding_address(to, logger)?)),
+            None => Ok(None),
+        }
     }
-    fn resolve_binding_address(binding_address: &str) -> SocketAddr {
-        let mut potential_addr_from: IntoIter<SocketAddr> = binding_address.to_socket_addrs()
-            .expect(format!("Could not resolve {} to SocketAddr", binding_address).as_str());
+    fn resolve_binding_address(binding_address: &str, logger: &Logger) -> io::Result<SocketAddr> {
+        let mut potential_addr_from: IntoIter<SocketAddr> = binding_address.to_socket_addrs().map_err(|e: io::Error| io::Error::new(
+            e.kind(),
+            format!("Could not resolve {} to SocketAddr: {}", binding_address, e)
+        ))?;
         if potential_addr_from.len() > 1 {
-            info!{crate::LOGGER, "Multiple SocketAddr resolutions [{}] -> {:?}, defaulting to [{}]",
+            info!{logger, "Multiple SocketAddr resolutions [{}] -> {:?}, defaulting to [{}]",
                 binding_address,
                 potential_addr_from.as_slice(),
                 potential_addr_from.as_slice()[0]
             };
         }
-        potential_addr_from.next()
-            .expect(format!("Binding address [{}] could not be resolved to SocketAddr", binding_address).as_str())
+        potential_addr_from.next().ok_or(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("Binding address [{}] could not be resolved to SocketAddr", binding_address)
+        ))
     }
-    fn invoke_acceptor_handler(listener_forward: &mut TcpListener, proxy_to: SocketAddr, handler_type: ThreadHandlerType) {
+    // The listener is closed when the loop returns, each connection takes the binding's context as it is when accepted
+    fn invoke_acceptor_handler(listener_forward: TcpListener, handler_type: ThreadHandlerType, slot: Arc<BindingSlot>, connections: Arc<ConnectionTracker>, recorder: Option<Arc<SessionRecorder>>, shutdown: Arc<ShutdownSignal>) {
         loop {
-            let (stream_forward, _addr) = try_except_return!{listener_forward.accept(), "Failed to accept connection"};
-            debug!(crate::LOGGER, "New connection");
-
-            let sender_forward: TcpStream = try_except_return!{TcpStream::connect(proxy_to), "Failed to bind"};
-            let sender_backward: TcpStream = try_except_return!{sender_forward.try_clone(), "Failed to clone stream"};
-            let stream_backward: TcpStream = try_except_return!{stream_forward.try_clone(), "Failed to clone stream"};
-            let metadata: Arc<Mutex<RequestMetadata>> = Arc::new(Mutex::new(RequestMetadata::new()));
-
-            macro_rules! new_acceptor {
-                ($stream:expr,
-                 $sender:expr,
-                 $metadata:expr,
-                 $capture_handler:expr,
-                 $progressive_handler:expr,
-                 $handler_type:expr) => {
-                    let metadata_clone: Arc<Mutex<RequestMetadata>> = $metadata.clone();
-
-                    let handler_fn: ThreadHandlerMethod = match $handler_type {
-                        ThreadHandlerType::CAPTURE => $capture_handler,
-                        ThreadHandlerType::PROGRESSIVE => $progressive_handler
-                    };
-
-                    thread::spawn(move || handler_fn($stream, $sender, metadata_clone));
+            let context: Arc<BindingContext> = slot.current();
+            // Nothing is accepted while the caps are full, so queued connections wait in the listen backlog
+            let available: bool = context.connections.wait_for_capacity(&shutdown) && connections.wait_for_capacity(&shutdown);
+            let (stream_forward, addr) = try_except_return!{context.logger, listener_forward.accept(), "Failed to accept connection"};
+            if shutdown.requested() {
+                debug!(context.logger, "Closing listener for binding [{}]", slot.current().rule.name);
+                return;
+            }
+            debug!(context.logger, "New connection");
+            // Another listener can take the global slot first, the connection then queues for the next one
+            let queue: Option<&ShutdownSignal> = match available {
+                true => Some(&shutdown),
+                false => None,
+            };
+            Proxy::dispatch_connection(stream_forward, addr, handler_type, &context, &connections, queue, &recorder);
+            // Everything waiting in the backlog has waited as long, refusing only one would leave the rest a timeout each
+            if !available && listener_forward.set_nonblocking(true).is_ok() {
+                while let Ok((stream_forward, addr)) = listener_forward.accept() {
+                    if stream_forward.set_nonblocking(false).is_ok() {
+                        Proxy::dispatch_connection(stream_forward, addr, handler_type, &context, &connections, None, &recorder);
+                    }
+                }
+                try_except_return!{context.logger, listener_forward.set_nonblocking(false), "Failed to restore listener"};
+            }
+        }
+    }
+    // Slots can also free up while the loop waits in accept, a connection refused on a full cap checks again first
+    fn dispatch_connection(stream_forward: TcpStream, addr: SocketAddr, handler_type: ThreadHandlerType, context: &Arc<BindingContext>, connections: &Arc<ConnectionTracker>, queue: Option<&ShutdownSignal>, recorder: &Option<Arc<SessionRecorder>>) {
+        let permits: (ConnectionPermit, ConnectionPermit) = match Proxy::reserve_connection(&context.connections, connections, queue) {
+            Some(permits) => permits,
+            None => {
+                info!(context.logger, "Connection from [{}] on binding [{}] refused by connection cap", addr, context.rule.name);
+                if context.rule.protocol == BindingProtocol::HTTP {
+                    Proxy::reject_http_connection(stream_forward);
                 }
+                return;
+            },
+        };
+        let context: Arc<BindingContext> = context.clone();
+        let recorder: Option<Arc<SessionRecorder>> = recorder.clone();
+        // Connection setup can block on the client or upstream, keep it off the accept loop
+        thread::spawn(move || Proxy::invoke_connection_handler(stream_forward, addr, handler_type, context, permits, recorder));
+    }
+    // Binding first so a connection queued behind its binding's cap does not hold a global slot.
+    // Queues when given the shutdown signal, which ends the wait early
+    fn reserve_connection(binding: &Arc<ConnectionTracker>, global: &Arc<ConnectionTracker>, queue: Option<&ShutdownSignal>) -> Option<(ConnectionPermit, ConnectionPermit)> {
+        let reserve = |tracker: &Arc<ConnectionTracker>| -> Option<ConnectionPermit> {
+            match queue {
+                Some(shutdown) => ConnectionTracker::reserve(tracker, shutdown),
+                None => ConnectionTracker::try_reserve(tracker),
+            }
+        };
+        let binding_permit: ConnectionPermit = reserve(binding)?;
+        Some((binding_permit, reserve(global)?))
+    }
+    fn invoke_connection_handler(stream_forward: TcpStream, addr: SocketAddr, handler_type: ThreadHandlerType, context: Arc<BindingContext>, mut permits: (ConnectionPermit, ConnectionPermit), recorder: Option<Arc<SessionRecorder>>) {
+        let proxy_to: Option<SocketAddr> = context.upstream;
+        let proxy_protocol: &ProxyProtocolRule = &context.rule.proxy_protocol;
+        let mut client_addr: SocketAddr = addr;
+        let mut destination_addr: SocketAddr = try_except_return!{context.logger, stream_forward.local_addr(), "Failed to read local address"};
+        if proxy_protocol.accept != ProxyProtocolVersion::NONE {
+            let header: ProxyHeader = try_except_return!{
+                context.logger,
+                proxy_protocol::read_header(&stream_forward, proxy_protocol.accept),
+                "Rejected connection"
+            };
+            if let (Some(source), Some(destination)) = (header.source, header.destination) {
+                debug!(context.logger, "PROXY protocol client [{}] via [{}]", source, addr);
+                client_addr = source;
+                destination_addr = destination;
             }
+        }
+
+        if let Err(retry_after) = context.enforcer.admit_connection(&client_addr) {
+            info!(context.logger, "Connection from [{}] on binding [{}] refused by rate limit", client_addr, context.rule.name);
+            if context.rule.protocol == BindingProtocol::HTTP {
+                Proxy::refuse_http_connection(stream_forward, enforcer::too_many_requests(retry_after), b"");
+            }
+            return;
+        }
+        // Per IP caps wait for the client address, which is only known once accepted
+        if !(permits.0.admit(client_addr.ip()) && permits.1.admit(client_addr.ip())) {
+            info!(context.logger, "Connection from [{}] on binding [{}] refused by per IP connection cap", client_addr, context.rule.name);
+            if context.rule.protocol == BindingProtocol::HTTP {
+                Proxy::refuse_http_connection(stream_forward, HttpResponseHead::local(503), b"");
+            }
+            return;
+        }
+        let permits: Arc<(ConnectionPermit, ConnectionPermit)> = Arc::new(permits);
 
-            new_acceptor!{
-                stream_forward,
-                sender_forward,
-                metadata,
-                ThreadHandler::forward_thread_capture_handler,
-                ThreadHandler::forward_thread_progressive_handler,
-                handler_type
+        // Behaves like an unreachable upstream, the client connection is simply closed
+        if context.faults.drop_connect() {
+            info!(context.logger, "FAULT INJECTED [DROPCONNECT] Connection from [{}] on binding [{}]", client_addr, context.rule.name);
+            return;
+        }
+        let mut request_metadata: RequestMetadata = RequestMetadata::new(context.rule.name.clone(), client_addr);
+        request_metadata.upstream_addr = proxy_to;
+        if !context.filters.is_empty() {
+            let connection: FilterConnection = FilterConnection {
+                id: request_metadata.id,
+                binding: context.rule.name.clone(),
+                client: client_addr,
+                upstream: proxy_to,
             };
-            new_acceptor!{
-                stream_backward,
-                sender_backward,
-                metadata,
-                ThreadHandler::backward_thread_capture_handler,
-                ThreadHandler::backward_thread_progressive_handler,
-                handler_type
+            match context.filters.on_connect(&connection) {
+                FilterAction::CONTINUE => request_metadata.filtered = Some(FilteredConnection::new(connection, &context.filters)),
+                FilterAction::RESPOND(response, body) => {
+                    info!(context.logger, "Connection from [{}] on binding [{}] answered by filter: {}", client_addr, context.rule.name, response.status);
+                    if context.rule.protocol == BindingProtocol::HTTP {
+                        Proxy::refuse_http_connection(stream_forward, response, body.as_slice());
+                    }
+                    return;
+                },
+                FilterAction::CLOSE => {
+                    info!(context.logger, "Connection from [{}] on binding [{}] refused by filter", client_addr, context.rule.name);
+                    return;
+                },
+            }
+        }
+        let proxy_to: SocketAddr = match proxy_to {
+            Some(proxy_to) => proxy_to,
+            None => {
+                request_metadata.recording = recorder.as_ref()
+                    .map(|recorder: &Arc<SessionRecorder>| recorder.open(request_metadata.id, context.rule.name.as_str(), &client_addr, None));
+                request_metadata.monitored = Some(BindingMonitor::open(&context.monitor, request_metadata.id, client_addr, None));
+                let metadata: Arc<Mutex<RequestMetadata>> = Arc::new(Mutex::new(request_metadata));
+                thread::spawn(move || {
+                    ThreadHandler::local_thread_handler(stream_forward, metadata, context);
+                    drop(permits);
+                });
+                return;
+            },
+        };
+        let sender_forward: TcpStream = try_except_return!{context.logger, TcpStream::connect(proxy_to), "Failed to bind"};
+        if proxy_protocol.send != ProxyProtocolVersion::NONE {
+            try_except_return!{
+                context.logger,
+                proxy_protocol::write_header(&sender_forward, proxy_protocol.send, &client_addr, &destination_addr),
+                "Failed to send PROXY protocol header"
             };
         }
+        let sender_backward: TcpStream = try_except_return!{context.logger, sender_forward.try_clone(), "Failed to clone stream"};
+        let stream_backward: TcpStream = try_except_return!{context.logger, stream_forward.try_clone(), "Failed to clone stream"};
+        if let Some(compare) = context.rule.mirror.compare.as_ref() {
+            request_metadata.comparator = Some(Arc::new(Mutex::new(ResponseComparator::new(request_metadata.id, context.rule.name.clone(), compare, &context.logger, &context.diff_logger))));
+        }
+        request_metadata.recording = recorder.as_ref()
+            .map(|recorder: &Arc<SessionRecorder>| recorder.open(request_metadata.id, context.rule.name.as_str(), &client_addr, Some(&proxy_to)));
+        request_metadata.monitored = Some(BindingMonitor::open(&context.monitor, request_metadata.id, client_addr, Some(proxy_to)));
+        let metadata: Arc<Mutex<RequestMetadata>> = Arc::new(Mutex::new(request_metadata));
+
+        macro_rules! new_acceptor {
+            ($stream:expr,
+             $sender:expr,
+             $metadata:expr,
+             $capture_handler:expr,
+             $progressive_handler:expr,
+             $handler_type:expr) => {
+                let metadata_clone: Arc<Mutex<RequestMetadata>> = $metadata.clone();
+                let context_clone: Arc<BindingContext> = context.clone();
+                let permits_clone: Arc<(ConnectionPermit, ConnectionPermit)> = permits.clone();
+
+                let handler_fn: ThreadHandlerMethod = match $handler_type {
+                    ThreadHandlerType::CAPTURE => $capture_handler,
+                    ThreadHandlerType::PROGRESSIVE => $progressive_handler
+                };
+
+                // The connection's slots are released once both directions have finished
+                thread::spawn(move || {
+                    handler_fn($stream, $sender, metadata_clone, context_clone);
+                    drop(permits_clone);
+                });
+            }
+        }
+
+        new_acceptor!{
+            stream_forward,
+            sender_forward,
+            metadata,
+            ThreadHandler::forward_thread_capture_handler,
+            ThreadHandler::forward_thread_progressive_handler,
+            handler_type
+        };
+        new_acceptor!{
+            stream_backward,
+            sender_backward,
+            metadata,
+            ThreadHandler::backward_thread_capture_handler,
+            ThreadHandler::backward_thread_progressive_handler,
+            handler_type
+        };
+    }
+    // Written on the accept loop, which cannot wait on the client, so the request is left unread and a slow client
+    // may see a reset instead of the response
+    fn reject_http_connection(mut stream_forward: TcpStream) {
+        let mut response: HttpResponseHead = HttpResponseHead::local(503);
+        response.headers.set("Connection", String::from("close"));
+        let _ = stream_forward.set_write_timeout(Some(REJECT_WRITE_TIMEOUT));
+        let _ = stream_forward.write_all(response.to_bytes_with_body(b"").as_slice());
+        let _ = stream_forward.shutdown(Shutdown::Write);
+    }
+    fn refuse_http_connection(mut stream_forward: TcpStream, mut response: HttpResponseHead, body: &[u8]) {
+        // Read the request first, closing with it unread resets the connection before the client sees the response
+        let mut request: [u8; 4096] = [0; 4096];
+        let _ = stream_forward.set_read_timeout(Some(Duration::from_secs(1)));
+        let _ = stream_forward.set_write_timeout(Some(Duration::from_secs(1)));
+        let _ = stream_forward.read(&mut request);
+        response.headers.set("Connection", String::from("close"));
+        let _ = stream_forward.write_all(response.to_bytes_with_body(body).as_slice());
+        let _ = stream_forward.shutdown(Shutdown::Both);
     }
-    pub fn start(&mut self, binding_set: bindingset::BindingSet) {
+    // Returns once every listener is bound, the proxy keeps running until the handle is shut down
+    pub fn start(mut self, binding_set: bindingset::BindingSet) -> io::Result<ProxyHandle> {
         let binding_count: usize = binding_set.bindings.len();
-        info!(crate::LOGGER, "Initializing proxy {} binding(s)", binding_count);
-        self.initialize_bindings(binding_set);
-        info!(crate::LOGGER, "Starting main listener loop");
-        loop {}
+        info!(self.logger, "Initializing proxy {} binding(s)", binding_count);
+        self.initialize_bindings(binding_set)?;
+        let bindings: Arc<BindingReloader> = Arc::new(BindingReloader::new(
+            self.listeners.iter().map(|listener: &ListenerBinding| listener.slot.clone()).collect(),
+            self.filters.clone(),
+            self.bindings_file.clone(),
+            &self.logger,
+        ));
+        let admin_address: Option<SocketAddr> = match self.admin_address.as_ref() {
+            Some(address) => AdminServer::new(bindings.clone(), &self.logger).start(address.as_str(), &self.shutdown),
+            None => None,
+        };
+        let handle: ProxyHandle = ProxyHandle {
+            addresses: self.listeners.iter()
+                .map(|listener: &ListenerBinding| (listener.slot.current().rule.name.clone(), listener.address))
+                .collect(),
+            admin_address,
+            bindings,
+            shutdown: self.shutdown.clone(),
+            logger: self.logger.clone(),
+        };
+        info!(self.logger, "Starting main listener loop");
+        thread::spawn(move || self.report_connection_counts());
+        Ok(handle)
+    }
+    // Owns the proxy from start, its thread pool is released once shutdown wakes the loop
+    fn report_connection_counts(self) {
+        loop {
+            if self.connection_stats_interval.as_secs() == 0 {
+                self.shutdown.wait();
+                return;
+            }
+            if self.shutdown.wait_timeout(self.connection_stats_interval) {
+                return;
+            }
+            self.log_connection_counts();
+        }
+    }
+    pub fn log_connection_counts(&self) {
+        let (open, clients, queued) = self.connections.counts();
+        info!(self.logger, "CONNECTIONS [global] [Open: {}] [Clients: {}] [Queued: {}]", open, clients, queued);
+        for listener in self.listeners.iter() {
+            let context: Arc<BindingContext> = listener.slot.current();
+            let (open, clients, queued) = context.connections.counts();
+            info!(self.logger, "CONNECTIONS [{}] [Open: {}] [Clients: {}] [Queued: {}]", context.connections.name, open, clients, queued);
+        }
+    }
+}
+
+// ---- ProxyHandle ----
+
+// Returned by start, clones share the same proxy
+#[derive(Clone)]
+pub struct ProxyHandle {
+    addresses: HashMap<String, SocketAddr>, // Bound listener address by binding name
+    admin_address: Option<SocketAddr>,
+    bindings: Arc<BindingReloader>,
+    shutdown: Arc<ShutdownSignal>,
+    logger: Logger,
+}
+
+impl ProxyHandle {
+    pub fn address(&self, binding: &str) -> Option<SocketAddr> {
+        self.addresses.get(binding).copied()
+    }
+    pub fn addresses(&self) -> &HashMap<String, SocketAddr> {
+        &self.addresses
+    }
+    // None when no admin address is configured or it could not be bound
+    pub fn admin_address(&self) -> Option<SocketAddr> {
+        self.admin_address
+    }
+    // Swaps in the bindings of the set for new connections, returning their names. Every running binding must be in the set
+    // under the same name and "from", WASM filters are loaded again from their modules
+    pub fn reload(&self, bindings: bindingset::BindingSet) -> io::Result<Vec<String>> {
+        self.bindings.reload(bindings)
+    }
+    // Blocks until another holder of the handle calls shutdown
+    pub fn wait(&self) {
+        self.shutdown.wait();
+    }
+    // Returns once every listener is closed, connections already accepted run until either side closes them
+    pub fn shutdown(&self) {
+        let mut listeners: Vec<SocketAddr> = self.addresses.values().copied().collect();
+        listeners.extend(self.admin_address);
+        self.shutdown.request(listeners.as_slice());
+        info!(self.logger, "Proxy shut down");
+    }
+}
+
+// ---- ProxyBuilder ----
+
+// Assembles a proxy without the config directory, for embedding it in services and test harnesses
+pub struct ProxyBuilder {
+    configuration: Config,
+    bindings: bindingset::BindingSet,
+    bindings_file: Option<String>,
+    logger: Option<Logger>,
+    diff_logger: Option<Logger>,
+    filters: HashMap<String, Arc<dyn Filter>>,
+}
+
+impl ProxyBuilder {
+    pub fn new() -> ProxyBuilder {
+        ProxyBuilder {
+            configuration: Config::new(""),
+            bindings: bindingset::BindingSet::new(String::from("1")),
+            bindings_file: None,
+            logger: None,
+            diff_logger: None,
+            filters: HashMap::new(),
+        }
+    }
+    // Merged with the properties already set, later values win
+    pub fn config(mut self, configuration: Config) -> ProxyBuilder {
+        self.configuration.properties.extend(configuration.properties);
+        self
+    }
+    // Takes any key config.properties does
+    pub fn property(mut self, key: &str, value: &str) -> ProxyBuilder {
+        self.configuration.properties.insert(String::from(key), String::from(value));
+        self
+    }
+    pub fn thread_pool_size(self, size: usize) -> ProxyBuilder {
+        self.property(THREAD_POOL_SIZE_KEY, size.to_string().as_str())
+    }
+    pub fn max_connections(self, max_connections: usize) -> ProxyBuilder {
+        self.property(MAX_CONNECTIONS_KEY, max_connections.to_string().as_str())
+    }
+    pub fn max_connections_per_ip(self, max_connections: usize) -> ProxyBuilder {
+        self.property(MAX_CONNECTIONS_PER_IP_KEY, max_connections.to_string().as_str())
+    }
+    // Whole seconds, zero turns the periodic counts off
+    pub fn connection_stats_interval(self, interval: Duration) -> ProxyBuilder {
+        self.property(CONNECTION_STATS_INTERVAL_KEY, interval.as_secs().to_string().as_str())
+    }
+    pub fn recording_directory(self, directory: &str) -> ProxyBuilder {
+        self.property(RECORDING_DIRECTORY_KEY, directory)
+    }
+    // A port of 0 binds an ephemeral port, read it back from the handle
+    pub fn admin_address(self, address: &str) -> ProxyBuilder {
+        self.property(ADMIN_ADDRESS_KEY, address)
+    }
+    // Bindings chain it by listing the name in their "filters"
+    pub fn filter(mut self, name: &str, filter: Arc<dyn Filter>) -> ProxyBuilder {
+        self.filters.insert(String::from(name), filter);
+        self
+    }
+    pub fn bindings(mut self, bindings: bindingset::BindingSet) -> ProxyBuilder {
+        self.bindings = bindings;
+        self.bindings_file = None;
+        self
+    }
+    // Replaces any bindings given so far. Read when the proxy starts, and again by the admin API's reload
+    pub fn bindings_file(mut self, filename: &str) -> ProxyBuilder {
+        self.bindings = bindingset::BindingSet::new(String::from("1"));
+        self.bindings_file = Some(String::from(filename));
+        self
+    }
+    // Only this proxy logs to it, records are discarded when no logger is given
+    pub fn logger(mut self, logger: Logger) -> ProxyBuilder {
+        self.logger = Some(logger);
+        self
+    }
+    // Response comparisons go to the main logger unless given their own
+    pub fn diff_logger(mut self, logger: Logger) -> ProxyBuilder {
+        self.diff_logger = Some(logger);
+        self
+    }
+    // Fails without starting anything when the properties or bindings are invalid, or a binding cannot be bound
+    pub fn start(mut self) -> io::Result<ProxyHandle> {
+        if let Some(filename) = self.bindings_file.as_ref() {
+            self.bindings = bindingset::BindingSet::from_file(String::from("1"), filename.clone())?;
+        }
+        let logger: Logger = self.logger.unwrap_or_else(|| Logger::root(Discard, o!()));
+        let diff_logger: Logger = self.diff_logger.unwrap_or_else(|| logger.clone());
+        self.bindings.set_applied(true);
+        let mut proxy: Proxy = Proxy::new(self.configuration, logger, diff_logger)?;
+        proxy.filters = self.filters;
+        proxy.bindings_file = self.bindings_file;
+        proxy.start(self.bindings)
+    }
+}
+
+impl Default for ProxyBuilder {
+    fn default() -> ProxyBuilder {
+        ProxyBuilder::new()
     }
 }
\ No newline at end of file
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use slog::Logger;
+
+use crate::servlet::binding_context::{BindingContext, BindingSlot};
+use crate::servlet::proxy::Proxy;
+use crate::traffic::bindingset::{check_unique_names, BindingRule, BindingSet};
+use crate::traffic::filter::{Filter, FilterChain};
+
+// The bindings a proxy is serving, shared by its handle and admin API so either can reload them.
+// Listeners stay bound through a reload, so it can change anything about a binding but its name and "from"
+pub(crate) struct BindingReloader {
+    slots: Vec<Arc<BindingSlot>>,
+    filters: HashMap<String, Arc<dyn Filter>>, // Registered with the proxy, named filters are looked up here again
+    source: Option<String>, // traffic.json the proxy was started from, what the admin API reloads
+    reloading: Mutex<()>,
+    logger: Logger,
+}
+
+impl BindingReloader {
+    pub fn new(slots: Vec<Arc<BindingSlot>>, filters: HashMap<String, Arc<dyn Filter>>, source: Option<String>, logger: &Logger) -> BindingReloader {
+        BindingReloader {
+            slots,
+            filters,
+            source,
+            reloading: Mutex::new(()),
+            logger: logger.clone(),
+        }
+    }
+    pub fn contexts(&self) -> Vec<Arc<BindingContext>> {
+        self.slots.iter().map(|slot: &Arc<BindingSlot>| slot.current()).collect()
+    }
+    // Every binding's new context is built, its rules compiled and filters loaded, before any is swapped,
+    // so a failed reload changes nothing. Returns the names of the reloaded bindings
+    pub fn reload(&self, binding_set: BindingSet) -> io::Result<Vec<String>> {
+        let _reloading = self.reloading.lock().unwrap_or_else(PoisonError::into_inner);
+        check_unique_names(&binding_set.bindings)?;
+        let mut rules: HashMap<String, BindingRule> = binding_set.bindings.into_iter()
+            .map(|rule: BindingRule| (rule.name.clone(), rule))
+            .collect();
+        let mut prepared: Vec<(&Arc<BindingSlot>, BindingContext)> = vec![];
+        for slot in self.slots.iter() {
+            let current: Arc<BindingContext> = slot.current();
+            let rule: BindingRule = rules.remove(&current.rule.name).ok_or(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Binding [{}] is missing from the reloaded bindings, removing a binding needs a restart", current.rule.name)
+            ))?;
+            if rule.from != current.rule.from {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Binding [{}] moved from [{}] to [{}], changing a listen address needs a restart", rule.name, current.rule.from, rule.from)
+                ));
+            }
+            let upstream: Option<SocketAddr> = Proxy::resolve_upstream(&rule, &self.logger)?;
+            let filters: FilterChain = FilterChain::resolve(&rule, &self.filters, &self.logger)?;
+            prepared.push((slot, BindingContext::reloaded(&current, rule, upstream, filters)?));
+        }
+        if let Some(name) = rules.keys().next() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Binding [{}] is not running, adding a binding needs a restart", name)
+            ));
+        }
+        let mut reloaded: Vec<String> = vec![];
+        for (slot, context) in prepared {
+            info!(self.logger, "Reloaded binding [{}]", context.rule.name);
+            reloaded.push(context.rule.name.clone());
+            slot.replace(context);
+        }
+        reloaded.sort();
+        Ok(reloaded)
+    }
+    // Reads the bindings again from the file the proxy was started with
+    pub fn reload_source(&self) -> io::Result<Vec<String>> {
+        let source: &String = self.source.as_ref().ok_or(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The proxy was not started from a bindings file"
+        ))?;
+        self.reload(BindingSet::from_file(String::from("1"), source.clone())?)
+    }
+}
@@ -1,19 +1,55 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 
+use crate::traffic::filter::FilteredConnection;
+use crate::traffic::http::diff::ResponseComparator;
+use crate::traffic::http::har::HarExchange;
+use crate::traffic::http::message::HttpRequestHead;
+use crate::traffic::monitor::MonitoredConnection;
+use crate::traffic::recording::recorder::SessionRecording;
+use crate::traffic::template::TemplateVariables;
+
 pub(crate) struct RequestMetadata {
     pub id: uuid::Uuid,
+    pub binding_name: String,
+    pub client_addr: SocketAddr,
+    pub upstream_addr: Option<SocketAddr>,
     pub request_start: u64,
     pub response_end: u64,
     pub response_packet_count: i32,
+    // Requests forwarded upstream that are still awaiting a response, oldest first
+    pub pending_requests: VecDeque<HttpRequestHead>,
+    pub response_in_progress: bool,
+    pub aborted: bool, // Set when a fault reset the connection, so neither side is closed cleanly
+    pub exchanges: u64, // Requests forwarded upstream so far, numbers each exchange
+    pub comparator: Option<Arc<Mutex<ResponseComparator>>>, // Shared with the mirror when shadow responses are compared
+    pub recording: Option<SessionRecording>, // Set when sessions are being recorded to disk
+    pub har_exchanges: VecDeque<HarExchange>, // Forwarded requests awaiting their response, when the binding keeps a HAR
+    pub monitored: Option<MonitoredConnection>, // Lists the connection in the admin API while it is open
+    pub filtered: Option<FilteredConnection>, // Set when the binding has filters
 }
 
 impl RequestMetadata {
-    pub fn new() -> RequestMetadata {
+    pub fn new(binding_name: String, client_addr: SocketAddr) -> RequestMetadata {
         RequestMetadata {
             id: uuid::Uuid::new_v4(),
+            binding_name,
+            client_addr,
+            upstream_addr: None,
             request_start: 0,
             response_end: 0,
             response_packet_count: 0,
+            pending_requests: VecDeque::new(),
+            response_in_progress: false,
+            aborted: false,
+            exchanges: 0,
+            comparator: None,
+            recording: None,
+            har_exchanges: VecDeque::new(),
+            monitored: None,
+            filtered: None,
         }
     }
     pub fn tag_request_start_time(&mut self) {
@@ -25,8 +61,16 @@ impl RequestMetadata {
     pub fn get_request_response_duration(&self) -> f32 {
         (self.response_end - self.request_start) as f32 / 1_000_000.0
     }
+    pub fn template_variables(&self) -> TemplateVariables {
+        let mut variables: TemplateVariables = TemplateVariables::new();
+        variables.insert(String::from("request_id"), self.id.to_string());
+        variables.insert(String::from("binding_name"), self.binding_name.clone());
+        variables.insert(String::from("client_ip"), self.client_addr.ip().to_string());
+        variables.insert(String::from("client_port"), self.client_addr.port().to_string());
+        variables
+    }
     fn millis_since_epoch() -> u64 {
         let since_the_epoch: Duration = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("get millis error");
-        (since_the_epoch.as_secs() * 1_000_000) + (since_the_epoch.subsec_nanos() as u64)
+        (since_the_epoch.as_secs() * 1_000_000_000) + (since_the_epoch.subsec_nanos() as u64)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,84 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+static WAKE_CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+static WAKE_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+struct ShutdownState {
+    requested: bool,
+    running: usize, // Accept loops still holding their listener
+}
+
+// Shared by a proxy's accept loops and background threads, which stop once it is requested
+pub(crate) struct ShutdownSignal {
+    state: Mutex<ShutdownState>,
+    changed: Condvar,
+}
+
+impl ShutdownSignal {
+    pub fn new() -> ShutdownSignal {
+        ShutdownSignal {
+            state: Mutex::new(ShutdownState {
+                requested: false,
+                running: 0,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+    pub fn requested(&self) -> bool {
+        self.state.lock().unwrap().requested
+    }
+    // Taken before an accept loop is spawned, the loop counts as running until the guard is dropped
+    pub fn enter(signal: &Arc<ShutdownSignal>) -> ShutdownGuard {
+        signal.state.lock().unwrap().running += 1;
+        ShutdownGuard {
+            signal: signal.clone(),
+        }
+    }
+    // True once shutdown has been requested, false when the timeout passes first
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let state: MutexGuard<ShutdownState> = self.state.lock().unwrap();
+        let (state, _) = self.changed.wait_timeout_while(state, timeout, |state: &mut ShutdownState| !state.requested).unwrap();
+        state.requested
+    }
+    pub fn wait(&self) {
+        let state: MutexGuard<ShutdownState> = self.state.lock().unwrap();
+        let _state = self.changed.wait_while(state, |state: &mut ShutdownState| !state.requested).unwrap();
+    }
+    // Blocked accepts only see the request once a connection arrives, so each listener is dialled until its loop has let go
+    pub fn request(&self, listeners: &[SocketAddr]) {
+        let mut state: MutexGuard<ShutdownState> = self.state.lock().unwrap();
+        state.requested = true;
+        self.changed.notify_all();
+        while state.running > 0 {
+            drop(state);
+            for address in listeners {
+                let _ = TcpStream::connect_timeout(&ShutdownSignal::wake_address(address), WAKE_CONNECT_TIMEOUT);
+            }
+            state = self.state.lock().unwrap();
+            if state.running > 0 {
+                state = self.changed.wait_timeout(state, WAKE_RETRY_INTERVAL).unwrap().0;
+            }
+        }
+    }
+    // Listeners bound to every interface are reached over loopback
+    fn wake_address(address: &SocketAddr) -> SocketAddr {
+        match address.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), address.port()),
+            IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), address.port()),
+            _ => *address,
+        }
+    }
+}
+
+pub(crate) struct ShutdownGuard {
+    signal: Arc<ShutdownSignal>,
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        self.signal.state.lock().unwrap().running -= 1;
+        self.signal.changed.notify_all();
+    }
+}
@@ -1,10 +1,21 @@
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream};
 use std::sync::{MutexGuard, Mutex, Arc};
-use std::io::{BufReader, BufRead, Write};
+use std::io::{self, BufReader, BufRead, Write};
+use std::thread;
+use std::time::Duration;
 
 use crate::traffic::packet::stream_packet_collector::StreamPacketCollector;
+use crate::traffic::http::transformer::HttpStreamTransformer;
+use crate::traffic::bandwidth::BandwidthShaper;
+use crate::traffic::faults::{self, FaultInjector, FaultOutcome, MessageStart};
+use crate::traffic::http::diff::ResponseComparator;
+use crate::traffic::mirror::{MirrorChunk, MirrorConnection};
+use crate::traffic::monitor::{ConnectionStats, MonitoredConnection};
+use crate::traffic::recording::recorder::SessionRecording;
+use crate::traffic::bindingset::{BindingProtocol, TrafficDirection};
 use crate::servlet::request_metadata::RequestMetadata;
-use crate::inc;
+use crate::servlet::binding_context::BindingContext;
+use crate::{inc, if_let_some, try_except_return};
 use core::fmt;
 use std::str::FromStr;
 
@@ -34,87 +45,379 @@ impl FromStr for ThreadHandlerType {
     }
 }
 
-pub(crate) type ThreadHandlerMethod = fn(TcpStream, TcpStream, Arc<Mutex<RequestMetadata>>);
+pub(crate) type ThreadHandlerMethod = fn(TcpStream, TcpStream, Arc<Mutex<RequestMetadata>>, Arc<BindingContext>);
 
 pub(crate) struct ThreadHandler;
 
 type Byte = u8;
 
 impl ThreadHandler {
-    pub fn forward_thread_capture_handler(stream_forward: TcpStream, sender_forward: TcpStream, metadata: Arc<Mutex<RequestMetadata>>) {
+    fn new_transformer(direction: TrafficDirection, context: &Arc<BindingContext>) -> Option<HttpStreamTransformer> {
+        match context.rule.protocol {
+            BindingProtocol::HTTP => Some(HttpStreamTransformer::new(direction, context.clone())),
+            BindingProtocol::TCP => None,
+        }
+    }
+    // Returns any responses the proxy generated itself for the collected requests
+    fn transform_collected(transformer: Option<HttpStreamTransformer>, packet_collector: &mut StreamPacketCollector, md: &mut RequestMetadata) -> Vec<Byte> {
+        if_let_some!{mut transformer = transformer, vec![]};
+        let mut transformed: Vec<Byte> = transformer.transform(packet_collector.buffer_to_slice(), md);
+        ThreadHandler::release_collected(&mut transformer, md, &mut transformed);
+        transformed.extend(transformer.finish(md));
+        ThreadHandler::release_collected(&mut transformer, md, &mut transformed);
+        *packet_collector.get_buffer() = transformed;
+        ThreadHandler::delay_local_responses(&mut transformer);
+        transformer.take_local_responses()
+    }
+    // Capturing holds the metadata for the whole exchange anyway, so intercepted messages are awaited under it
+    fn release_collected(transformer: &mut HttpStreamTransformer, md: &mut RequestMetadata, transformed: &mut Vec<Byte>) {
+        while let Some(decision) = transformer.await_decision(md.id) {
+            transformed.extend(transformer.release(decision, md));
+        }
+    }
+    // Writes intercepted messages once they are decided on, returns false once the connection has ended.
+    // Awaited without the metadata lock so the other direction keeps flowing meanwhile
+    fn release_held(transformer: &mut HttpStreamTransformer, id: uuid::Uuid, metadata: &Arc<Mutex<RequestMetadata>>, injector: &mut Option<FaultInjector>,
+                    mirror: &mut Option<MirrorConnection>, writer: &mut TcpStream, peer: &mut TcpStream) -> bool {
+        while let Some(decision) = transformer.await_decision(id) {
+            let released: Vec<Byte> = transformer.release(decision, &mut metadata.lock().unwrap());
+            let starts: Vec<MessageStart> = transformer.take_message_starts();
+            if let Some(mirror) = mirror.as_mut() {
+                mirror.send(transformer.take_mirrored());
+            }
+            match ThreadHandler::inject(injector, writer, released.as_slice(), starts) {
+                Ok(FaultOutcome::FORWARD) => {},
+                Ok(outcome) => {
+                    ThreadHandler::end_connection(outcome, peer, writer, metadata);
+                    return false;
+                },
+                Err(_) => {
+                    debug!(transformer.logger(), "Peer closed connection [{}]", id);
+                    return false;
+                },
+            }
+            // Only requests are answered locally, and a dropped message closes both sides whichever way it went
+            if ThreadHandler::write_local_responses(transformer, peer, Some(writer)) {
+                debug!(transformer.logger(), "Closed connection after intercepted message [{}]", id);
+                return false;
+            }
+        }
+        true
+    }
+    // Paces reads to the binding's bandwidth limits, before the metadata lock is taken so waiting never blocks the other direction
+    fn shape<'a>(shaper: &mut Option<BandwidthShaper>, buffer: &'a [Byte]) -> &'a [Byte] {
+        if_let_some!{shaper = shaper.as_mut(), buffer};
+        let length: usize = std::cmp::min(buffer.len(), shaper.quantum());
+        shaper.wait(length);
+        &buffer[..length]
+    }
+    // Records and counts what was read from the peer, before any of the binding's rules change it
+    fn record(recording: &Option<SessionRecording>, stats: &Option<Arc<ConnectionStats>>, direction: TrafficDirection, buffer: &[Byte]) {
+        if let Some(recording) = recording.as_ref() {
+            recording.record(direction, buffer);
+        }
+        if let Some(stats) = stats.as_ref() {
+            stats.count(direction, buffer.len());
+        }
+    }
+    fn stats(md: &RequestMetadata) -> Option<Arc<ConnectionStats>> {
+        md.monitored.as_ref().map(|monitored: &MonitoredConnection| monitored.stats.clone())
+    }
+    // Writes the output, through the fault injector when the binding has faults, which may sleep so no lock may be held
+    fn inject(injector: &mut Option<FaultInjector>, writer: &mut TcpStream, output: &[Byte], starts: Vec<MessageStart>) -> io::Result<FaultOutcome> {
+        match injector.as_mut() {
+            Some(injector) => injector.forward(writer, output.to_vec(), starts),
+            None => writer.write_all(output).map(|_| FaultOutcome::FORWARD),
+        }
+    }
+    fn end_connection(outcome: FaultOutcome, client: &TcpStream, upstream: &TcpStream, metadata: &Arc<Mutex<RequestMetadata>>) {
+        if outcome == FaultOutcome::RESET {
+            // Flagged first so the other direction does not close its side cleanly when it wakes
+            metadata.lock().unwrap().aborted = true;
+            faults::reset(client, upstream);
+            return;
+        }
+        let _ = client.shutdown(Shutdown::Both);
+        let _ = upstream.shutdown(Shutdown::Both);
+    }
+    // Mocked responses can ask to be held back, which only holds up this connection
+    fn delay_local_responses(transformer: &mut HttpStreamTransformer) {
+        let delay: Duration = transformer.take_local_delay();
+        if delay > Duration::ZERO {
+            thread::sleep(delay);
+        }
+    }
+    // Returns true once the connection has been closed in favour of a local response
+    fn write_local_responses(transformer: &mut HttpStreamTransformer, client: &mut TcpStream, upstream: Option<&TcpStream>) -> bool {
+        ThreadHandler::delay_local_responses(transformer);
+        let local_responses: Vec<Byte> = transformer.take_local_responses();
+        if !local_responses.is_empty() && client.write_all(local_responses.as_slice()).is_err() {
+            debug!(transformer.logger(), "Client closed connection");
+        }
+        if !transformer.should_close() {
+            return false;
+        }
+        let _ = client.shutdown(Shutdown::Both);
+        if let Some(upstream) = upstream {
+            let _ = upstream.shutdown(Shutdown::Both);
+        }
+        true
+    }
+    pub fn forward_thread_capture_handler(stream_forward: TcpStream, sender_forward: TcpStream, metadata: Arc<Mutex<RequestMetadata>>, context: Arc<BindingContext>) {
+        let mut client: TcpStream = try_except_return!{context.logger, stream_forward.try_clone(), "Failed to clone stream"};
         let mut packet_collector: StreamPacketCollector = StreamPacketCollector::new(sender_forward, stream_forward);
         let mut md: MutexGuard<RequestMetadata> = metadata.lock().unwrap();
+        packet_collector.record(md.recording.clone(), TrafficDirection::EGRESS);
         packet_collector.read_all_packets_from_stream();
+        if let Some(stats) = ThreadHandler::stats(&md) {
+            stats.count(TrafficDirection::EGRESS, packet_collector.buffer_to_slice().len());
+        }
+        let local_responses: Vec<Byte> = ThreadHandler::transform_collected(ThreadHandler::new_transformer(TrafficDirection::EGRESS, &context), &mut packet_collector, &mut md);
+        if !local_responses.is_empty() && client.write_all(local_responses.as_slice()).is_err() {
+            debug!(context.logger, "Client closed connection");
+        }
         match packet_collector.write_buffer_to_remote() {
             Some(_) => {},
-            None => { debug!{crate::LOGGER, "Connection closed"}; }
+            None => { debug!{context.logger, "Connection closed"}; }
         };
-        packet_collector.flush_stream_to_remote();
+        packet_collector.flush_stream_to_remote(&context.logger);
         md.tag_response_end_time();
         md.tag_request_start_time();
-        info!(crate::LOGGER, "TRAFFIC LOG [EGRESS] [{}] [Packets: {}]", md.id, packet_collector.packet_count);
-        debug!(crate::LOGGER, "REQUEST CONTENT [EGRESS]: {}", packet_collector.buffer_to_string().chars().as_str());
-        debug!(crate::LOGGER, "Remote closed connection");
+        info!(context.logger, "TRAFFIC LOG [EGRESS] [{}] [Packets: {}]", md.id, packet_collector.packet_count);
+        debug!(context.logger, "REQUEST CONTENT [EGRESS]: {}", packet_collector.buffer_to_string().chars().as_str());
+        debug!(context.logger, "Remote closed connection");
     }
-    pub fn forward_thread_progressive_handler(stream_forward: TcpStream, mut sender_forward: TcpStream, metadata: Arc<Mutex<RequestMetadata>>) {
+    pub fn forward_thread_progressive_handler(stream_forward: TcpStream, mut sender_forward: TcpStream, metadata: Arc<Mutex<RequestMetadata>>, context: Arc<BindingContext>) {
+        let mut client: TcpStream = try_except_return!{context.logger, stream_forward.try_clone(), "Failed to clone stream"};
         let mut stream_forward: BufReader<TcpStream> = BufReader::new(stream_forward);
+        let (id, comparator, recording, stats): (uuid::Uuid, Option<Arc<Mutex<ResponseComparator>>>, Option<SessionRecording>, Option<Arc<ConnectionStats>>) = {
+            let md: MutexGuard<RequestMetadata> = metadata.lock().unwrap();
+            (md.id, md.comparator.clone(), md.recording.clone(), ThreadHandler::stats(&md))
+        };
+        let mut transformer: Option<HttpStreamTransformer> = ThreadHandler::new_transformer(TrafficDirection::EGRESS, &context);
+        let mut shaper: Option<BandwidthShaper> = BandwidthShaper::new(TrafficDirection::EGRESS, &context);
+        let mut injector: Option<FaultInjector> = FaultInjector::new(TrafficDirection::EGRESS, &context, id);
+        let mut mirror: Option<MirrorConnection> = MirrorConnection::open(&context, id, comparator);
         let mut buffer: &[Byte];
         let mut buffer_length: usize;
         loop {
-            buffer = stream_forward.fill_buf().unwrap();
+            buffer = match stream_forward.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    debug!(context.logger, "Client connection failed [{}]: {}", id, e);
+                    // Nothing more will come from the client, closing the upstream lets the backward handler exit
+                    let _ = sender_forward.shutdown(Shutdown::Both);
+                    return;
+                },
+            };
+            if buffer.is_empty() {
+                debug!(context.logger, "Client closed connection");
+                // Pass the close on so the upstream finishes too and the backward handler can exit
+                if !metadata.lock().unwrap().aborted {
+                    let _ = sender_forward.shutdown(Shutdown::Write);
+                }
+                return;
+            }
+            buffer = ThreadHandler::shape(&mut shaper, buffer);
             buffer_length = buffer.len();
+            ThreadHandler::record(&recording, &stats, TrafficDirection::EGRESS, buffer);
+            let mut md: MutexGuard<RequestMetadata> = metadata.lock().unwrap();
+            let transformed: Option<Vec<Byte>> = transformer.as_mut()
+                .map(|transformer: &mut HttpStreamTransformer| transformer.transform(buffer, &mut md));
+            let starts: Vec<MessageStart> = transformer.as_mut()
+                .map_or(vec![], |transformer: &mut HttpStreamTransformer| transformer.take_message_starts());
+            if let Some(mirror) = mirror.as_mut() {
+                mirror.send(match transformer.as_mut() {
+                    Some(transformer) => transformer.take_mirrored(),
+                    None => MirrorChunk { bytes: buffer.to_vec(), requests: vec![] },
+                });
+            }
+            debug!(context.logger, "REQUEST CONTENT [EGRESS]: {}", String::from_utf8_lossy(&buffer).chars().as_str());
+            info!(context.logger, "TRAFFIC LOG [EGRESS] [{}]", md.id);
+            md.tag_request_start_time();
+            drop(md);
+            let output: &[Byte] = transformed.as_deref().unwrap_or(buffer);
+            match ThreadHandler::inject(&mut injector, &mut sender_forward, output, starts) {
+                Ok(FaultOutcome::FORWARD) => {},
+                Ok(outcome) => {
+                    ThreadHandler::end_connection(outcome, &client, &sender_forward, &metadata);
+                    return;
+                },
+                Err(e) => {
+                    debug!(context.logger, "Upstream connection failed [{}]: {}", id, e);
+                    let _ = client.shutdown(Shutdown::Both);
+                    return;
+                },
+            }
+            if let Some(transformer) = transformer.as_mut() {
+                if ThreadHandler::write_local_responses(transformer, &mut client, Some(&sender_forward)) {
+                    debug!(context.logger, "Closed connection after local response [{}]", id);
+                    return;
+                }
+                if !ThreadHandler::release_held(transformer, id, &metadata, &mut injector, &mut mirror, &mut sender_forward, &mut client) {
+                    return;
+                }
+            }
+            if let Err(e) = sender_forward.flush() {
+                debug!(context.logger, "Upstream connection failed [{}]: {}", id, e);
+                let _ = client.shutdown(Shutdown::Both);
+                return;
+            }
+            stream_forward.consume(buffer_length);
+        }
+    }
+    // Serves a binding without an upstream, every request is answered by the binding's rules
+    pub fn local_thread_handler(stream_forward: TcpStream, metadata: Arc<Mutex<RequestMetadata>>, context: Arc<BindingContext>) {
+        let mut client: TcpStream = try_except_return!{context.logger, stream_forward.try_clone(), "Failed to clone stream"};
+        let mut stream_forward: BufReader<TcpStream> = BufReader::new(stream_forward);
+        let (id, recording, stats): (uuid::Uuid, Option<SessionRecording>, Option<Arc<ConnectionStats>>) = {
+            let md: MutexGuard<RequestMetadata> = metadata.lock().unwrap();
+            (md.id, md.recording.clone(), ThreadHandler::stats(&md))
+        };
+        let mut transformer: HttpStreamTransformer = HttpStreamTransformer::new(TrafficDirection::EGRESS, context.clone());
+        let mut buffer: &[Byte];
+        let mut buffer_length: usize;
+        loop {
+            buffer = match stream_forward.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(_) => &[],
+            };
             if buffer.is_empty() {
-                debug!(crate::LOGGER, "Client closed connection");
+                debug!(context.logger, "Client closed connection");
+                let _ = client.shutdown(Shutdown::Both);
                 return;
             }
-            sender_forward.write_all(&buffer).expect("Failed to write to remote");
+            buffer_length = buffer.len();
+            ThreadHandler::record(&recording, &stats, TrafficDirection::EGRESS, buffer);
             let mut md: MutexGuard<RequestMetadata> = metadata.lock().unwrap();
-            debug!(crate::LOGGER, "REQUEST CONTENT [EGRESS]: {}", String::from_utf8_lossy(&buffer).chars().as_str());
-            info!(crate::LOGGER, "TRAFFIC LOG [EGRESS] [{}]", md.id);
+            // Anything left over is not HTTP the rules could answer, and there is nowhere to forward it
+            let mut unanswered: Vec<Byte> = transformer.transform(buffer, &mut md);
+            debug!(context.logger, "REQUEST CONTENT [EGRESS]: {}", String::from_utf8_lossy(&buffer).chars().as_str());
+            info!(context.logger, "TRAFFIC LOG [EGRESS] [{}]", md.id);
             md.tag_request_start_time();
-            sender_forward.flush().expect("Failed to flush remote");
+            drop(md);
+            loop {
+                if ThreadHandler::write_local_responses(&mut transformer, &mut client, None) {
+                    debug!(context.logger, "Closed connection after local response [{}]", id);
+                    return;
+                }
+                if !unanswered.is_empty() {
+                    debug!(context.logger, "Closed connection after unanswerable bytes [{}]", id);
+                    let _ = client.shutdown(Shutdown::Both);
+                    return;
+                }
+                match transformer.await_decision(id) {
+                    Some(decision) => unanswered = transformer.release(decision, &mut metadata.lock().unwrap()),
+                    None => break,
+                }
+            }
             stream_forward.consume(buffer_length);
         }
     }
     // "Progressive" refers to forwarding all packets as they come through
-    pub fn backward_thread_progressive_handler(mut stream_backward: TcpStream, sender_backward: TcpStream, metadata: Arc<Mutex<RequestMetadata>>) {
+    pub fn backward_thread_progressive_handler(mut stream_backward: TcpStream, sender_backward: TcpStream, metadata: Arc<Mutex<RequestMetadata>>, context: Arc<BindingContext>) {
         let mut sender_backward: BufReader<TcpStream> = BufReader::new(sender_backward);
+        let (id, recording, stats): (uuid::Uuid, Option<SessionRecording>, Option<Arc<ConnectionStats>>) = {
+            let md: MutexGuard<RequestMetadata> = metadata.lock().unwrap();
+            (md.id, md.recording.clone(), ThreadHandler::stats(&md))
+        };
+        let mut transformer: Option<HttpStreamTransformer> = ThreadHandler::new_transformer(TrafficDirection::INGRESS, &context);
+        let mut shaper: Option<BandwidthShaper> = BandwidthShaper::new(TrafficDirection::INGRESS, &context);
+        let mut injector: Option<FaultInjector> = FaultInjector::new(TrafficDirection::INGRESS, &context, id);
         let mut buffer: &[Byte];
         let mut length: usize;
         loop {
-            buffer = sender_backward.fill_buf().unwrap();
+            buffer = match sender_backward.fill_buf() {
+                Ok(buffer) => ThreadHandler::shape(&mut shaper, buffer),
+                Err(e) => {
+                    debug!(context.logger, "Upstream connection failed [{}]: {}", id, e);
+                    // Closed outright, a clean close could pass a response cut short for a complete one
+                    let _ = stream_backward.shutdown(Shutdown::Both);
+                    return;
+                },
+            };
             length = buffer.len();
+            ThreadHandler::record(&recording, &stats, TrafficDirection::INGRESS, buffer);
             let mut md: MutexGuard<RequestMetadata> = metadata.lock().unwrap();
             if buffer.is_empty() {
+                if md.aborted {
+                    debug!(context.logger, "Connection reset by fault [{}]", md.id);
+                    return;
+                }
+                if let Some(transformer) = transformer.as_mut() {
+                    let remaining: Vec<Byte> = transformer.finish(&mut md);
+                    if stream_backward.write_all(remaining.as_slice()).is_err() {
+                        debug!(context.logger, "Client closed connection");
+                    }
+                    // A response that ends with the connection is only complete now
+                    if transformer.is_holding() {
+                        drop(md);
+                        if !ThreadHandler::release_held(transformer, id, &metadata, &mut injector, &mut None, &mut stream_backward, sender_backward.get_mut()) {
+                            return;
+                        }
+                        md = metadata.lock().unwrap();
+                    }
+                }
                 md.tag_response_end_time();
-                info!(crate::LOGGER, "TRAFFIC LOG [INGRESS] [{}] [Packets: {}] [{} ms]", md.id, md.response_packet_count, md.get_request_response_duration());
-                debug!(crate::LOGGER, "Remote closed connection");
+                info!(context.logger, "TRAFFIC LOG [INGRESS] [{}] [Packets: {}] [{} ms]", md.id, md.response_packet_count, md.get_request_response_duration());
+                debug!(context.logger, "Remote closed connection");
+                let _ = stream_backward.shutdown(Shutdown::Write);
                 return;
             }
-            if stream_backward.write_all(&buffer).is_err() {
-                debug!(crate::LOGGER, "Client closed connection");
+            let transformed: Option<Vec<Byte>> = transformer.as_mut()
+                .map(|transformer: &mut HttpStreamTransformer| transformer.transform(buffer, &mut md));
+            let starts: Vec<MessageStart> = transformer.as_mut()
+                .map_or(vec![], |transformer: &mut HttpStreamTransformer| transformer.take_message_starts());
+            debug!(context.logger, "RESPONSE CONTENT [EGRESS]: {}", String::from_utf8_lossy(&buffer).chars().as_str());
+            inc!{md.response_packet_count};
+            drop(md);
+            let output: &[Byte] = transformed.as_deref().unwrap_or(buffer);
+            match ThreadHandler::inject(&mut injector, &mut stream_backward, output, starts) {
+                Ok(FaultOutcome::FORWARD) => {},
+                Ok(outcome) => {
+                    ThreadHandler::end_connection(outcome, &stream_backward, sender_backward.get_ref(), &metadata);
+                    return;
+                },
+                Err(_) => {
+                    debug!(context.logger, "Client closed connection");
+                    return;
+                },
+            }
+            if let Some(transformer) = transformer.as_mut() {
+                // Responses are never answered locally, but a filter can close the connection
+                if ThreadHandler::write_local_responses(transformer, &mut stream_backward, Some(sender_backward.get_ref())) {
+                    debug!(context.logger, "Closed connection after filtered response [{}]", id);
+                    return;
+                }
+                if !ThreadHandler::release_held(transformer, id, &metadata, &mut injector, &mut None, &mut stream_backward, sender_backward.get_mut()) {
+                    return;
+                }
+            }
+            if let Err(e) = stream_backward.flush() {
+                debug!(context.logger, "Client connection failed [{}]: {}", id, e);
                 return;
             }
-
-            debug!(crate::LOGGER, "RESPONSE CONTENT [EGRESS]: {}", String::from_utf8_lossy(&buffer).chars().as_str());
-            inc!{md.response_packet_count};
-            stream_backward.flush().expect("Failed to flush locally");
             sender_backward.consume(length);
         }
     }
     // "Capture" refers to reading all packets and sending as one packet to client
-    pub fn backward_thread_capture_handler(stream_backward: TcpStream, sender_backward: TcpStream, metadata: Arc<Mutex<RequestMetadata>>) {
+    pub fn backward_thread_capture_handler(stream_backward: TcpStream, sender_backward: TcpStream, metadata: Arc<Mutex<RequestMetadata>>, context: Arc<BindingContext>) {
         let mut packet_collector: StreamPacketCollector = StreamPacketCollector::new(stream_backward, sender_backward);
         let mut md: MutexGuard<RequestMetadata> = metadata.lock().unwrap();
+        packet_collector.record(md.recording.clone(), TrafficDirection::INGRESS);
         packet_collector.read_all_packets_from_stream();
+        if let Some(stats) = ThreadHandler::stats(&md) {
+            stats.count(TrafficDirection::INGRESS, packet_collector.buffer_to_slice().len());
+        }
+        ThreadHandler::transform_collected(ThreadHandler::new_transformer(TrafficDirection::INGRESS, &context), &mut packet_collector, &mut md);
         match packet_collector.write_buffer_to_remote() {
             Some(_) => {},
-            None => { debug!{crate::LOGGER, "Connection closed"}; }
+            None => { debug!{context.logger, "Connection closed"}; }
         };
-        packet_collector.flush_stream_to_remote();
+        packet_collector.flush_stream_to_remote(&context.logger);
         md.tag_response_end_time();
-        info!(crate::LOGGER, "TRAFFIC LOG [INGRESS] [{}] [Packets: {}] [{} ms]", md.id, packet_collector.packet_count, md.get_request_response_duration());
-        debug!(crate::LOGGER, "RESPONSE CONTENT [INGRESS]: {}", packet_collector.buffer_to_string().chars().as_str());
-        debug!(crate::LOGGER, "Remote closed connection");
+        info!(context.logger, "TRAFFIC LOG [INGRESS] [{}] [Packets: {}] [{} ms]", md.id, packet_collector.packet_count, md.get_request_response_duration());
+        debug!(context.logger, "RESPONSE CONTENT [INGRESS]: {}", packet_collector.buffer_to_string().chars().as_str());
+        debug!(context.logger, "Remote closed connection");
     }
 }
\ No newline at end of file
@@ -0,0 +1,39 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use serde_json::Value;
+
+use crate::traffic::http::message::HttpResponseHead;
+use crate::traffic::http::stream;
+
+type Byte = u8;
+
+static ADMIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The admin API answers one request per connection and closes it
+pub fn request(admin: &str, method: &str, path: &str, body: &[Byte]) -> io::Result<(u16, Vec<Byte>)> {
+    let mut stream: TcpStream = TcpStream::connect(admin)?;
+    stream.set_read_timeout(Some(ADMIN_TIMEOUT))?;
+    let mut request: Vec<Byte> = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        method, path, admin, body.len(),
+    ).into_bytes();
+    request.extend_from_slice(body);
+    stream.write_all(request.as_slice())?;
+    let mut response: Vec<Byte> = vec![];
+    stream.read_to_end(&mut response)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Malformed response");
+    let head_end: usize = stream::find_subsequence(response.as_slice(), b"\r\n\r\n").ok_or_else(invalid)?;
+    let head: HttpResponseHead = HttpResponseHead::parse(&response[..head_end]).ok_or_else(invalid)?;
+    Ok((head.status, response.split_off(head_end + 4)))
+}
+
+// Errors carry the admin API's own reason when it gave one
+pub fn get_json(admin: &str, path: &str) -> Result<Value, String> {
+    let (status, body): (u16, Vec<Byte>) = request(admin, "GET", path, b"").map_err(|e| e.to_string())?;
+    let json: Value = serde_json::from_slice::<Value>(body.as_slice()).map_err(|_| format!("GET {}: {} with a malformed body", path, status))?;
+    if status / 100 != 2 {
+        return Err(format!("GET {}: {}", path, json["error"].as_str().unwrap_or("failed")));
+    }
+    Ok(json)
+}
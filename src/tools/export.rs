@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::traffic::packet::pcapng;
+use crate::traffic::recording::reader::{self, RecordedSession};
+
+static USAGE: &'static str = "Usage: tcp_proxy export <RECORDING FILE | DIRECTORY> --output <FILE> [--format <PCAPNG>] [--binding <NAME>]";
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ExportFormat {
+    PCAPNG,
+}
+
+impl FromStr for ExportFormat {
+    type Err = ();
+    fn from_str(input: &str) -> Result<ExportFormat, Self::Err> {
+        match input {
+            "" | "PCAPNG" => Ok(ExportFormat::PCAPNG),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct ExportOptions {
+    pub recording: PathBuf,
+    pub output: PathBuf,
+    pub format: ExportFormat,
+    pub binding: Option<String>, // Only sessions recorded on this binding are exported
+}
+
+impl ExportOptions {
+    pub fn parse(args: &[String]) -> Result<ExportOptions, String> {
+        let mut args: std::slice::Iter<String> = args.iter();
+        let recording: PathBuf = PathBuf::from(args.next().ok_or("No recording given")?);
+        let (mut output, mut format, mut binding): (Option<PathBuf>, ExportFormat, Option<String>) = (None, ExportFormat::PCAPNG, None);
+        while let Some(flag) = args.next() {
+            let value: &String = args.next().ok_or(format!("No value given for {}", flag))?;
+            match flag.as_str() {
+                "--output" => output = Some(PathBuf::from(value)),
+                "--format" => format = value.to_ascii_uppercase().parse::<ExportFormat>().map_err(|_| format!("Invalid {}: {}", flag, value))?,
+                "--binding" => binding = Some(value.clone()),
+                _ => return Err(format!("Unknown option {}", flag)),
+            }
+        }
+        Ok(ExportOptions {
+            recording,
+            output: output.ok_or("No output given")?,
+            format,
+            binding,
+        })
+    }
+}
+
+// Returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let options: ExportOptions = match ExportOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return 2;
+        },
+    };
+    let sessions: Vec<RecordedSession> = match reader::read_sessions(options.recording.as_path()) {
+        Ok(sessions) => sessions.into_iter()
+            .filter(|session: &RecordedSession| options.binding.as_ref().map_or(true, |binding: &String| *binding == session.header.binding))
+            .collect(),
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", options.recording.display(), e);
+            return 2;
+        },
+    };
+    let output: File = match File::create(options.output.as_path()) {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Unable to create {}: {}", options.output.display(), e);
+            return 2;
+        },
+    };
+    let exported: io::Result<usize> = match options.format {
+        ExportFormat::PCAPNG => pcapng::export_sessions(&sessions, BufWriter::new(output)),
+    };
+    match exported {
+        Ok(packets) => {
+            println!("EXPORT [Sessions: {}] [Packets: {}] [{}]", sessions.len(), packets, options.output.display());
+            0
+        },
+        Err(e) => {
+            eprintln!("Unable to write {}: {}", options.output.display(), e);
+            1
+        },
+    }
+}
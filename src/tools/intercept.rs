@@ -0,0 +1,109 @@
+use base64::Engine;
+use serde_json::{json, Map, Value};
+
+use crate::tools::admin_client;
+
+type Byte = u8;
+
+static USAGE: &'static str = "Usage: tcp_proxy intercept --admin <ADDRESS> <list | show <ID> | forward <ID> | respond <ID> | drop <ID>> \
+[--method <METHOD>] [--target <TARGET>] [--status <CODE>] [--reason <REASON>] [--header \"<NAME>: <VALUE>\"]... [--remove-header <NAME>]... \
+[--body <TEXT> | --body-file <FILE>]";
+
+pub enum InterceptCommand {
+    LIST,
+    SHOW(u64),
+    DECIDE(u64, Value), // The decision posted for the held message
+}
+
+pub struct InterceptOptions {
+    pub admin: String,
+    pub command: InterceptCommand,
+}
+
+impl InterceptOptions {
+    pub fn parse(args: &[String]) -> Result<InterceptOptions, String> {
+        let mut args: std::slice::Iter<String> = args.iter();
+        let (mut admin, mut positional): (Option<String>, Vec<String>) = (None, vec![]);
+        let mut decision: Map<String, Value> = Map::new();
+        let (mut set_headers, mut remove_headers): (Map<String, Value>, Vec<Value>) = (Map::new(), vec![]);
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg.clone());
+                continue;
+            }
+            let value: &String = args.next().ok_or(format!("No value given for {}", arg))?;
+            match arg.as_str() {
+                "--admin" => admin = Some(value.clone()),
+                "--method" | "--target" | "--reason" => { decision.insert(String::from(&arg[2..]), json!(value)); },
+                "--status" => { decision.insert(String::from("status"), json!(value.parse::<u16>().map_err(|_| format!("Invalid {}: {}", arg, value))?)); },
+                "--header" => {
+                    let (name, header_value): (&str, &str) = value.split_once(':').ok_or(format!("Invalid {}: {}", arg, value))?;
+                    set_headers.insert(String::from(name.trim()), json!(header_value.trim()));
+                },
+                "--remove-header" => remove_headers.push(json!(value)),
+                "--body" => { decision.insert(String::from("body"), json!(value)); },
+                "--body-file" => {
+                    let body: Vec<Byte> = std::fs::read(value).map_err(|e| format!("Unable to read {}: {}", value, e))?;
+                    match String::from_utf8(body) {
+                        Ok(text) => decision.insert(String::from("body"), json!(text)),
+                        Err(e) => decision.insert(String::from("body_base64"), json!(base64::engine::general_purpose::STANDARD.encode(e.as_bytes()))),
+                    };
+                },
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+        if !set_headers.is_empty() {
+            decision.insert(String::from("set_headers"), Value::Object(set_headers));
+        }
+        if !remove_headers.is_empty() {
+            decision.insert(String::from("remove_headers"), Value::Array(remove_headers));
+        }
+        let id: Option<u64> = match positional.get(1) {
+            Some(id) => Some(id.parse::<u64>().map_err(|_| format!("Invalid id: {}", id))?),
+            None => None,
+        };
+        let command: InterceptCommand = match (positional.first().map(String::as_str), id) {
+            (Some("list"), None) => InterceptCommand::LIST,
+            (Some("show"), Some(id)) => InterceptCommand::SHOW(id),
+            (Some(action @ ("forward" | "respond" | "drop")), Some(id)) => {
+                decision.insert(String::from("action"), json!(action.to_ascii_uppercase()));
+                InterceptCommand::DECIDE(id, Value::Object(decision))
+            },
+            (Some(_), _) => return Err(format!("Invalid command: {}", positional.join(" "))),
+            (None, _) => return Err(String::from("No command given")),
+        };
+        Ok(InterceptOptions {
+            admin: admin.ok_or("No admin address given")?,
+            command,
+        })
+    }
+}
+
+// Returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let options: InterceptOptions = match InterceptOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return 2;
+        },
+    };
+    let (method, path, body): (&str, String, Vec<Byte>) = match &options.command {
+        InterceptCommand::LIST => ("GET", String::from("/intercepts"), vec![]),
+        InterceptCommand::SHOW(id) => ("GET", format!("/intercepts/{}", id), vec![]),
+        InterceptCommand::DECIDE(id, decision) => ("POST", format!("/intercepts/{}", id), decision.to_string().into_bytes()),
+    };
+    match admin_client::request(options.admin.as_str(), method, path.as_str(), body.as_slice()) {
+        Ok((status, response)) => {
+            match serde_json::from_slice::<Value>(response.as_slice()) {
+                Ok(response) => println!("{}", serde_json::to_string_pretty(&response).unwrap_or_default()),
+                Err(_) => println!("{}", String::from_utf8_lossy(response.as_slice())),
+            }
+            if status / 100 == 2 { 0 } else { 1 }
+        },
+        Err(e) => {
+            eprintln!("Unable to reach the admin API at {}: {}", options.admin, e);
+            2
+        },
+    }
+}
@@ -0,0 +1,16 @@
+pub mod admin_client;
+pub mod export;
+pub mod intercept;
+pub mod replay;
+pub mod top;
+
+// The subcommands of the tcp_proxy binary, None when the name is not one of them
+pub fn run(command: &str, args: &[String]) -> Option<i32> {
+    match command {
+        "replay" => Some(replay::run(args)),
+        "export" => Some(export::run(args)),
+        "intercept" => Some(intercept::run(args)),
+        "top" => Some(top::run(args)),
+        _ => None,
+    }
+}
@@ -0,0 +1,325 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use rayon::ThreadPool;
+
+use crate::traffic::bindingset::{BodyComparison, CompareRule, TrafficDirection};
+use crate::traffic::http::diff::{self, CapturedResponse, ResponseReader};
+use crate::traffic::http::stream::{HttpStreamEvent, HttpStreamParser};
+use crate::traffic::recording::format::SessionChunk;
+use crate::traffic::recording::reader::{self, RecordedSession};
+
+type Byte = u8;
+
+static USAGE: &'static str = "Usage: tcp_proxy replay <RECORDING FILE | DIRECTORY> [--to <ADDR:PORT>] [--speed <MULTIPLIER>] \
+[--concurrency <SESSIONS>] [--binding <NAME>] [--timeout <SECS>] [--headers <NAME,...>] [--body <NONE | HASH | JSON>] \
+[--ignore <FIELD,...>] [--report <FILE>]";
+
+// ---- ReplayOptions ----
+
+pub struct ReplayOptions {
+    pub recording: PathBuf,
+    pub to: Option<SocketAddr>, // Each session's recorded upstream when unset
+    pub speed: f64, // Multiplies the recorded pace, 0 sends everything as fast as possible
+    pub concurrency: usize,
+    pub binding: Option<String>, // Only sessions recorded on this binding are replayed
+    pub timeout: Duration, // How long the upstream may go quiet before a session's responses are considered complete
+    pub compare: CompareRule,
+    pub report: Option<PathBuf>, // JSON lines, one per differing exchange
+}
+
+impl ReplayOptions {
+    pub fn new(recording: PathBuf) -> ReplayOptions {
+        ReplayOptions {
+            recording,
+            to: None,
+            speed: 1.0,
+            concurrency: 10,
+            binding: None,
+            timeout: Duration::from_secs(5),
+            compare: CompareRule::new(),
+            report: None,
+        }
+    }
+    pub fn parse(args: &[String]) -> Result<ReplayOptions, String> {
+        let mut args: std::slice::Iter<String> = args.iter();
+        let mut options: ReplayOptions = ReplayOptions::new(PathBuf::from(args.next().ok_or("No recording given")?));
+        while let Some(flag) = args.next() {
+            let value: &String = args.next().ok_or(format!("No value given for {}", flag))?;
+            let invalid = || format!("Invalid {}: {}", flag, value);
+            match flag.as_str() {
+                "--to" => options.to = Some(value.to_socket_addrs().map_err(|_| invalid())?.next().ok_or_else(invalid)?),
+                "--speed" => options.speed = value.parse::<f64>().ok().filter(|speed: &f64| *speed >= 0.0).ok_or_else(invalid)?,
+                "--concurrency" => options.concurrency = value.parse::<usize>().ok().filter(|sessions: &usize| *sessions > 0).ok_or_else(invalid)?,
+                "--binding" => options.binding = Some(value.clone()),
+                "--timeout" => options.timeout = Duration::from_secs(value.parse::<u64>().map_err(|_| invalid())?),
+                "--headers" => options.compare.headers = split_list(value),
+                "--body" => options.compare.body = value.to_ascii_uppercase().parse::<BodyComparison>().map_err(|_| invalid())?,
+                "--ignore" => options.compare.ignore_fields = split_list(value),
+                "--report" => options.report = Some(PathBuf::from(value)),
+                _ => return Err(format!("Unknown option {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|item: &&str| !item.is_empty()).map(String::from).collect()
+}
+
+// ---- Replay ----
+
+struct ReplayedExchange {
+    exchange: u64,
+    request: String,
+    differences: Vec<String>,
+}
+
+struct SessionOutcome {
+    session: uuid::Uuid,
+    binding: String,
+    exchanges: u64,
+    differing: Vec<ReplayedExchange>,
+    error: Option<String>,
+}
+
+// Returns the process exit code, 0 when every session replayed without differences
+pub fn run(args: &[String]) -> i32 {
+    let options: ReplayOptions = match ReplayOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return 2;
+        },
+    };
+    let sessions: Vec<RecordedSession> = match reader::read_sessions(options.recording.as_path()) {
+        Ok(sessions) => sessions.into_iter()
+            .filter(|session: &RecordedSession| options.binding.as_ref().map_or(true, |binding: &String| *binding == session.header.binding))
+            .filter(|session: &RecordedSession| session.chunks.iter().any(|chunk: &SessionChunk| chunk.direction == TrafficDirection::EGRESS))
+            .collect(),
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", options.recording.display(), e);
+            return 2;
+        },
+    };
+    let mut report: Option<File> = match options.report.as_ref().map(File::create).transpose() {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Unable to create report: {}", e);
+            return 2;
+        },
+    };
+    println!("Replaying {} session(s) from {}", sessions.len(), options.recording.display());
+    let pool: ThreadPool = rayon::ThreadPoolBuilder::new().num_threads(options.concurrency).build().unwrap();
+    let options: Arc<ReplayOptions> = Arc::new(options);
+    let (sender, receiver): (Sender<SessionOutcome>, Receiver<SessionOutcome>) = mpsc::channel();
+    let session_count: usize = sessions.len();
+    let origin: u64 = sessions.first().map_or(0, |session: &RecordedSession| session.header.started);
+    let start: Instant = Instant::now();
+    for session in sessions {
+        // Sessions start at their recorded offsets from the first, or later while every slot is busy
+        wait_until(start, session.header.started - origin, options.speed);
+        let (options_clone, sender_clone): (Arc<ReplayOptions>, Sender<SessionOutcome>) = (options.clone(), sender.clone());
+        pool.spawn(move || {
+            let outcome: SessionOutcome = replay_session(&session, &options_clone);
+            print_outcome(&outcome);
+            let _ = sender_clone.send(outcome);
+        });
+    }
+    let outcomes: Vec<SessionOutcome> = receiver.iter().take(session_count).collect();
+    if let Some(report) = report.as_mut() {
+        if let Err(e) = write_report(report, &outcomes) {
+            eprintln!("Unable to write report: {}", e);
+        }
+    }
+    let failed: usize = outcomes.iter().filter(|outcome: &&SessionOutcome| outcome.error.is_some()).count();
+    let exchanges: u64 = outcomes.iter().map(|outcome: &SessionOutcome| outcome.exchanges).sum();
+    let differing: usize = outcomes.iter().map(|outcome: &SessionOutcome| outcome.differing.len()).sum();
+    println!("REPLAY [Sessions: {}] [Failed: {}] [Exchanges: {}] [Differing: {}] [{} ms]",
+        outcomes.len(), failed, exchanges, differing, start.elapsed().as_millis());
+    if failed > 0 || differing > 0 { 1 } else { 0 }
+}
+
+fn wait_until(start: Instant, offset_micros: u64, speed: f64) {
+    if speed <= 0.0 {
+        return;
+    }
+    let due: Instant = start + Duration::from_micros((offset_micros as f64 / speed) as u64);
+    let now: Instant = Instant::now();
+    if due > now {
+        thread::sleep(due - now);
+    }
+}
+
+fn replay_session(session: &RecordedSession, options: &ReplayOptions) -> SessionOutcome {
+    let mut outcome: SessionOutcome = SessionOutcome {
+        session: session.header.id,
+        binding: session.header.binding.clone(),
+        exchanges: 0,
+        differing: vec![],
+        error: None,
+    };
+    let requests: Vec<(String, String)> = recorded_requests(session.bytes(TrafficDirection::EGRESS).as_slice());
+    let methods: Vec<String> = requests.iter().map(|(method, _): &(String, String)| method.clone()).collect();
+    let replayed: Vec<Byte> = match send_session(session, options, methods.clone()) {
+        Ok(replayed) => replayed,
+        Err(e) => {
+            outcome.error = Some(e.to_string());
+            return outcome;
+        },
+    };
+    let recorded: Vec<Byte> = session.bytes(TrafficDirection::INGRESS);
+    if requests.is_empty() {
+        // Not HTTP, the whole response stream is compared
+        outcome.exchanges = 1;
+        if let Some(difference) = compare_streams(recorded.as_slice(), replayed.as_slice()) {
+            outcome.differing.push(ReplayedExchange { exchange: 1, request: String::new(), differences: vec![difference] });
+        }
+        return outcome;
+    }
+    let (recorded, replayed): (Vec<Option<CapturedResponse>>, Vec<Option<CapturedResponse>>) = (
+        read_responses(recorded.as_slice(), &methods, &options.compare),
+        read_responses(replayed.as_slice(), &methods, &options.compare),
+    );
+    outcome.exchanges = requests.len() as u64;
+    for (index, (_, request)) in requests.iter().enumerate() {
+        let differences: Vec<String> = match (recorded[index].as_ref(), replayed[index].as_ref()) {
+            (Some(recorded), Some(replayed)) => diff::compare(&options.compare, recorded, replayed),
+            (Some(_), None) => vec![String::from("replay: no response")],
+            (None, Some(_)) => vec![String::from("recording: no response")],
+            (None, None) => vec![],
+        };
+        if !differences.is_empty() {
+            outcome.differing.push(ReplayedExchange { exchange: index as u64 + 1, request: request.clone(), differences });
+        }
+    }
+    outcome
+}
+
+// Writes the recorded client side at its recorded pace while the upstream's responses are read on another thread
+fn send_session(session: &RecordedSession, options: &ReplayOptions, methods: Vec<String>) -> io::Result<Vec<Byte>> {
+    let target: SocketAddr = match options.to {
+        Some(to) => to,
+        None => session.header.upstream.parse::<SocketAddr>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid upstream {}", session.header.upstream)))?,
+    };
+    let mut upstream: TcpStream = TcpStream::connect_timeout(&target, options.timeout)?;
+    let mut responses: TcpStream = upstream.try_clone()?;
+    responses.set_read_timeout(Some(options.timeout))?;
+    let compare: CompareRule = options.compare.clone();
+    let reader: JoinHandle<Vec<Byte>> = thread::spawn(move || read_until_answered(&mut responses, methods, compare));
+    let start: Instant = Instant::now();
+    for chunk in session.chunks.iter().filter(|chunk: &&SessionChunk| chunk.direction == TrafficDirection::EGRESS) {
+        wait_until(start, chunk.offset, options.speed);
+        if let Err(e) = upstream.write_all(chunk.bytes.as_slice()) {
+            eprintln!("REPLAY [{}] Upstream stopped reading: {}", session.header.id, e);
+            break;
+        }
+    }
+    let _ = upstream.shutdown(Shutdown::Write);
+    reader.join().map_err(|_| io::Error::new(io::ErrorKind::Other, "Response reader panicked"))
+}
+
+// HTTP sessions finish once every request is answered, since upstreams keep connections alive, anything else at the close
+fn read_until_answered(responses: &mut TcpStream, methods: Vec<String>, compare: CompareRule) -> Vec<Byte> {
+    let mut reader: ResponseReader = ResponseReader::new(compare);
+    let mut next_request = methods.iter().enumerate();
+    let mut replayed: Vec<Byte> = vec![];
+    let mut buffer: [Byte; 8192] = [0; 8192];
+    loop {
+        let read: usize = match responses.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        replayed.extend_from_slice(&buffer[..read]);
+        if !methods.is_empty() {
+            reader.push(&buffer[..read], || {
+                next_request.next().map(|(index, method): (usize, &String)| (index as u64, method.clone()))
+            });
+            if reader.responses() >= methods.len() as u64 && !reader.is_reading_body() {
+                break;
+            }
+        }
+    }
+    let _ = responses.shutdown(Shutdown::Both);
+    replayed
+}
+
+// Method and request line of every request in the client side, empty when it is not HTTP
+fn recorded_requests(egress: &[Byte]) -> Vec<(String, String)> {
+    let mut parser: HttpStreamParser = HttpStreamParser::new(TrafficDirection::EGRESS);
+    parser.push(egress);
+    let mut requests: Vec<(String, String)> = vec![];
+    while let Some(event) = parser.next_event() {
+        match event {
+            HttpStreamEvent::REQUEST(head) => requests.push((head.method.clone(), format!("{} {}", head.method, head.target))),
+            HttpStreamEvent::RAW(_) => break,
+            _ => {},
+        }
+    }
+    requests
+}
+
+// Indexed by request, requests without a complete response are left empty, as are upgraded connections'
+fn read_responses(ingress: &[Byte], methods: &Vec<String>, compare: &CompareRule) -> Vec<Option<CapturedResponse>> {
+    let mut reader: ResponseReader = ResponseReader::new(compare.clone());
+    let mut next_request = methods.iter().enumerate();
+    let mut responses: Vec<(u64, CapturedResponse)> = reader.push(ingress, || {
+        next_request.next().map(|(index, method): (usize, &String)| (index as u64, method.clone()))
+    });
+    responses.extend(reader.finish());
+    let mut ordered: Vec<Option<CapturedResponse>> = methods.iter().map(|_| None).collect();
+    for (index, response) in responses {
+        ordered[index as usize] = Some(response);
+    }
+    ordered
+}
+
+fn compare_streams(recorded: &[Byte], replayed: &[Byte]) -> Option<String> {
+    if recorded == replayed {
+        return None;
+    }
+    let first: usize = recorded.iter().zip(replayed.iter()).take_while(|(recorded, replayed)| recorded == replayed).count();
+    Some(format!("ingress: {} != {} bytes, first difference at byte {}", recorded.len(), replayed.len(), first))
+}
+
+// ---- Report ----
+
+fn print_outcome(outcome: &SessionOutcome) {
+    if let Some(error) = outcome.error.as_ref() {
+        println!("REPLAY FAILED [{}] [{}]: {}", outcome.session, outcome.binding, error);
+        return;
+    }
+    for exchange in outcome.differing.iter() {
+        println!("REPLAY DIFF [{}] [{}] [Exchange: {}] {} {}",
+            outcome.session, outcome.binding, exchange.exchange, exchange.request, exchange.differences.join("; "));
+    }
+}
+
+fn write_report(report: &mut File, outcomes: &Vec<SessionOutcome>) -> io::Result<()> {
+    for outcome in outcomes.iter() {
+        if let Some(error) = outcome.error.as_ref() {
+            writeln!(report, "{}", serde_json::json!({
+                "session": outcome.session.to_string(),
+                "binding": outcome.binding,
+                "error": error,
+            }))?;
+        }
+        for exchange in outcome.differing.iter() {
+            writeln!(report, "{}", serde_json::json!({
+                "session": outcome.session.to_string(),
+                "binding": outcome.binding,
+                "exchange": exchange.exchange,
+                "request": exchange.request,
+                "differences": exchange.differences,
+            }))?;
+        }
+    }
+    report.flush()
+}
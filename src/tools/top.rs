@@ -0,0 +1,482 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::time::{Duration, Instant};
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Sparkline, Table, TableState, Tabs, Wrap};
+use ratatui::Frame;
+use serde_json::Value;
+
+use crate::tools::admin_client;
+use crate::if_let_some;
+
+static USAGE: &'static str = "Usage: tcp_proxy top --admin <ADDRESS> [--interval <MS>] [--last <EXCHANGES>]";
+// Samples kept for each sparkline, one per refresh
+static HISTORY_SAMPLES: usize = 240;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum TopPane {
+    CONNECTIONS,
+    EXCHANGES,
+    VERDICTS,
+}
+
+static PANES: [TopPane; 3] = [TopPane::CONNECTIONS, TopPane::EXCHANGES, TopPane::VERDICTS];
+
+pub struct TopOptions {
+    pub admin: String,
+    pub interval: Duration,
+    pub last: usize, // Exchanges and verdicts fetched for the selected binding
+}
+
+impl TopOptions {
+    pub fn parse(args: &[String]) -> Result<TopOptions, String> {
+        let mut args: std::slice::Iter<String> = args.iter();
+        let (mut admin, mut interval, mut last): (Option<String>, u64, usize) = (None, 1000, 100);
+        while let Some(flag) = args.next() {
+            let value: &String = args.next().ok_or(format!("No value given for {}", flag))?;
+            match flag.as_str() {
+                "--admin" => admin = Some(value.clone()),
+                "--interval" => interval = value.parse::<u64>().ok().filter(|interval: &u64| *interval >= 100).ok_or(format!("Invalid {}: {}", flag, value))?,
+                "--last" => last = value.parse::<usize>().ok().filter(|last: &usize| *last > 0).ok_or(format!("Invalid {}: {}", flag, value))?,
+                _ => return Err(format!("Unknown option {}", flag)),
+            }
+        }
+        Ok(TopOptions {
+            admin: admin.ok_or("No admin address given")?,
+            interval: Duration::from_millis(interval),
+            last,
+        })
+    }
+}
+
+// ---- BindingView ----
+
+struct BindingView {
+    summary: Value, // As the admin API last reported it
+    egress_rates: VecDeque<u64>, // Bytes per second, oldest first
+    ingress_rates: VecDeque<u64>,
+}
+
+impl BindingView {
+    fn bytes(&self, field: &str) -> u64 {
+        self.summary[field].as_u64().unwrap_or(0)
+    }
+    fn push_rates(&mut self, summary: Value, elapsed: Duration) {
+        let seconds: f64 = elapsed.as_secs_f64().max(0.001);
+        for (field, rates) in [("egress_bytes", &mut self.egress_rates), ("ingress_bytes", &mut self.ingress_rates)] {
+            // A restarted proxy counts from zero again
+            let delta: u64 = summary[field].as_u64().unwrap_or(0).saturating_sub(self.summary[field].as_u64().unwrap_or(0));
+            if rates.len() >= HISTORY_SAMPLES {
+                rates.pop_front();
+            }
+            rates.push_back((delta as f64 / seconds) as u64);
+        }
+        self.summary = summary;
+    }
+}
+
+// ---- TopState ----
+
+struct TopState {
+    options: TopOptions,
+    bindings: Vec<BindingView>,
+    selected: usize, // Binding
+    pane: TopPane,
+    rows: TableState, // Row selected in the pane
+    connections: Vec<Value>,
+    exchanges: Vec<Value>, // HAR entries, newest first
+    verdicts: Vec<Value>, // Newest first
+    detail: Option<Value>, // Exchange being drilled into
+    scroll: u16,
+    polled: Option<Instant>,
+    error: Option<String>,
+}
+
+impl TopState {
+    fn new(options: TopOptions) -> TopState {
+        TopState {
+            options,
+            bindings: vec![],
+            selected: 0,
+            pane: TopPane::CONNECTIONS,
+            rows: TableState::default(),
+            connections: vec![],
+            exchanges: vec![],
+            verdicts: vec![],
+            detail: None,
+            scroll: 0,
+            polled: None,
+            error: None,
+        }
+    }
+    fn poll(&mut self) {
+        self.error = self.refresh().err();
+    }
+    fn refresh(&mut self) -> Result<(), String> {
+        let admin: &str = self.options.admin.as_str();
+        let summaries: Value = admin_client::get_json(admin, "/bindings")?;
+        let elapsed: Duration = self.polled.map_or(self.options.interval, |polled: Instant| polled.elapsed());
+        self.polled = Some(Instant::now());
+        let mut previous: HashMap<String, BindingView> = self.bindings.drain(..)
+            .map(|view: BindingView| (String::from(view.summary["name"].as_str().unwrap_or("")), view))
+            .collect();
+        for summary in summaries["bindings"].as_array().cloned().unwrap_or_default() {
+            let view: BindingView = match previous.remove(summary["name"].as_str().unwrap_or("")) {
+                Some(mut view) => {
+                    view.push_rates(summary, elapsed);
+                    view
+                },
+                None => BindingView {
+                    summary,
+                    egress_rates: VecDeque::new(),
+                    ingress_rates: VecDeque::new(),
+                },
+            };
+            self.bindings.push(view);
+        }
+        self.selected = self.selected.min(self.bindings.len().saturating_sub(1));
+        let binding: &BindingView = match self.bindings.get(self.selected) {
+            Some(binding) => binding,
+            None => return Ok(()),
+        };
+        let name: String = url_encode(binding.summary["name"].as_str().unwrap_or(""));
+        let last: usize = self.options.last;
+        self.connections = admin_client::get_json(admin, format!("/connections/{}", name).as_str())?["connections"]
+            .as_array().cloned().unwrap_or_default();
+        self.verdicts = admin_client::get_json(admin, format!("/verdicts/{}?last={}", name, last).as_str())?["verdicts"]
+            .as_array().cloned().unwrap_or_default();
+        self.verdicts.reverse();
+        self.exchanges = match binding.summary["har"].as_bool().unwrap_or(false) {
+            true => admin_client::get_json(admin, format!("/har/{}?last={}", name, last).as_str())?["log"]["entries"]
+                .as_array().cloned().unwrap_or_default(),
+            false => vec![],
+        };
+        self.exchanges.reverse();
+        Ok(())
+    }
+    fn pane_rows(&self) -> usize {
+        match self.pane {
+            TopPane::CONNECTIONS => self.connections.len(),
+            TopPane::EXCHANGES => self.exchanges.len(),
+            TopPane::VERDICTS => self.verdicts.len(),
+        }
+    }
+    // Returns false once the user quits
+    fn key(&mut self, key: KeyEvent) -> bool {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return false;
+        }
+        if self.detail.is_some() {
+            match key.code {
+                KeyCode::Esc | KeyCode::Backspace | KeyCode::Enter => self.detail = None,
+                KeyCode::Char('q') => return false,
+                KeyCode::Up | KeyCode::Char('k') => self.scroll = self.scroll.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => self.scroll = self.scroll.saturating_add(1),
+                KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(20),
+                KeyCode::PageDown => self.scroll = self.scroll.saturating_add(20),
+                KeyCode::Home => self.scroll = 0,
+                _ => {},
+            }
+            return true;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Left | KeyCode::Char('h') => self.select_binding(-1),
+            KeyCode::Right | KeyCode::Char('l') => self.select_binding(1),
+            KeyCode::Tab => self.select_pane(1),
+            KeyCode::BackTab => self.select_pane(-1),
+            KeyCode::Char('1') => self.pane = TopPane::CONNECTIONS,
+            KeyCode::Char('2') => self.pane = TopPane::EXCHANGES,
+            KeyCode::Char('3') => self.pane = TopPane::VERDICTS,
+            KeyCode::Up | KeyCode::Char('k') => self.rows.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.rows.select_next(),
+            KeyCode::Enter if self.pane == TopPane::EXCHANGES => {
+                self.detail = self.rows.selected().and_then(|row: usize| self.exchanges.get(row)).cloned();
+                self.scroll = 0;
+            },
+            _ => {},
+        }
+        true
+    }
+    fn select_binding(&mut self, step: isize) {
+        if self.bindings.is_empty() {
+            return;
+        }
+        self.selected = (self.selected as isize + step).rem_euclid(self.bindings.len() as isize) as usize;
+        self.rows.select(None);
+        self.poll();
+    }
+    fn select_pane(&mut self, step: isize) {
+        let index: usize = PANES.iter().position(|pane: &TopPane| *pane == self.pane).unwrap_or(0);
+        self.pane = PANES[(index as isize + step).rem_euclid(PANES.len() as isize) as usize];
+        self.rows.select(None);
+    }
+}
+
+// Returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let options: TopOptions = match TopOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return 2;
+        },
+    };
+    // Fail before taking over the terminal when the proxy cannot be reached
+    if let Err(e) = admin_client::get_json(options.admin.as_str(), "/bindings") {
+        eprintln!("Unable to reach the admin API at {}: {}", options.admin, e);
+        return 2;
+    }
+    let mut terminal: DefaultTerminal = ratatui::init();
+    let result: io::Result<()> = watch(&mut terminal, TopState::new(options));
+    ratatui::restore();
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        },
+    }
+}
+
+fn watch(terminal: &mut DefaultTerminal, mut state: TopState) -> io::Result<()> {
+    state.poll();
+    loop {
+        terminal.draw(|frame: &mut Frame| draw(frame, &mut state))?;
+        let wait: Duration = state.options.interval.saturating_sub(state.polled.map_or(Duration::ZERO, |polled: Instant| polled.elapsed()));
+        if event::poll(wait)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !state.key(key) {
+                    return Ok(());
+                }
+            }
+            continue;
+        }
+        state.poll();
+        // Keeps the interval when the proxy is unreachable, polled is only set by a successful refresh
+        if state.error.is_some() {
+            state.polled = Some(Instant::now());
+        }
+    }
+}
+
+// ---- Drawing ----
+
+fn draw(frame: &mut Frame, state: &mut TopState) {
+    let binding_rows: u16 = state.bindings.len().min(8) as u16;
+    let [header, bindings, sparklines, pane, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(binding_rows + 3),
+        Constraint::Length(6),
+        Constraint::Min(5),
+        Constraint::Length(1),
+    ]).areas(frame.area());
+    let status: Span = match state.error.as_ref() {
+        Some(error) => Span::styled(format!(" {}", error), Style::default().fg(Color::Red)),
+        None => Span::raw(format!(" refreshed every {} ms", state.options.interval.as_millis())),
+    };
+    frame.render_widget(Line::from(vec![Span::styled(format!("tcp_proxy top {}", state.options.admin), Style::default().add_modifier(Modifier::BOLD)), status]), header);
+    draw_bindings(frame, state, bindings);
+    draw_sparklines(frame, state, sparklines);
+    match state.detail.as_ref() {
+        Some(exchange) => frame.render_widget(
+            Paragraph::new(exchange_lines(exchange))
+                .block(Block::default().borders(Borders::ALL).title(" Exchange "))
+                .wrap(Wrap { trim: false })
+                .scroll((state.scroll, 0)),
+            pane,
+        ),
+        None => draw_pane(frame, state, pane),
+    }
+    let help: &str = match state.detail {
+        Some(_) => "↑/↓ scroll  PgUp/PgDn page  Esc back  q quit",
+        None => "←/→ binding  Tab/1-3 pane  ↑/↓ row  Enter open exchange  q quit",
+    };
+    frame.render_widget(Line::styled(help, Style::default().fg(Color::DarkGray)), footer);
+}
+
+fn draw_bindings(frame: &mut Frame, state: &TopState, area: Rect) {
+    let rows: Vec<Row> = state.bindings.iter().map(|view: &BindingView| {
+        let summary: &Value = &view.summary;
+        Row::new(vec![
+            text(&summary["name"]),
+            format!("{} -> {}", text(&summary["from"]), match text(&summary["to"]).as_str() {
+                "" => String::from("(local)"),
+                to => String::from(to),
+            }),
+            text(&summary["protocol"]),
+            text(&summary["open"]),
+            text(&summary["accepted"]),
+            format!("{}/s", bytes(view.egress_rates.back().copied().unwrap_or(0))),
+            format!("{}/s", bytes(view.ingress_rates.back().copied().unwrap_or(0))),
+            bytes(view.bytes("egress_bytes")),
+            bytes(view.bytes("ingress_bytes")),
+        ])
+    }).collect();
+    let table: Table = Table::new(rows, [
+        Constraint::Length(16),
+        Constraint::Min(30),
+        Constraint::Length(8),
+        Constraint::Length(6),
+        Constraint::Length(9),
+        Constraint::Length(12),
+        Constraint::Length(12),
+        Constraint::Length(10),
+        Constraint::Length(10),
+    ])
+        .header(Row::new(vec!["BINDING", "ROUTE", "PROTOCOL", "OPEN", "ACCEPTED", "EGRESS", "INGRESS", "SENT", "RECEIVED"])
+            .style(Style::default().add_modifier(Modifier::BOLD)))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(Block::default().borders(Borders::ALL).title(" Bindings "));
+    let mut selected: TableState = TableState::default().with_selected(Some(state.selected).filter(|_| !state.bindings.is_empty()));
+    frame.render_stateful_widget(table, area, &mut selected);
+}
+
+fn draw_sparklines(frame: &mut Frame, state: &TopState, area: Rect) {
+    let [egress, ingress] = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(area);
+    if_let_some!{view = state.bindings.get(state.selected)};
+    for (area, title, rates, color) in [(egress, "Egress", &view.egress_rates, Color::Cyan), (ingress, "Ingress", &view.ingress_rates, Color::Green)] {
+        // Newest on the right, as many samples as fit
+        let width: usize = area.width.saturating_sub(2) as usize;
+        let shown: Vec<u64> = rates.iter().skip(rates.len().saturating_sub(width)).copied().collect();
+        let peak: u64 = shown.iter().copied().max().unwrap_or(0);
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::default().borders(Borders::ALL)
+                    .title(format!(" {} {}/s, peak {}/s ", title, bytes(rates.back().copied().unwrap_or(0)), bytes(peak))))
+                .data(&shown)
+                .style(Style::default().fg(color)),
+            area,
+        );
+    }
+}
+
+fn draw_pane(frame: &mut Frame, state: &mut TopState, area: Rect) {
+    let [tabs, list] = Layout::vertical([Constraint::Length(1), Constraint::Min(1)]).areas(area);
+    let binding: &str = state.bindings.get(state.selected).and_then(|view: &BindingView| view.summary["name"].as_str()).unwrap_or("");
+    let titles: Vec<String> = vec![
+        format!("1 Connections ({})", state.connections.len()),
+        format!("2 Exchanges ({})", state.exchanges.len()),
+        format!("3 Verdicts ({})", state.verdicts.len()),
+    ];
+    let index: usize = PANES.iter().position(|pane: &TopPane| *pane == state.pane).unwrap_or(0);
+    frame.render_widget(Tabs::new(titles).select(index).highlight_style(Style::default().add_modifier(Modifier::REVERSED)), tabs);
+    let (header, rows, widths): (Vec<&str>, Vec<Row>, Vec<Constraint>) = match state.pane {
+        TopPane::CONNECTIONS => (
+            vec!["CONNECTION", "CLIENT", "UPSTREAM", "OPENED", "SENT", "RECEIVED"],
+            state.connections.iter().map(|connection: &Value| Row::new(vec![
+                text(&connection["id"]),
+                text(&connection["client"]),
+                text(&connection["upstream"]),
+                text(&connection["opened"]),
+                bytes(connection["egress_bytes"].as_u64().unwrap_or(0)),
+                bytes(connection["ingress_bytes"].as_u64().unwrap_or(0)),
+            ])).collect(),
+            vec![Constraint::Length(36), Constraint::Length(22), Constraint::Length(22), Constraint::Length(24), Constraint::Length(10), Constraint::Length(10)],
+        ),
+        TopPane::EXCHANGES => (
+            vec!["STARTED", "METHOD", "URL", "STATUS", "TIME", "SIZE"],
+            state.exchanges.iter().map(|entry: &Value| Row::new(vec![
+                text(&entry["startedDateTime"]),
+                text(&entry["request"]["method"]),
+                text(&entry["request"]["url"]),
+                text(&entry["response"]["status"]),
+                format!("{} ms", entry["time"].as_f64().unwrap_or(0.0).round()),
+                bytes(entry["response"]["content"]["size"].as_u64().unwrap_or(0)),
+            ])).collect(),
+            vec![Constraint::Length(24), Constraint::Length(8), Constraint::Min(30), Constraint::Length(6), Constraint::Length(10), Constraint::Length(10)],
+        ),
+        TopPane::VERDICTS => (
+            vec!["TIME", "CONNECTION", "REQUEST", "VERDICT", "STATUS"],
+            state.verdicts.iter().map(|verdict: &Value| Row::new(vec![
+                text(&verdict["time"]),
+                text(&verdict["connection"]),
+                text(&verdict["request"]),
+                text(&verdict["verdict"]),
+                text(&verdict["status"]),
+            ])).collect(),
+            vec![Constraint::Length(24), Constraint::Length(36), Constraint::Min(30), Constraint::Length(8), Constraint::Length(6)],
+        ),
+    };
+    let title: String = match state.pane {
+        TopPane::EXCHANGES if !state.bindings.get(state.selected).map_or(false, |view: &BindingView| view.summary["har"].as_bool().unwrap_or(false)) =>
+            format!(" {} does not buffer exchanges, see har.buffer ", binding),
+        _ => format!(" {} ", binding),
+    };
+    if state.rows.selected().map_or(false, |row: usize| row >= state.pane_rows()) {
+        state.rows.select(state.pane_rows().checked_sub(1));
+    }
+    let table: Table = Table::new(rows, widths)
+        .header(Row::new(header).style(Style::default().add_modifier(Modifier::BOLD)))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(Block::default().borders(Borders::ALL).title(title));
+    frame.render_stateful_widget(table, list, &mut state.rows);
+}
+
+// Request and response as the HAR entry recorded them
+fn exchange_lines(entry: &Value) -> Vec<Line<'static>> {
+    let bold: Style = Style::default().add_modifier(Modifier::BOLD);
+    let mut lines: Vec<Line> = vec![];
+    let request: &Value = &entry["request"];
+    let response: &Value = &entry["response"];
+    lines.push(Line::styled(format!("{} {} {}", text(&request["method"]), text(&request["url"]), text(&request["httpVersion"])), bold));
+    header_lines(&mut lines, &request["headers"]);
+    if let Some(body) = request["postData"]["text"].as_str() {
+        lines.push(Line::raw(""));
+        lines.extend(body.lines().map(|line: &str| Line::raw(String::from(line))));
+    }
+    lines.push(Line::raw(""));
+    lines.push(Line::styled(format!("{} {} {}", text(&response["httpVersion"]), text(&response["status"]), text(&response["statusText"])), bold));
+    header_lines(&mut lines, &response["headers"]);
+    if let Some(body) = response["content"]["text"].as_str() {
+        lines.push(Line::raw(""));
+        if let Some(encoding) = response["content"]["encoding"].as_str() {
+            lines.push(Line::styled(format!("({} encoded)", encoding), Style::default().fg(Color::DarkGray)));
+        }
+        lines.extend(body.lines().map(|line: &str| Line::raw(String::from(line))));
+    }
+    if let Some(comment) = entry["comment"].as_str() {
+        lines.push(Line::raw(""));
+        lines.push(Line::styled(String::from(comment), Style::default().fg(Color::DarkGray)));
+    }
+    lines.push(Line::raw(""));
+    lines.push(Line::styled(format!("{} ms, {} via {}", entry["time"].as_f64().unwrap_or(0.0).round(), text(&entry["_clientAddress"]), text(&entry["serverIPAddress"])), Style::default().fg(Color::DarkGray)));
+    lines
+}
+
+fn header_lines(lines: &mut Vec<Line<'static>>, headers: &Value) {
+    for header in headers.as_array().into_iter().flatten() {
+        lines.push(Line::from(vec![
+            Span::styled(format!("{}: ", text(&header["name"])), Style::default().fg(Color::Cyan)),
+            Span::raw(text(&header["value"])),
+        ]));
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn bytes(count: u64) -> String {
+    match count {
+        count if count >= 1024 * 1024 * 1024 => format!("{:.1} GiB", count as f64 / (1024.0 * 1024.0 * 1024.0)),
+        count if count >= 1024 * 1024 => format!("{:.1} MiB", count as f64 / (1024.0 * 1024.0)),
+        count if count >= 1024 => format!("{:.1} KiB", count as f64 / 1024.0),
+        count => format!("{} B", count),
+    }
+}
+
+fn url_encode(segment: &str) -> String {
+    segment.bytes().map(|byte: u8| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => String::from(byte as char),
+        byte => format!("%{:02X}", byte),
+    }).collect()
+}
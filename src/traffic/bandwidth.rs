@@ -0,0 +1,91 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::servlet::binding_context::BindingContext;
+use crate::traffic::bindingset::{BandwidthRule, TrafficDirection};
+use crate::traffic::limiter::TokenBucket;
+
+fn new_bucket(bytes_per_second: u64, burst_bytes: u64) -> Option<TokenBucket> {
+    if bytes_per_second == 0 {
+        return None;
+    }
+    let burst: u64 = match burst_bytes {
+        0 => std::cmp::max(bytes_per_second / 10, 1),
+        burst => burst,
+    };
+    Some(TokenBucket::new(bytes_per_second as f64, burst as f64))
+}
+
+// The binding-wide buckets, shared by every connection on the binding
+pub struct BindingBandwidth {
+    pub egress: Option<Mutex<TokenBucket>>,
+    pub ingress: Option<Mutex<TokenBucket>>,
+}
+
+impl BindingBandwidth {
+    pub fn new(rule: &BandwidthRule) -> BindingBandwidth {
+        BindingBandwidth {
+            egress: new_bucket(rule.egress_bytes_per_second, rule.burst_bytes).map(Mutex::new),
+            ingress: new_bucket(rule.ingress_bytes_per_second, rule.burst_bytes).map(Mutex::new),
+        }
+    }
+}
+
+// Paces one direction of a connection against its own bucket and the binding's
+pub(crate) struct BandwidthShaper {
+    direction: TrafficDirection,
+    context: Arc<BindingContext>,
+    connection: Option<TokenBucket>,
+    quantum: usize,
+}
+
+impl BandwidthShaper {
+    pub fn new(direction: TrafficDirection, context: &Arc<BindingContext>) -> Option<BandwidthShaper> {
+        let rule: &BandwidthRule = &context.rule.bandwidth;
+        let connection_rate: u64 = match direction {
+            TrafficDirection::EGRESS => rule.connection_egress_bytes_per_second,
+            TrafficDirection::INGRESS => rule.connection_ingress_bytes_per_second,
+        };
+        let connection: Option<TokenBucket> = new_bucket(connection_rate, rule.burst_bytes);
+        let binding_capacity: Option<f64> = BandwidthShaper::binding_bucket(direction, context)
+            .map(|bucket: &Mutex<TokenBucket>| bucket.lock().unwrap().capacity());
+        let capacities: Vec<f64> = connection.iter().map(|bucket: &TokenBucket| bucket.capacity())
+            .chain(binding_capacity)
+            .collect();
+        if capacities.is_empty() {
+            return None;
+        }
+        Some(BandwidthShaper {
+            direction,
+            context: context.clone(),
+            connection,
+            // Never move more than a burst at once, so the stream is paced rather than sent in large gaps
+            quantum: capacities.iter().cloned().fold(f64::MAX, f64::min) as usize,
+        })
+    }
+    pub fn quantum(&self) -> usize {
+        self.quantum
+    }
+    // Blocks until the bytes fit within every bucket
+    pub fn wait(&mut self, bytes: usize) {
+        let connection_wait: Duration = match self.connection.as_mut() {
+            Some(bucket) => bucket.reserve(bytes as f64),
+            None => Duration::from_secs(0),
+        };
+        let binding_wait: Duration = match BandwidthShaper::binding_bucket(self.direction, &self.context) {
+            Some(bucket) => bucket.lock().unwrap().reserve(bytes as f64),
+            None => Duration::from_secs(0),
+        };
+        let wait: Duration = std::cmp::max(connection_wait, binding_wait);
+        if wait > Duration::from_secs(0) {
+            thread::sleep(wait);
+        }
+    }
+    fn binding_bucket(direction: TrafficDirection, context: &BindingContext) -> Option<&Mutex<TokenBucket>> {
+        match direction {
+            TrafficDirection::EGRESS => context.bandwidth.egress.as_ref(),
+            TrafficDirection::INGRESS => context.bandwidth.ingress.as_ref(),
+        }
+    }
+}
@@ -2,17 +2,24 @@ use std::net::SocketAddr;
 use std::collections::HashSet;
 use std::vec::Vec;
 use std::fs;
+use std::io;
 use std::str::FromStr;
+use core::fmt;
+use slog::{Discard, Logger};
+use crate::traffic::http::message;
 use crate::traffic::json_mappings::*;
+use crate::traffic::script::RequestScript;
 
 // ---- Enums ----
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub enum RuleType {
-    HEADER, // Expects "header_mappings": [ { "key": "header name", "value": "header value" } ]
-    URL, // Expects "url_wildcard": "regex\sstring"
-    METHOD, // Expects "method_enum": "<GET | POST | DELETE | PATCH | PUT | OPTIONS>"
-    VERSION, // Expects "version_float": <0.9 | 1.0 | 1.1 | 2.0 | 3.0>
+    HEADER, // Expects "header_mappings": [ { "key": "header name", "value": "header value", "mode": "EXACT" } ]
+    URL, // Expects "url_wildcard": "regex\sstring" and/or "url_match": { "host": "glob", "path": "glob", "query": [ ... ] }
+    METHOD, // Expects "method_enum": "GET" and/or "allowed_methods": [ "GET", "HEAD" ], "denied_methods": [ "TRACE" ]
+    VERSION, // Expects "version_float": <0.9 | 1.0 | 1.1 | 2.0> and/or "min_version", "max_version", "versions": [ ... ]
+    RATELIMIT, // Expects "rate_limit": { "key": "CLIENTIP", "requests_per_second": 10, "request_burst": 20 }
+    SCRIPT, // Expects "script": "<rhai source>" or "script_file": "<path>", only valid on egress rules
 }
 
 impl FromStr for RuleType {
@@ -23,47 +30,450 @@ impl FromStr for RuleType {
             "URL"  => Ok(RuleType::URL),
             "METHOD"  => Ok(RuleType::METHOD),
             "VERSION"  => Ok(RuleType::VERSION),
+            "RATELIMIT" => Ok(RuleType::RATELIMIT),
+            "SCRIPT" => Ok(RuleType::SCRIPT),
             _ => Ok(RuleType::URL),
         }
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum HttpMethod {
+    // RFC 9110 and RFC 5789
     GET,
+    HEAD,
     POST,
-    DELETE,
-    PATCH,
     PUT,
+    DELETE,
+    CONNECT,
     OPTIONS,
-    UPDATE
+    TRACE,
+    PATCH,
+    // WebDAV, RFC 4918
+    PROPFIND,
+    PROPPATCH,
+    MKCOL,
+    COPY,
+    MOVE,
+    LOCK,
+    UNLOCK,
+    // Any other token, e.g. "UPDATE" or "PURGE"
+    EXTENSION(String),
 }
 
 impl FromStr for HttpMethod {
     type Err = ();
+    // Methods are case-sensitive, "get" is an extension method rather than GET
     fn from_str(input: &str) -> Result<HttpMethod, Self::Err> {
         match input {
             "GET"  => Ok(HttpMethod::GET),
+            "HEAD" => Ok(HttpMethod::HEAD),
             "POST"  => Ok(HttpMethod::POST),
-            "DELETE"  => Ok(HttpMethod::DELETE),
-            "PATCH"  => Ok(HttpMethod::PATCH),
             "PUT"  => Ok(HttpMethod::PUT),
+            "DELETE"  => Ok(HttpMethod::DELETE),
+            "CONNECT" => Ok(HttpMethod::CONNECT),
             "OPTIONS"  => Ok(HttpMethod::OPTIONS),
-            "UPDATE" => Ok(HttpMethod::UPDATE),
-            _ => Ok(HttpMethod::GET),
+            "TRACE" => Ok(HttpMethod::TRACE),
+            "PATCH"  => Ok(HttpMethod::PATCH),
+            "PROPFIND" => Ok(HttpMethod::PROPFIND),
+            "PROPPATCH" => Ok(HttpMethod::PROPPATCH),
+            "MKCOL" => Ok(HttpMethod::MKCOL),
+            "COPY" => Ok(HttpMethod::COPY),
+            "MOVE" => Ok(HttpMethod::MOVE),
+            "LOCK" => Ok(HttpMethod::LOCK),
+            "UNLOCK" => Ok(HttpMethod::UNLOCK),
+            token if message::is_token(token) => Ok(HttpMethod::EXTENSION(String::from(token))),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for HttpMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpMethod::EXTENSION(token) => write!(f, "{}", token),
+            method => write!(f, "{:?}", method),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, PartialOrd, Ord)]
+pub struct HttpVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl HttpVersion {
+    pub fn new(major: u8, minor: u8) -> HttpVersion {
+        HttpVersion {
+            major,
+            minor,
+        }
+    }
+    // JSON carries versions as floats
+    pub fn from_float(value: f32) -> io::Result<HttpVersion> {
+        match value > 0.0 {
+            true => HttpVersion::from_str(format!("{:.1}", value).as_str()),
+            false => Err(()),
+        }.map_err(|_| invalid(format!("Invalid HTTP version: {}", value)))
+    }
+}
+
+impl FromStr for HttpVersion {
+    type Err = ();
+    // Accepts "1.1" as well as the wire form "HTTP/1.1", "HTTP/2" is read as 2.0
+    fn from_str(input: &str) -> Result<HttpVersion, Self::Err> {
+        let number: &str = input.strip_prefix("HTTP/").unwrap_or(input);
+        let (major, minor): (&str, &str) = number.split_once('.').unwrap_or((number, "0"));
+        if major.is_empty() || minor.is_empty() || !major.chars().chain(minor.chars()).all(|c: char| c.is_ascii_digit()) {
+            return Err(());
+        }
+        match (major.parse::<u8>(), minor.parse::<u8>()) {
+            (Ok(major), Ok(minor)) => Ok(HttpVersion::new(major, minor)),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HTTP/{}.{}", self.major, self.minor)
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum VersionAction {
+    REJECT, // Answer 505 HTTP Version Not Supported and close
+    DOWNGRADE, // Forward HTTP/1.x requests newer than "max_version" as "max_version", reject anything else
+}
+
+impl FromStr for VersionAction {
+    type Err = ();
+    fn from_str(input: &str) -> Result<VersionAction, Self::Err> {
+        match input {
+            "" | "REJECT" => Ok(VersionAction::REJECT),
+            "DOWNGRADE" => Ok(VersionAction::DOWNGRADE),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum RateLimitKey {
+    CLIENTIP,
+    HEADER, // Value of "key_header", e.g. an API key, requests without it share one bucket
+    BINDING, // One bucket for the whole binding
+}
+
+impl FromStr for RateLimitKey {
+    type Err = ();
+    fn from_str(input: &str) -> Result<RateLimitKey, Self::Err> {
+        match input {
+            "" | "CLIENTIP" => Ok(RateLimitKey::CLIENTIP),
+            "HEADER" => Ok(RateLimitKey::HEADER),
+            "BINDING" => Ok(RateLimitKey::BINDING),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum HeaderMatchMode {
+    EXACT, // Value equals "value"
+    PREFIX, // Value starts with "value", e.g. "application/json" matches "application/json; charset=utf-8"
+    REGEX, // Value matches the "value" regex
+    PRESENT, // Header exists, "value" is ignored
+    ABSENT, // Header does not exist, "value" is ignored
+    LT, // Numeric comparisons of the value against "value"
+    LTE,
+    GT,
+    GTE,
+}
+
+impl FromStr for HeaderMatchMode {
+    type Err = ();
+    fn from_str(input: &str) -> Result<HeaderMatchMode, Self::Err> {
+        match input {
+            "" | "EXACT" => Ok(HeaderMatchMode::EXACT),
+            "PREFIX" => Ok(HeaderMatchMode::PREFIX),
+            "REGEX" => Ok(HeaderMatchMode::REGEX),
+            "PRESENT" => Ok(HeaderMatchMode::PRESENT),
+            "ABSENT" => Ok(HeaderMatchMode::ABSENT),
+            "LT" => Ok(HeaderMatchMode::LT),
+            "LTE" => Ok(HeaderMatchMode::LTE),
+            "GT" => Ok(HeaderMatchMode::GT),
+            "GTE" => Ok(HeaderMatchMode::GTE),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum PathMatchMode {
+    GLOB, // "*" matches within a segment, "**" across segments
+    REGEX,
+}
+
+impl FromStr for PathMatchMode {
+    type Err = ();
+    fn from_str(input: &str) -> Result<PathMatchMode, Self::Err> {
+        match input {
+            "" | "GLOB" => Ok(PathMatchMode::GLOB),
+            "REGEX" => Ok(PathMatchMode::REGEX),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum HeaderActionType {
+    SET, // Replaces all values of "key" with "value"
+    APPEND, // Adds another "key" header with "value"
+    REMOVE, // Removes all "key" headers
+    REPLACE, // Applies "pattern" -> "replacement" to each value of "key"
+}
+
+impl FromStr for HeaderActionType {
+    type Err = ();
+    fn from_str(input: &str) -> Result<HeaderActionType, Self::Err> {
+        match input {
+            "SET" => Ok(HeaderActionType::SET),
+            "APPEND" => Ok(HeaderActionType::APPEND),
+            "REMOVE" => Ok(HeaderActionType::REMOVE),
+            "REPLACE" => Ok(HeaderActionType::REPLACE),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum UrlActionType {
+    REWRITE, // Substitutes "pattern" -> "replacement" on the request target before forwarding
+    REDIRECT, // Answers with "status" and a Location of "replacement" instead of forwarding
+}
+
+impl FromStr for UrlActionType {
+    type Err = ();
+    fn from_str(input: &str) -> Result<UrlActionType, Self::Err> {
+        match input {
+            "REWRITE" => Ok(UrlActionType::REWRITE),
+            "REDIRECT" => Ok(UrlActionType::REDIRECT),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum BindingProtocol {
+    TCP, // Bytes are forwarded untouched, only connection level features apply
+    HTTP, // Streams are parsed as HTTP/1.x so rules can inspect and transform messages
+}
+
+impl FromStr for BindingProtocol {
+    type Err = ();
+    fn from_str(input: &str) -> Result<BindingProtocol, Self::Err> {
+        match input {
+            "" | "TCP" => Ok(BindingProtocol::TCP),
+            "HTTP" => Ok(BindingProtocol::HTTP),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum TrafficDirection {
+    EGRESS, // Client -> upstream (requests)
+    INGRESS, // Upstream -> client (responses)
+}
+
+impl fmt::Display for TrafficDirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            TrafficDirection::EGRESS => "EGRESS",
+            TrafficDirection::INGRESS => "INGRESS",
+        })
+    }
+}
+
+impl FromStr for TrafficDirection {
+    type Err = ();
+    fn from_str(input: &str) -> Result<TrafficDirection, Self::Err> {
+        match input {
+            "EGRESS" => Ok(TrafficDirection::EGRESS),
+            "INGRESS" => Ok(TrafficDirection::INGRESS),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum ForwardingMode {
+    NONE, // Leave the header as received
+    APPEND, // Extend incoming values from trusted proxies, replace them otherwise
+    OVERWRITE, // Always replace incoming values
+}
+
+impl FromStr for ForwardingMode {
+    type Err = ();
+    fn from_str(input: &str) -> Result<ForwardingMode, Self::Err> {
+        match input {
+            "" | "NONE" => Ok(ForwardingMode::NONE),
+            "APPEND" => Ok(ForwardingMode::APPEND),
+            "OVERWRITE" => Ok(ForwardingMode::OVERWRITE),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum ProxyProtocolVersion {
+    NONE,
+    V1, // Human readable header
+    V2, // Binary header
+    ANY, // Accept either version, only valid for "accept"
+}
+
+impl FromStr for ProxyProtocolVersion {
+    type Err = ();
+    fn from_str(input: &str) -> Result<ProxyProtocolVersion, Self::Err> {
+        match input {
+            "" | "NONE" => Ok(ProxyProtocolVersion::NONE),
+            "V1" => Ok(ProxyProtocolVersion::V1),
+            "V2" => Ok(ProxyProtocolVersion::V2),
+            "ANY" => Ok(ProxyProtocolVersion::ANY),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum OverflowPolicy {
+    REJECT, // Refuse connections over the cap straight away
+    QUEUE, // Leave them in the listen backlog until a slot frees up or "queue_timeout_ms" passes
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = ();
+    fn from_str(input: &str) -> Result<OverflowPolicy, Self::Err> {
+        match input {
+            "" | "REJECT" => Ok(OverflowPolicy::REJECT),
+            "QUEUE" => Ok(OverflowPolicy::QUEUE),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum FaultType {
+    LATENCY, // Delays the message by a duration drawn from "latency"
+    RESET, // Resets both sides of the connection
+    TRUNCATE, // Closes both sides of the connection cleanly
+    CORRUPT, // Overwrites "corrupt_bytes" random bytes of the message
+    STALL, // Stops forwarding for "stall_ms" then closes the connection
+    DROPCONNECT, // Closes the client connection instead of dialling the upstream
+}
+
+impl FromStr for FaultType {
+    type Err = ();
+    fn from_str(input: &str) -> Result<FaultType, Self::Err> {
+        match input {
+            "LATENCY" => Ok(FaultType::LATENCY),
+            "RESET" => Ok(FaultType::RESET),
+            "TRUNCATE" => Ok(FaultType::TRUNCATE),
+            "CORRUPT" => Ok(FaultType::CORRUPT),
+            "STALL" => Ok(FaultType::STALL),
+            "DROPCONNECT" => Ok(FaultType::DROPCONNECT),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum LatencyDistribution {
+    FIXED, // Always "ms"
+    UNIFORM, // Anywhere between "min_ms" and "max_ms"
+    NORMAL, // Mean of "ms" with "stddev_ms", kept within "min_ms" and "max_ms" when set
+}
+
+impl FromStr for LatencyDistribution {
+    type Err = ();
+    fn from_str(input: &str) -> Result<LatencyDistribution, Self::Err> {
+        match input {
+            "" | "FIXED" => Ok(LatencyDistribution::FIXED),
+            "UNIFORM" => Ok(LatencyDistribution::UNIFORM),
+            "NORMAL" => Ok(LatencyDistribution::NORMAL),
+            _ => Err(()),
         }
     }
 }
 
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum BodyComparison {
+    NONE,
+    HASH, // Bodies must be byte for byte identical
+    JSON, // Bodies are compared as JSON documents, skipping "ignore_fields"
+}
+
+impl FromStr for BodyComparison {
+    type Err = ();
+    fn from_str(input: &str) -> Result<BodyComparison, Self::Err> {
+        match input {
+            "NONE" => Ok(BodyComparison::NONE),
+            "" | "HASH" => Ok(BodyComparison::HASH),
+            "JSON" => Ok(BodyComparison::JSON),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum InterceptExpiry {
+    FORWARD, // Messages nobody decided on in time are forwarded unchanged
+    DROP, // The connection is closed instead
+}
+
+impl FromStr for InterceptExpiry {
+    type Err = ();
+    fn from_str(input: &str) -> Result<InterceptExpiry, Self::Err> {
+        match input {
+            "" | "FORWARD" => Ok(InterceptExpiry::FORWARD),
+            "DROP" => Ok(InterceptExpiry::DROP),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum HarBodies {
+    NONE,
+    TEXT, // Kept as text, bytes that are not UTF-8 are replaced
+    BASE64,
+}
+
+impl FromStr for HarBodies {
+    type Err = ();
+    fn from_str(input: &str) -> Result<HarBodies, Self::Err> {
+        match input {
+            "NONE" => Ok(HarBodies::NONE),
+            "" | "TEXT" => Ok(HarBodies::TEXT),
+            "BASE64" => Ok(HarBodies::BASE64),
+            _ => Err(()),
+        }
+    }
+}
 
 // ---- Programmatic Structs ----
 
 // ---- HeaderMapping ----
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct HeaderMapping {
-    key: String,
-    value: String,
+    pub key: String,
+    pub value: String,
+    pub mode: HeaderMatchMode,
+    pub all_values: bool, // Repeated headers must all match rather than any one of them
+    pub ignore_case: bool,
+    pub negate: bool,
 }
 
 impl HeaderMapping {
@@ -71,19 +481,165 @@ impl HeaderMapping {
         HeaderMapping {
             key,
             value,
+            mode: HeaderMatchMode::EXACT,
+            all_values: false,
+            ignore_case: false,
+            negate: false,
         }
     }
 }
 
+// ---- HeaderAction ----
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct HeaderAction {
+    pub action: HeaderActionType,
+    pub key: String,
+    pub value: String,
+    pub pattern: String,
+    pub replacement: String,
+}
+
+impl HeaderAction {
+    pub fn new(action: HeaderActionType, key: String) -> HeaderAction {
+        HeaderAction {
+            action,
+            key,
+            value: String::new(),
+            pattern: String::new(),
+            replacement: String::new(),
+        }
+    }
+}
+
+// ---- UrlAction ----
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct UrlAction {
+    pub action: UrlActionType,
+    pub pattern: String,
+    pub replacement: String,
+    pub status: u16,
+}
+
+impl UrlAction {
+    pub fn new(action: UrlActionType, pattern: String, replacement: String) -> UrlAction {
+        UrlAction {
+            action,
+            pattern,
+            replacement,
+            status: 302,
+        }
+    }
+}
+
+// ---- MockResponse ----
+
+// Answers a matched request from configuration instead of forwarding it
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>, // Inline or read from "body_file" when the bindings are loaded
+    pub template: bool, // ${...} placeholders in header values and the body are filled in from the request
+    pub delay_ms: u64,
+}
+
+impl MockResponse {
+    pub fn new() -> MockResponse {
+        MockResponse {
+            status: 200,
+            headers: vec![],
+            body: vec![],
+            template: false,
+            delay_ms: 0,
+        }
+    }
+}
+
+impl Default for MockResponse {
+    fn default() -> MockResponse {
+        MockResponse::new()
+    }
+}
+
+// Empty fields match anything, the path is percent-decoded and normalised before matching
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct UrlMatch {
+    pub host: String, // Glob, "*" matches a single label
+    pub path: String,
+    pub path_mode: PathMatchMode,
+    pub query: Vec<HeaderMapping>, // Keyed by query parameter name
+}
+
+impl UrlMatch {
+    pub fn new() -> UrlMatch {
+        UrlMatch {
+            host: String::new(),
+            path: String::new(),
+            path_mode: PathMatchMode::GLOB,
+            query: vec![],
+        }
+    }
+}
+
+impl Default for UrlMatch {
+    fn default() -> UrlMatch {
+        UrlMatch::new()
+    }
+}
+
+// Rates of 0 are unlimited, a burst of 0 defaults to one second's worth of the rate
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct RateLimitRule {
+    pub key: RateLimitKey,
+    pub key_header: String,
+    pub requests_per_second: u32,
+    pub request_burst: u32,
+    pub connections_per_second: u32,
+    pub connection_burst: u32,
+}
+
+impl RateLimitRule {
+    pub fn new() -> RateLimitRule {
+        RateLimitRule {
+            key: RateLimitKey::CLIENTIP,
+            key_header: String::new(),
+            requests_per_second: 0,
+            request_burst: 0,
+            connections_per_second: 0,
+            connection_burst: 0,
+        }
+    }
+}
+
+impl Default for RateLimitRule {
+    fn default() -> RateLimitRule {
+        RateLimitRule::new()
+    }
+}
+
 // ---- Rule ----
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct Rule {
     pub kind: RuleType,
     pub header_mappings: Vec<HeaderMapping>,
     pub url_wildcard: String,
-    pub method_enum: HttpMethod,
-    pub version_float: String,
+    pub url_match: UrlMatch,
+    pub method_enum: Option<HttpMethod>,
+    pub allowed_methods: Vec<HttpMethod>,
+    pub denied_methods: Vec<HttpMethod>,
+    pub version_float: Option<HttpVersion>,
+    pub min_version: Option<HttpVersion>,
+    pub max_version: Option<HttpVersion>,
+    pub versions: Vec<HttpVersion>,
+    pub version_action: VersionAction,
+    pub rate_limit: RateLimitRule,
+    pub header_actions: Vec<HeaderAction>,
+    pub url_actions: Vec<UrlAction>,
+    pub mock: Option<MockResponse>,
+    pub script: String, // Source, compiled once per binding by the enforcer
 }
 
 impl Rule {
@@ -92,8 +648,20 @@ impl Rule {
             kind,
             header_mappings: vec![],
             url_wildcard: String::from("."),
-            method_enum: HttpMethod::GET,
-            version_float: String::from("1.1"),
+            url_match: UrlMatch::new(),
+            method_enum: None,
+            allowed_methods: vec![],
+            denied_methods: vec![],
+            version_float: None,
+            min_version: None,
+            max_version: None,
+            versions: vec![],
+            version_action: VersionAction::REJECT,
+            rate_limit: RateLimitRule::new(),
+            header_actions: vec![],
+            url_actions: vec![],
+            mock: None,
+            script: String::new(),
         }
     }
     pub fn add_header_mapping(&mut self, mapping: HeaderMapping) {
@@ -106,16 +674,25 @@ impl Rule {
         self.url_wildcard = wildcard;
     }
     pub fn set_method_enum(&mut self, method: HttpMethod) {
-        self.method_enum = method;
+        self.method_enum = Some(method);
+    }
+    pub fn st_version_float(&mut self, version: HttpVersion) {
+        self.version_float = Some(version);
     }
-    pub fn st_version_float(&mut self, version: String) {
-        self.version_float = version;
+    pub fn add_header_action(&mut self, action: HeaderAction) {
+        self.header_actions.push(action);
+    }
+    pub fn add_url_action(&mut self, action: UrlAction) {
+        self.url_actions.push(action);
+    }
+    pub fn set_mock(&mut self, mock: MockResponse) {
+        self.mock = Some(mock);
     }
 }
 
 // ---- RuleSet ----
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct RuleSet {
     pub egress: Vec<Rule>,
     pub ingress: Vec<Rule>,
@@ -136,13 +713,332 @@ impl RuleSet {
     }
 }
 
+// ---- ForwardingRule ----
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct ForwardingRule {
+    pub x_forwarded_for: ForwardingMode,
+    pub x_forwarded_proto: ForwardingMode,
+    pub x_forwarded_host: ForwardingMode,
+    pub forwarded: ForwardingMode,
+    pub via: ForwardingMode,
+    pub via_pseudonym: String,
+    pub trusted_proxies: Vec<String>,
+}
+
+impl ForwardingRule {
+    pub fn new() -> ForwardingRule {
+        ForwardingRule {
+            x_forwarded_for: ForwardingMode::NONE,
+            x_forwarded_proto: ForwardingMode::NONE,
+            x_forwarded_host: ForwardingMode::NONE,
+            forwarded: ForwardingMode::NONE,
+            via: ForwardingMode::NONE,
+            via_pseudonym: String::from("tcp_proxy"),
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+impl Default for ForwardingRule {
+    fn default() -> ForwardingRule {
+        ForwardingRule::new()
+    }
+}
+
+// ---- ProxyProtocolRule ----
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct ProxyProtocolRule {
+    pub accept: ProxyProtocolVersion, // Header expected from clients, e.g. a load balancer in front
+    pub send: ProxyProtocolVersion, // Header written to the upstream after connecting
+}
+
+impl ProxyProtocolRule {
+    pub fn new() -> ProxyProtocolRule {
+        ProxyProtocolRule {
+            accept: ProxyProtocolVersion::NONE,
+            send: ProxyProtocolVersion::NONE,
+        }
+    }
+}
+
+impl Default for ProxyProtocolRule {
+    fn default() -> ProxyProtocolRule {
+        ProxyProtocolRule::new()
+    }
+}
+
+// ---- ConnectionLimits ----
+
+// Caps of 0 are unlimited, a queue timeout of 0 waits indefinitely
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct ConnectionLimits {
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    pub overflow: OverflowPolicy,
+    pub queue_timeout_ms: u64,
+}
+
+impl ConnectionLimits {
+    pub fn new() -> ConnectionLimits {
+        ConnectionLimits {
+            max_connections: 0,
+            max_connections_per_ip: 0,
+            overflow: OverflowPolicy::REJECT,
+            queue_timeout_ms: 0,
+        }
+    }
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> ConnectionLimits {
+        ConnectionLimits::new()
+    }
+}
+
+// ---- BandwidthRule ----
+
+// Rates are bytes per second, 0 is unlimited, a burst of 0 defaults to a tenth of a second at the rate
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct BandwidthRule {
+    pub egress_bytes_per_second: u64, // Shared by every connection on the binding
+    pub ingress_bytes_per_second: u64,
+    pub connection_egress_bytes_per_second: u64, // Applied to each connection separately
+    pub connection_ingress_bytes_per_second: u64,
+    pub burst_bytes: u64,
+}
+
+impl BandwidthRule {
+    pub fn new() -> BandwidthRule {
+        BandwidthRule {
+            egress_bytes_per_second: 0,
+            ingress_bytes_per_second: 0,
+            connection_egress_bytes_per_second: 0,
+            connection_ingress_bytes_per_second: 0,
+            burst_bytes: 0,
+        }
+    }
+}
+
+impl Default for BandwidthRule {
+    fn default() -> BandwidthRule {
+        BandwidthRule::new()
+    }
+}
+
+// ---- FaultRule ----
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct LatencyRule {
+    pub distribution: LatencyDistribution,
+    pub ms: u64,
+    pub min_ms: u64,
+    pub max_ms: u64, // 0 leaves NORMAL delays unbounded above
+    pub stddev_ms: u64,
+}
+
+impl LatencyRule {
+    pub fn new() -> LatencyRule {
+        LatencyRule {
+            distribution: LatencyDistribution::FIXED,
+            ms: 0,
+            min_ms: 0,
+            max_ms: 0,
+            stddev_ms: 0,
+        }
+    }
+}
+
+impl Default for LatencyRule {
+    fn default() -> LatencyRule {
+        LatencyRule::new()
+    }
+}
+
+// Rolled once per message on HTTP bindings and once per direction of a connection on TCP bindings
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct FaultRule {
+    pub kind: FaultType,
+    pub direction: TrafficDirection,
+    pub probability: u32, // In millionths, so the rule stays hashable
+    pub after_bytes: u64, // How far into the message the fault strikes
+    pub latency: LatencyRule,
+    pub corrupt_bytes: u32,
+    pub stall_ms: u64,
+    pub scope: Option<Rule>, // Only messages matching the rule are affected
+}
+
+impl FaultRule {
+    pub fn new(kind: FaultType) -> FaultRule {
+        FaultRule {
+            kind,
+            direction: TrafficDirection::INGRESS,
+            probability: 1_000_000,
+            after_bytes: 0,
+            latency: LatencyRule::new(),
+            corrupt_bytes: 1,
+            stall_ms: 30_000,
+            scope: None,
+        }
+    }
+}
+
+// ---- MirrorRule ----
+
+// Status codes are always compared
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct CompareRule {
+    pub headers: Vec<String>,
+    pub body: BodyComparison,
+    pub ignore_fields: Vec<String>, // JSON pointers such as "/data/updated_at", or field names ignored at any depth
+    pub max_body_bytes: usize, // Larger JSON bodies are compared by hash instead
+}
+
+impl CompareRule {
+    pub fn new() -> CompareRule {
+        CompareRule {
+            headers: vec![],
+            body: BodyComparison::HASH,
+            ignore_fields: vec![],
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+impl Default for CompareRule {
+    fn default() -> CompareRule {
+        CompareRule::new()
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct MirrorRule {
+    pub to: String, // Shadow upstream, empty when the binding is not mirrored
+    pub scope: Option<Rule>, // Only requests matching the rule are mirrored
+    pub compare: Option<CompareRule>, // Shadow responses are discarded unless set
+}
+
+impl MirrorRule {
+    pub fn new() -> MirrorRule {
+        MirrorRule {
+            to: String::new(),
+            scope: None,
+            compare: None,
+        }
+    }
+}
+
+impl Default for MirrorRule {
+    fn default() -> MirrorRule {
+        MirrorRule::new()
+    }
+}
+
+// ---- HarRule ----
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct HarRule {
+    pub directory: String, // HAR files are written here, empty keeps exchanges in memory only
+    pub max_file_bytes: u64, // A new file is started once the current one would grow past this
+    pub max_files: usize, // Oldest files are deleted beyond this, 0 keeps every file
+    pub buffer: usize, // Latest exchanges kept in memory for the admin endpoint
+    pub bodies: HarBodies,
+    pub max_body_bytes: usize, // Longer bodies are cut short
+}
+
+impl HarRule {
+    pub fn new() -> HarRule {
+        HarRule {
+            directory: String::new(),
+            max_file_bytes: 16 * 1024 * 1024,
+            max_files: 10,
+            buffer: 100,
+            bodies: HarBodies::TEXT,
+            max_body_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl Default for HarRule {
+    fn default() -> HarRule {
+        HarRule::new()
+    }
+}
+
+// ---- InterceptRule ----
+
+// Messages are held as the upstream or client sent them, before the binding's rules
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct InterceptRule {
+    pub requests: Option<Rule>, // Requests matching the rule are held
+    pub responses: Option<Rule>, // Responses matching the rule are held, URL and METHOD rules see the request they answer
+    pub timeout_ms: u64,
+    pub on_timeout: InterceptExpiry,
+    pub max_held: usize, // Messages held at once on the binding, further ones get "on_timeout" straight away
+}
+
+impl InterceptRule {
+    pub fn new() -> InterceptRule {
+        InterceptRule {
+            requests: None,
+            responses: None,
+            timeout_ms: 5 * 60 * 1000,
+            on_timeout: InterceptExpiry::FORWARD,
+            max_held: 100,
+        }
+    }
+}
+
+impl Default for InterceptRule {
+    fn default() -> InterceptRule {
+        InterceptRule::new()
+    }
+}
+
+// ---- FilterRule ----
+
+// A WebAssembly module run as a filter, the limits apply to every hook call on their own
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct WasmFilterRule {
+    pub module: String, // Path to the .wasm file, read again whenever the bindings are reloaded
+    pub max_memory_bytes: usize,
+    pub max_fuel: u64, // Roughly one unit per instruction executed
+}
+
+impl WasmFilterRule {
+    pub fn new(module: String) -> WasmFilterRule {
+        WasmFilterRule {
+            module,
+            max_memory_bytes: 16 * 1024 * 1024,
+            max_fuel: 10_000_000,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum FilterRule {
+    NAMED(String), // Registered with the proxy under this name
+    WASM(WasmFilterRule),
+}
+
 // ---- BindingRule ----
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct BindingRule {
     pub name: String,
     pub from: String,
-    pub to: String,
+    pub to: String, // Empty when every request is answered by the binding's rules
+    pub protocol: BindingProtocol,
+    pub forwarding: ForwardingRule,
+    pub proxy_protocol: ProxyProtocolRule,
+    pub connection_limits: ConnectionLimits,
+    pub bandwidth: BandwidthRule,
+    pub faults: Vec<FaultRule>,
+    pub mirror: MirrorRule,
+    pub har: Option<HarRule>, // Exchanges are only archived when set
+    pub intercept: Option<InterceptRule>,
+    pub filters: Vec<FilterRule>, // In the order requests pass through them
     pub rules: RuleSet
 }
 
@@ -152,9 +1048,52 @@ impl BindingRule {
             name,
             from,
             to,
+            protocol: BindingProtocol::TCP,
+            forwarding: ForwardingRule::new(),
+            proxy_protocol: ProxyProtocolRule::new(),
+            connection_limits: ConnectionLimits::new(),
+            bandwidth: BandwidthRule::new(),
+            faults: vec![],
+            mirror: MirrorRule::new(),
+            har: None,
+            intercept: None,
+            filters: vec![],
             rules,
         }
     }
+    pub fn set_protocol(&mut self, protocol: BindingProtocol) {
+        self.protocol = protocol;
+    }
+    pub fn set_forwarding(&mut self, forwarding: ForwardingRule) {
+        self.forwarding = forwarding;
+    }
+    pub fn set_proxy_protocol(&mut self, proxy_protocol: ProxyProtocolRule) {
+        self.proxy_protocol = proxy_protocol;
+    }
+    pub fn set_connection_limits(&mut self, connection_limits: ConnectionLimits) {
+        self.connection_limits = connection_limits;
+    }
+    pub fn set_bandwidth(&mut self, bandwidth: BandwidthRule) {
+        self.bandwidth = bandwidth;
+    }
+    pub fn add_fault(&mut self, fault: FaultRule) {
+        self.faults.push(fault);
+    }
+    pub fn set_mirror(&mut self, mirror: MirrorRule) {
+        self.mirror = mirror;
+    }
+    pub fn set_har(&mut self, har: HarRule) {
+        self.har = Some(har);
+    }
+    pub fn set_intercept(&mut self, intercept: InterceptRule) {
+        self.intercept = Some(intercept);
+    }
+    pub fn add_filter(&mut self, name: String) {
+        self.filters.push(FilterRule::NAMED(name));
+    }
+    pub fn add_wasm_filter(&mut self, wasm: WasmFilterRule) {
+        self.filters.push(FilterRule::WASM(wasm));
+    }
 }
 
 // ---- BindingSet ----
@@ -184,13 +1123,29 @@ impl BindingSet {
         }
         return rules_present;
     }
-    pub fn from_file(id: String, filename: String) -> BindingSet {
-        let data: String = fs::read_to_string(filename).expect("Unable to read file");
-        let parsed: JSONBindingSet = serde_json::from_str(data.as_str()).unwrap();
-        BindingSet {
+    pub fn from_file(id: String, filename: String) -> io::Result<BindingSet> {
+        let data: String = fs::read_to_string(filename.as_str()).map_err(|e: io::Error| io::Error::new(
+            e.kind(),
+            format!("Unable to read bindings from [{}]: {}", filename, e)
+        ))?;
+        BindingSet::from_json(id, data.as_str()).map_err(|e: io::Error| io::Error::new(
+            e.kind(),
+            format!("Unable to read bindings from [{}]: {}", filename, e)
+        ))
+    }
+    // Same layout as traffic.json
+    pub fn from_json(id: String, data: &str) -> io::Result<BindingSet> {
+        let parsed: JSONBindingSet = serde_json::from_str(data).map_err(|e: serde_json::Error| invalid(format!("Invalid bindings JSON: {}", e)))?;
+        Ok(BindingSet {
             id,
             applied: false,
-            bindings: assemble_bindings_from_json(parsed),
+            bindings: assemble_bindings_from_json(parsed)?,
+        })
+    }
+    pub fn builder(id: &str) -> BindingSetBuilder {
+        BindingSetBuilder {
+            binding_set: BindingSet::new(String::from(id)),
+            error: None,
         }
     }
     pub fn set_applied(&mut self, new_applied_setting: bool) {
@@ -198,33 +1153,496 @@ impl BindingSet {
     }
 }
 
-fn assemble_rules_from_json(json_val: JSONRule) -> Rule {
+// ---- BindingSetBuilder ----
+
+// Collects bindings built in code, inline JSON and traffic.json files into one set.
+// The first source that cannot be read is kept and returned by build
+pub struct BindingSetBuilder {
+    binding_set: BindingSet,
+    error: Option<io::Error>,
+}
+
+impl BindingSetBuilder {
+    pub fn binding(mut self, rule: BindingRule) -> BindingSetBuilder {
+        self.binding_set.add_rule(rule);
+        self
+    }
+    pub fn json(self, data: &str) -> BindingSetBuilder {
+        let parsed: io::Result<BindingSet> = BindingSet::from_json(self.binding_set.id.clone(), data);
+        self.extend(parsed)
+    }
+    pub fn file(self, filename: &str) -> BindingSetBuilder {
+        let parsed: io::Result<BindingSet> = BindingSet::from_file(self.binding_set.id.clone(), String::from(filename));
+        self.extend(parsed)
+    }
+    pub fn build(self) -> io::Result<BindingSet> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        check_unique_names(&self.binding_set.bindings)?;
+        Ok(self.binding_set)
+    }
+    fn extend(mut self, parsed: io::Result<BindingSet>) -> BindingSetBuilder {
+        match parsed {
+            Ok(parsed) => self.binding_set.bindings.extend(parsed.bindings),
+            Err(e) => { self.error.get_or_insert(e); },
+        }
+        self
+    }
+}
+
+// Bindings that cannot be assembled fail the load with the reason, so a bad file never takes the caller down
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Bindings are reloaded, listed and addressed by name, so a name used twice would leave one of them unreachable
+pub(crate) fn check_unique_names<'a, I: IntoIterator<Item = &'a BindingRule>>(bindings: I) -> io::Result<()> {
+    let mut names: HashSet<&str> = HashSet::new();
+    for binding in bindings {
+        if !names.insert(binding.name.as_str()) {
+            return Err(invalid(format!("Binding name [{}] is used more than once", binding.name)));
+        }
+    }
+    Ok(())
+}
+
+fn assemble_rules_from_json(json_val: JSONRule) -> io::Result<Rule> {
     let mut rule: Rule = Rule::new(RuleType::from_str(json_val.kind.as_str()).unwrap());
     for mapping in json_val.header_mappings {
-        rule.add_header_mapping(HeaderMapping::new(mapping.key, mapping.value));
+        rule.add_header_mapping(assemble_header_mapping_from_json(mapping)?);
     }
     rule.url_wildcard = json_val.url_wildcard;
-    rule.method_enum = HttpMethod::from_str(json_val.method_enum.as_str()).unwrap();
-    rule.version_float = json_val.version_float.to_string();
-    rule
+    rule.url_match = assemble_url_match_from_json(json_val.url_match)?;
+    if !json_val.method_enum.is_empty() {
+        rule.set_method_enum(assemble_method(json_val.method_enum.as_str())?);
+    }
+    rule.allowed_methods = json_val.allowed_methods.iter().map(|method: &String| assemble_method(method.as_str())).collect::<io::Result<Vec<HttpMethod>>>()?;
+    rule.denied_methods = json_val.denied_methods.iter().map(|method: &String| assemble_method(method.as_str())).collect::<io::Result<Vec<HttpMethod>>>()?;
+    rule.version_float = assemble_optional_version(json_val.version_float)?;
+    rule.min_version = assemble_optional_version(json_val.min_version)?;
+    rule.max_version = assemble_optional_version(json_val.max_version)?;
+    rule.versions = json_val.versions.iter().map(|version: &f32| HttpVersion::from_float(*version)).collect::<io::Result<Vec<HttpVersion>>>()?;
+    let version_action: &str = json_val.version_action.as_str();
+    rule.version_action = VersionAction::from_str(version_action)
+        .map_err(|_| invalid(format!("Unknown version action: {}", version_action)))?;
+    rule.rate_limit = assemble_rate_limit_from_json(json_val.rate_limit)?;
+    for json_action in json_val.header_actions {
+        rule.add_header_action(assemble_header_action_from_json(json_action)?);
+    }
+    for json_action in json_val.url_actions {
+        let url_action: UrlAction = assemble_url_action_from_json(&rule, json_action)?;
+        rule.add_url_action(url_action);
+    }
+    if let Some(json_mock) = json_val.mock {
+        rule.set_mock(assemble_mock_from_json(json_mock)?);
+    }
+    if rule.kind == RuleType::SCRIPT {
+        rule.script = assemble_script_from_json(json_val.script, json_val.script_file)?;
+    }
+    Ok(rule)
+}
+
+fn assemble_script_from_json(script: String, script_file: String) -> io::Result<String> {
+    let source: String = match (script.is_empty(), script_file.is_empty()) {
+        (false, true) => script,
+        (true, false) => fs::read_to_string(script_file.as_str())
+            .map_err(|_| invalid(format!("Unable to read script_file {}", script_file)))?,
+        (true, true) => return Err(invalid(String::from("SCRIPT rules need a script or a script_file"))),
+        (false, false) => return Err(invalid(String::from("SCRIPT rules take a script or a script_file, not both"))),
+    };
+    // Compiled here as well so a broken script fails the load rather than the binding's first request
+    if let Err(e) = RequestScript::compile(source.as_str(), &Logger::root(Discard, o!())) {
+        return Err(invalid(format!("Invalid script: {}", e)));
+    }
+    Ok(source)
+}
+
+fn assemble_mock_from_json(json_val: JSONMock) -> io::Result<MockResponse> {
+    let mut mock: MockResponse = MockResponse::new();
+    if json_val.status != 0 {
+        mock.status = json_val.status;
+    }
+    if mock.status < 200 || mock.status > 599 {
+        return Err(invalid(format!("Mock status must be between 200 and 599, got {}", mock.status)));
+    }
+    mock.headers = json_val.headers.into_iter().collect();
+    for (name, value) in mock.headers.iter() {
+        check_header_field(name.as_str(), value.as_str(), "Mock header")?;
+    }
+    mock.body = match (json_val.body.is_empty(), json_val.body_file.is_empty()) {
+        (_, true) => json_val.body.into_bytes(),
+        (true, false) => {
+            let body_file: &str = json_val.body_file.as_str();
+            fs::read(body_file).map_err(|_| invalid(format!("Unable to read mock body_file {}", body_file)))?
+        },
+        (false, false) => return Err(invalid(String::from("Mock responses take a body or a body_file, not both"))),
+    };
+    mock.template = json_val.template;
+    mock.delay_ms = json_val.delay_ms;
+    Ok(mock)
+}
+
+fn assemble_url_action_from_json(rule: &Rule, json_val: JSONUrlAction) -> io::Result<UrlAction> {
+    let action_type: UrlActionType = UrlActionType::from_str(json_val.action.as_str())
+        .map_err(|_| invalid(format!("Unknown URL action type: {}", json_val.action)))?;
+    // Actions without their own pattern reuse the captures of the rule's url_wildcard, an empty one would match every target
+    let pattern: String = if json_val.pattern.is_empty() { rule.url_wildcard.clone() } else { json_val.pattern };
+    if pattern.is_empty() {
+        return Err(invalid(String::from("URL actions need a pattern when their rule has no url_wildcard")));
+    }
+    let mut action: UrlAction = UrlAction::new(action_type, pattern, json_val.replacement);
+    if json_val.status != 0 {
+        action.status = json_val.status;
+    }
+    if action_type == UrlActionType::REDIRECT && ![301, 302, 303, 307, 308].contains(&action.status) {
+        return Err(invalid(format!("URL redirect status must be one of 301, 302, 303, 307 or 308, got {}", action.status)));
+    }
+    Ok(action)
+}
+
+fn assemble_rate_limit_from_json(json_val: JSONRateLimit) -> io::Result<RateLimitRule> {
+    let mut rate_limit: RateLimitRule = RateLimitRule::new();
+    rate_limit.key = RateLimitKey::from_str(json_val.key.as_str())
+        .map_err(|_| invalid(format!("Unknown rate limit key: {}", json_val.key)))?;
+    if rate_limit.key == RateLimitKey::HEADER && json_val.key_header.is_empty() {
+        return Err(invalid(String::from("Rate limits keyed by HEADER require a key_header")));
+    }
+    // Headers are not known until the first request, long after the connection was accepted
+    if rate_limit.key == RateLimitKey::HEADER && json_val.connections_per_second != 0 {
+        return Err(invalid(String::from("Connection rate limits cannot be keyed by HEADER")));
+    }
+    rate_limit.key_header = json_val.key_header;
+    rate_limit.requests_per_second = json_val.requests_per_second;
+    rate_limit.request_burst = json_val.request_burst;
+    rate_limit.connections_per_second = json_val.connections_per_second;
+    rate_limit.connection_burst = json_val.connection_burst;
+    Ok(rate_limit)
+}
+
+fn assemble_method(method: &str) -> io::Result<HttpMethod> {
+    HttpMethod::from_str(method).map_err(|_| invalid(format!("Invalid HTTP method: [{}]", method)))
+}
+
+// 0 leaves the version unset, anything else has to be a version
+fn assemble_optional_version(value: f32) -> io::Result<Option<HttpVersion>> {
+    match value == 0.0 {
+        true => Ok(None),
+        false => HttpVersion::from_float(value).map(Some),
+    }
+}
+
+fn assemble_url_match_from_json(json_val: JSONUrlMatch) -> io::Result<UrlMatch> {
+    let mut url_match: UrlMatch = UrlMatch::new();
+    url_match.host = json_val.host;
+    url_match.path = json_val.path;
+    let path_mode: &str = json_val.path_mode.as_str();
+    url_match.path_mode = PathMatchMode::from_str(path_mode)
+        .map_err(|_| invalid(format!("Unknown path match mode: {}", path_mode)))?;
+    for mapping in json_val.query {
+        url_match.query.push(assemble_header_mapping_from_json(mapping)?);
+    }
+    Ok(url_match)
+}
+
+fn assemble_header_mapping_from_json(json_val: JSONHeaderMapping) -> io::Result<HeaderMapping> {
+    let mode: HeaderMatchMode = HeaderMatchMode::from_str(json_val.mode.as_str())
+        .map_err(|_| invalid(format!("Unknown header match mode [{}] for header [{}]", json_val.mode, json_val.key)))?;
+    if [HeaderMatchMode::LT, HeaderMatchMode::LTE, HeaderMatchMode::GT, HeaderMatchMode::GTE].contains(&mode)
+        && json_val.value.trim().parse::<f64>().is_err() {
+        return Err(invalid(format!("Header [{}] uses a numeric match mode but value [{}] is not a number", json_val.key, json_val.value)));
+    }
+    let mut mapping: HeaderMapping = HeaderMapping::new(json_val.key, json_val.value);
+    mapping.mode = mode;
+    mapping.all_values = json_val.all_values;
+    mapping.ignore_case = json_val.ignore_case;
+    mapping.negate = json_val.negate;
+    Ok(mapping)
+}
+
+fn assemble_header_action_from_json(json_val: JSONHeaderAction) -> io::Result<HeaderAction> {
+    let action_type: HeaderActionType = HeaderActionType::from_str(json_val.action.as_str())
+        .map_err(|_| invalid(format!("Unknown header action type: {}", json_val.action)))?;
+    check_header_field(json_val.key.as_str(), json_val.value.as_str(), "Header action")?;
+    check_header_field(json_val.key.as_str(), json_val.replacement.as_str(), "Header action")?;
+    let mut action: HeaderAction = HeaderAction::new(action_type, json_val.key);
+    action.value = json_val.value;
+    action.pattern = json_val.pattern;
+    action.replacement = json_val.replacement;
+    Ok(action)
+}
+
+fn check_header_field(name: &str, value: &str, context: &str) -> io::Result<()> {
+    if !message::valid_header_name(name) {
+        return Err(invalid(format!("{} name is not a valid header name: {:?}", context, name)));
+    }
+    if value.contains(['\r', '\n']) {
+        return Err(invalid(format!("{} value for {} contains a line break", context, name)));
+    }
+    Ok(())
+}
+
+fn assemble_forwarding_mode(binding_name: &str, header: &str, mode: &str) -> io::Result<ForwardingMode> {
+    ForwardingMode::from_str(mode)
+        .map_err(|_| invalid(format!("Unknown forwarding mode [{}] for {} on binding [{}]", mode, header, binding_name)))
 }
 
-fn assemble_bindings_from_json(json_val: JSONBindingSet) -> HashSet<BindingRule> {
-    let mut binding_rule_set: HashSet<BindingRule> = HashSet::new();
+fn assemble_forwarding_from_json(binding_name: &str, json_val: JSONForwarding) -> io::Result<ForwardingRule> {
+    let mut forwarding: ForwardingRule = ForwardingRule::new();
+    forwarding.x_forwarded_for = assemble_forwarding_mode(binding_name, "x_forwarded_for", json_val.x_forwarded_for.as_str())?;
+    forwarding.x_forwarded_proto = assemble_forwarding_mode(binding_name, "x_forwarded_proto", json_val.x_forwarded_proto.as_str())?;
+    forwarding.x_forwarded_host = assemble_forwarding_mode(binding_name, "x_forwarded_host", json_val.x_forwarded_host.as_str())?;
+    forwarding.forwarded = assemble_forwarding_mode(binding_name, "forwarded", json_val.forwarded.as_str())?;
+    forwarding.via = assemble_forwarding_mode(binding_name, "via", json_val.via.as_str())?;
+    if !json_val.via_pseudonym.is_empty() {
+        forwarding.via_pseudonym = json_val.via_pseudonym;
+    }
+    forwarding.trusted_proxies = json_val.trusted_proxies;
+    Ok(forwarding)
+}
+
+fn assemble_proxy_protocol_from_json(binding_name: &str, json_val: JSONProxyProtocol) -> io::Result<ProxyProtocolRule> {
+    let mut proxy_protocol: ProxyProtocolRule = ProxyProtocolRule::new();
+    proxy_protocol.accept = ProxyProtocolVersion::from_str(json_val.accept.as_str())
+        .map_err(|_| invalid(format!("Unknown PROXY protocol version [{}] to accept on binding [{}]", json_val.accept, binding_name)))?;
+    proxy_protocol.send = match ProxyProtocolVersion::from_str(json_val.send.as_str()) {
+        Ok(ProxyProtocolVersion::ANY) | Err(_) => return Err(invalid(format!("Unknown PROXY protocol version [{}] to send on binding [{}]", json_val.send, binding_name))),
+        Ok(version) => version,
+    };
+    Ok(proxy_protocol)
+}
+
+fn assemble_connection_limits_from_json(binding_name: &str, json_val: JSONConnectionLimits) -> io::Result<ConnectionLimits> {
+    let mut connection_limits: ConnectionLimits = ConnectionLimits::new();
+    connection_limits.max_connections = json_val.max_connections;
+    connection_limits.max_connections_per_ip = json_val.max_connections_per_ip;
+    connection_limits.overflow = OverflowPolicy::from_str(json_val.overflow.as_str())
+        .map_err(|_| invalid(format!("Unknown connection overflow policy [{}] on binding [{}]", json_val.overflow, binding_name)))?;
+    connection_limits.queue_timeout_ms = json_val.queue_timeout_ms;
+    // Queued connections sit in the listen backlog, a wait without an end would leave the binding unable to shut down
+    if connection_limits.overflow == OverflowPolicy::QUEUE && connection_limits.queue_timeout_ms == 0 {
+        return Err(invalid(format!("QUEUE overflow needs a positive queue_timeout_ms on binding [{}]", binding_name)));
+    }
+    // Per IP caps are only checked once a connection is accepted, there is no backlog left to queue it in
+    if connection_limits.overflow == OverflowPolicy::QUEUE && connection_limits.max_connections_per_ip != 0 {
+        return Err(invalid(format!("QUEUE overflow cannot be used with max_connections_per_ip on binding [{}]", binding_name)));
+    }
+    Ok(connection_limits)
+}
+
+fn assemble_bandwidth_from_json(json_val: JSONBandwidth) -> BandwidthRule {
+    let mut bandwidth: BandwidthRule = BandwidthRule::new();
+    bandwidth.egress_bytes_per_second = json_val.egress_bytes_per_second;
+    bandwidth.ingress_bytes_per_second = json_val.ingress_bytes_per_second;
+    bandwidth.connection_egress_bytes_per_second = json_val.connection_egress_bytes_per_second;
+    bandwidth.connection_ingress_bytes_per_second = json_val.connection_ingress_bytes_per_second;
+    bandwidth.burst_bytes = json_val.burst_bytes;
+    bandwidth
+}
+
+fn assemble_fault_from_json(binding_name: &str, protocol: BindingProtocol, json_val: JSONFault) -> io::Result<FaultRule> {
+    let kind: FaultType = FaultType::from_str(json_val.kind.as_str())
+        .map_err(|_| invalid(format!("Unknown fault type [{}] on binding [{}]", json_val.kind, binding_name)))?;
+    let mut fault: FaultRule = FaultRule::new(kind);
+    if !json_val.direction.is_empty() {
+        fault.direction = TrafficDirection::from_str(json_val.direction.as_str())
+            .map_err(|_| invalid(format!("Unknown fault direction [{}] on binding [{}]", json_val.direction, binding_name)))?;
+    }
+    if let Some(probability) = json_val.probability {
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(invalid(format!("Fault probability must be between 0 and 1 on binding [{}], got {}", binding_name, probability)));
+        }
+        fault.probability = (probability * 1_000_000.0).round() as u32;
+    }
+    fault.after_bytes = json_val.after_bytes;
+    fault.latency.distribution = LatencyDistribution::from_str(json_val.latency.distribution.as_str())
+        .map_err(|_| invalid(format!("Unknown latency distribution [{}] on binding [{}]", json_val.latency.distribution, binding_name)))?;
+    fault.latency.ms = json_val.latency.ms;
+    fault.latency.min_ms = json_val.latency.min_ms;
+    fault.latency.max_ms = json_val.latency.max_ms;
+    fault.latency.stddev_ms = json_val.latency.stddev_ms;
+    if json_val.corrupt_bytes != 0 {
+        fault.corrupt_bytes = json_val.corrupt_bytes;
+    }
+    if json_val.stall_ms != 0 {
+        fault.stall_ms = json_val.stall_ms;
+    }
+    if let Some(scope) = json_val.scope {
+        // Scopes are matched against parsed messages, which connection level faults and TCP bindings never see
+        if kind == FaultType::DROPCONNECT || protocol != BindingProtocol::HTTP {
+            return Err(invalid(format!("Only faults other than DROPCONNECT on HTTP bindings can be scoped, binding [{}]", binding_name)));
+        }
+        let scope: Rule = assemble_rules_from_json(scope)?;
+        if scope.kind == RuleType::RATELIMIT || scope.kind == RuleType::SCRIPT {
+            return Err(invalid(format!("Faults cannot be scoped by a {:?} rule on binding [{}]", scope.kind, binding_name)));
+        }
+        fault.scope = Some(scope);
+    }
+    Ok(fault)
+}
+
+fn assemble_mirror_from_json(binding_name: &str, protocol: BindingProtocol, json_val: JSONMirror) -> io::Result<MirrorRule> {
+    let mut mirror: MirrorRule = MirrorRule::new();
+    mirror.to = json_val.to;
+    if let Some(scope) = json_val.scope {
+        if protocol != BindingProtocol::HTTP || mirror.to.is_empty() {
+            return Err(invalid(format!("Only mirrors with a target on HTTP bindings can be scoped, binding [{}]", binding_name)));
+        }
+        let scope: Rule = assemble_rules_from_json(scope)?;
+        if scope.kind == RuleType::RATELIMIT || scope.kind == RuleType::SCRIPT {
+            return Err(invalid(format!("Mirrors cannot be scoped by a {:?} rule on binding [{}]", scope.kind, binding_name)));
+        }
+        mirror.scope = Some(scope);
+    }
+    if let Some(json_compare) = json_val.compare {
+        if protocol != BindingProtocol::HTTP || mirror.to.is_empty() {
+            return Err(invalid(format!("Only mirrors with a target on HTTP bindings can compare responses, binding [{}]", binding_name)));
+        }
+        let mut compare: CompareRule = CompareRule::new();
+        compare.headers = json_compare.headers;
+        let body: &str = json_compare.body.as_str();
+        compare.body = BodyComparison::from_str(body)
+            .map_err(|_| invalid(format!("Unknown body comparison [{}] on binding [{}]", body, binding_name)))?;
+        compare.ignore_fields = json_compare.ignore_fields;
+        if json_compare.max_body_bytes != 0 {
+            compare.max_body_bytes = json_compare.max_body_bytes;
+        }
+        mirror.compare = Some(compare);
+    }
+    Ok(mirror)
+}
+
+fn assemble_intercept_from_json(binding_name: &str, protocol: BindingProtocol, json_val: JSONIntercept) -> io::Result<InterceptRule> {
+    if protocol != BindingProtocol::HTTP {
+        return Err(invalid(format!("Only HTTP bindings can intercept messages, binding [{}]", binding_name)));
+    }
+    let assemble_match = |json_rule: JSONRule| -> io::Result<Rule> {
+        let rule: Rule = assemble_rules_from_json(json_rule)?;
+        if rule.kind == RuleType::RATELIMIT || rule.kind == RuleType::SCRIPT {
+            return Err(invalid(format!("Intercepts cannot match with a {:?} rule on binding [{}]", rule.kind, binding_name)));
+        }
+        Ok(rule)
+    };
+    let mut intercept: InterceptRule = InterceptRule::new();
+    intercept.requests = json_val.requests.map(assemble_match).transpose()?;
+    intercept.responses = json_val.responses.map(assemble_match).transpose()?;
+    if intercept.requests.is_none() && intercept.responses.is_none() {
+        return Err(invalid(format!("Intercept on binding [{}] needs a requests or responses rule", binding_name)));
+    }
+    if json_val.timeout_ms != 0 {
+        intercept.timeout_ms = json_val.timeout_ms;
+    }
+    if json_val.max_held != 0 {
+        intercept.max_held = json_val.max_held;
+    }
+    let on_timeout: &str = json_val.on_timeout.as_str();
+    intercept.on_timeout = InterceptExpiry::from_str(on_timeout)
+        .map_err(|_| invalid(format!("Unknown intercept on_timeout [{}] on binding [{}]", on_timeout, binding_name)))?;
+    Ok(intercept)
+}
+
+fn assemble_har_from_json(binding_name: &str, protocol: BindingProtocol, json_val: JSONHar) -> io::Result<HarRule> {
+    if protocol != BindingProtocol::HTTP {
+        return Err(invalid(format!("Only HTTP bindings can be archived as HAR, binding [{}]", binding_name)));
+    }
+    let mut har: HarRule = HarRule::new();
+    har.directory = json_val.directory;
+    if json_val.max_file_bytes != 0 {
+        har.max_file_bytes = json_val.max_file_bytes;
+    }
+    if let Some(max_files) = json_val.max_files {
+        har.max_files = max_files;
+    }
+    if let Some(buffer) = json_val.buffer {
+        har.buffer = buffer;
+    }
+    let bodies: &str = json_val.bodies.as_str();
+    har.bodies = HarBodies::from_str(bodies)
+        .map_err(|_| invalid(format!("Unknown HAR bodies [{}] on binding [{}]", bodies, binding_name)))?;
+    if let Some(max_body_bytes) = json_val.max_body_bytes {
+        har.max_body_bytes = max_body_bytes;
+    }
+    if har.directory.is_empty() && har.buffer == 0 {
+        return Err(invalid(format!("HAR on binding [{}] needs a directory or a buffer", binding_name)));
+    }
+    Ok(har)
+}
+
+fn assemble_wasm_filter_from_json(binding_name: &str, json_val: JSONWasmFilter) -> io::Result<WasmFilterRule> {
+    if json_val.wasm.is_empty() {
+        return Err(invalid(format!("WASM filter on binding [{}] needs a module", binding_name)));
+    }
+    let mut wasm: WasmFilterRule = WasmFilterRule::new(json_val.wasm);
+    if json_val.max_memory_bytes != 0 {
+        wasm.max_memory_bytes = json_val.max_memory_bytes;
+    }
+    if json_val.max_fuel != 0 {
+        wasm.max_fuel = json_val.max_fuel;
+    }
+    Ok(wasm)
+}
+
+fn assemble_bindings_from_json(json_val: JSONBindingSet) -> io::Result<HashSet<BindingRule>> {
+    let mut binding_rules: Vec<BindingRule> = vec![];
     for binding in json_val.bindings {
         let mut ruleset: RuleSet = RuleSet::new();
         for json_rule in binding.rules.egress {
-            ruleset.add_egress_rule(assemble_rules_from_json(json_rule));
+            ruleset.add_egress_rule(assemble_rules_from_json(json_rule)?);
         }
         for json_rule in binding.rules.ingress {
-            ruleset.add_ingress_rule(assemble_rules_from_json(json_rule));
+            let rule: Rule = assemble_rules_from_json(json_rule)?;
+            // Scripts decide what happens to a request, a response has nothing left for them to decide
+            if rule.kind == RuleType::SCRIPT {
+                return Err(invalid(format!("SCRIPT rules can only be egress rules, binding [{}]", binding.name)));
+            }
+            ruleset.add_ingress_rule(rule);
+        }
+        let (protocol_name, name): (&str, &str) = (binding.protocol.as_str(), binding.name.as_str());
+        let protocol: BindingProtocol = BindingProtocol::from_str(protocol_name)
+            .map_err(|_| invalid(format!("Unknown protocol [{}] for binding [{}]", protocol_name, name)))?;
+        if binding.to.is_empty() && protocol != BindingProtocol::HTTP {
+            return Err(invalid(format!("Only HTTP bindings can leave out an upstream, binding [{}]", binding.name)));
         }
-        binding_rule_set.insert(BindingRule::new(
+        let forwarding: ForwardingRule = assemble_forwarding_from_json(binding.name.as_str(), binding.forwarding)?;
+        let proxy_protocol: ProxyProtocolRule = assemble_proxy_protocol_from_json(binding.name.as_str(), binding.proxy_protocol)?;
+        let connection_limits: ConnectionLimits = assemble_connection_limits_from_json(binding.name.as_str(), binding.connection_limits)?;
+        let mut binding_rule: BindingRule = BindingRule::new(
             binding.name,
             binding.from,
             binding.to,
             ruleset,
-        ));
+        );
+        binding_rule.set_protocol(protocol);
+        binding_rule.set_forwarding(forwarding);
+        binding_rule.set_proxy_protocol(proxy_protocol);
+        binding_rule.set_connection_limits(connection_limits);
+        binding_rule.set_bandwidth(assemble_bandwidth_from_json(binding.bandwidth));
+        for json_fault in binding.faults {
+            let fault: FaultRule = assemble_fault_from_json(binding_rule.name.as_str(), protocol, json_fault)?;
+            binding_rule.add_fault(fault);
+        }
+        let mirror: MirrorRule = assemble_mirror_from_json(binding_rule.name.as_str(), protocol, binding.mirror)?;
+        binding_rule.set_mirror(mirror);
+        if let Some(json_intercept) = binding.intercept {
+            let intercept: InterceptRule = assemble_intercept_from_json(binding_rule.name.as_str(), protocol, json_intercept)?;
+            binding_rule.set_intercept(intercept);
+        }
+        if let Some(json_har) = binding.har {
+            let har: HarRule = assemble_har_from_json(binding_rule.name.as_str(), protocol, json_har)?;
+            binding_rule.set_har(har);
+        }
+        for json_filter in binding.filters {
+            match json_filter {
+                JSONFilter::NAMED(name) => binding_rule.add_filter(name),
+                JSONFilter::WASM(json_wasm) => {
+                    let wasm: WasmFilterRule = assemble_wasm_filter_from_json(binding_rule.name.as_str(), json_wasm)?;
+                    binding_rule.add_wasm_filter(wasm);
+                },
+            }
+        }
+        binding_rules.push(binding_rule);
     }
-    return binding_rule_set;
+    // Checked before the set, which would fold two identical bindings into one without a word
+    check_unique_names(&binding_rules)?;
+    Ok(binding_rules.into_iter().collect())
 }
\ No newline at end of file
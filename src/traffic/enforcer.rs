@@ -1,18 +1,440 @@
-use crate::traffic::bindingset::BindingRule;
+use crate::traffic::bindingset::{BindingRule, Rule, RuleSet, RuleType, HeaderAction, HeaderActionType, HeaderMapping, HeaderMatchMode, HttpMethod, HttpVersion, MockResponse, PathMatchMode, RateLimitKey, UrlAction, UrlActionType, UrlMatch, VersionAction};
+use crate::traffic::http::message::{HttpHeaders, HttpRequestHead, HttpResponseHead};
+use crate::traffic::http::stream::HttpBodyFraming;
+use crate::traffic::http::url::{self, RequestUrl};
+use crate::traffic::limiter::KeyedLimiter;
+use crate::traffic::script::RequestScript;
+use crate::traffic::template::{self, TemplateVariables};
 use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::str::FromStr;
 use pnet::packet::tcp::TcpPacket;
+use regex::{Regex, RegexBuilder, Captures};
+use slog::Logger;
+
+type Byte = u8;
+
+// ---- Verdict ----
+
+pub enum Verdict {
+    FORWARD,
+    RESPOND(HttpResponseHead, Vec<Byte>), // Answer the client directly, the request is not forwarded
+    MOCK(HttpResponseHead, Vec<Byte>, Duration), // A configured response, written once the delay has passed
+}
+
+pub fn too_many_requests(retry_after: Duration) -> HttpResponseHead {
+    let mut response: HttpResponseHead = HttpResponseHead::local(429);
+    response.headers.set("Retry-After", retry_after.as_secs_f64().ceil().max(1.0).to_string());
+    response
+}
+
+// Patterns in rules are only compiled once the bindings are applied, an invalid one fails that rather than a request
+fn compile_pattern(pattern: &str, ignore_case: bool, description: &str) -> io::Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e: regex::Error| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid {} [{}]: {}", description, pattern, e)))
+}
+
+// ---- HeaderMatcher ----
+
+struct HeaderMatcher {
+    mapping: HeaderMapping,
+    pattern: Option<Regex>,
+    number: f64,
+}
+
+impl HeaderMatcher {
+    fn new(mapping: &HeaderMapping) -> io::Result<HeaderMatcher> {
+        Ok(HeaderMatcher {
+            mapping: mapping.clone(),
+            pattern: match mapping.mode {
+                HeaderMatchMode::REGEX => Some(compile_pattern(mapping.value.as_str(), mapping.ignore_case, "header match regex")?),
+                _ => None,
+            },
+            number: mapping.value.trim().parse::<f64>().unwrap_or(0.0),
+        })
+    }
+    // Also used for query parameters, which share the same predicate fields
+    fn matches(&self, values: Vec<&str>) -> bool {
+        let matched: bool = match self.mapping.mode {
+            HeaderMatchMode::PRESENT => !values.is_empty(),
+            HeaderMatchMode::ABSENT => values.is_empty(),
+            // A missing header never satisfies a value comparison, even when every value must match
+            _ if values.is_empty() => false,
+            _ if self.mapping.all_values => values.iter().all(|value: &&str| self.matches_value(value)),
+            _ => values.iter().any(|value: &&str| self.matches_value(value)),
+        };
+        matched != self.mapping.negate
+    }
+    fn matches_value(&self, value: &str) -> bool {
+        let expected: &str = self.mapping.value.as_str();
+        match self.mapping.mode {
+            HeaderMatchMode::EXACT if self.mapping.ignore_case => value.eq_ignore_ascii_case(expected),
+            HeaderMatchMode::EXACT => value == expected,
+            HeaderMatchMode::PREFIX if self.mapping.ignore_case => value.len() >= expected.len()
+                && value.is_char_boundary(expected.len())
+                && value[..expected.len()].eq_ignore_ascii_case(expected),
+            HeaderMatchMode::PREFIX => value.starts_with(expected),
+            HeaderMatchMode::REGEX => self.pattern.as_ref().unwrap().is_match(value),
+            HeaderMatchMode::PRESENT | HeaderMatchMode::ABSENT => true,
+            mode => match value.trim().parse::<f64>() {
+                Ok(actual) => match mode {
+                    HeaderMatchMode::LT => actual < self.number,
+                    HeaderMatchMode::LTE => actual <= self.number,
+                    HeaderMatchMode::GT => actual > self.number,
+                    _ => actual >= self.number,
+                },
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+// ---- UrlMatcher ----
+
+struct UrlMatcher {
+    host: Option<Regex>,
+    path: Option<Regex>,
+    query: Vec<HeaderMatcher>,
+}
+
+impl UrlMatcher {
+    fn new(url_match: &UrlMatch) -> io::Result<UrlMatcher> {
+        Ok(UrlMatcher {
+            host: match url_match.host.as_str() {
+                "" => None,
+                host => Some(compile_pattern(url::glob_to_regex(host, '.').as_str(), true, "url_match host")?),
+            },
+            path: match (url_match.path.as_str(), url_match.path_mode) {
+                ("", _) => None,
+                (path, PathMatchMode::GLOB) => Some(compile_pattern(url::glob_to_regex(path, '/').as_str(), false, "url_match path")?),
+                (path, PathMatchMode::REGEX) => Some(compile_pattern(path, false, "url_match path")?),
+            },
+            query: url_match.query.iter().map(HeaderMatcher::new).collect::<io::Result<Vec<HeaderMatcher>>>()?,
+        })
+    }
+    fn is_empty(&self) -> bool {
+        self.host.is_none() && self.path.is_none() && self.query.is_empty()
+    }
+    fn matches(&self, request: &HttpRequestHead) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let request_url: RequestUrl = RequestUrl::new(request);
+        self.host.as_ref().map_or(true, |host: &Regex| host.is_match(request_url.host.as_str()))
+            && self.path.as_ref().map_or(true, |path: &Regex| path.is_match(request_url.path.as_str()))
+            && self.query.iter().all(|matcher: &HeaderMatcher| matcher.matches(request_url.query_values(matcher.mapping.key.as_str())))
+    }
+}
+
+// ---- CompiledRule ----
+
+// Rules hold their patterns as strings so they stay hashable, these are compiled once per binding
+pub(crate) struct CompiledRule {
+    rule: Rule,
+    url_pattern: Regex,
+    url_matcher: UrlMatcher,
+    header_matchers: Vec<HeaderMatcher>,
+    action_patterns: Vec<Option<Regex>>,
+    url_action_patterns: Vec<Regex>,
+    request_limiter: Option<KeyedLimiter>,
+    connection_limiter: Option<KeyedLimiter>,
+    script: Option<RequestScript>,
+    logger: Logger,
+}
+
+impl CompiledRule {
+    pub(crate) fn new(rule: &Rule, logger: &Logger) -> io::Result<CompiledRule> {
+        Ok(CompiledRule {
+            rule: rule.clone(),
+            url_pattern: compile_pattern(rule.url_wildcard.as_str(), false, "url_wildcard regex")?,
+            url_matcher: UrlMatcher::new(&rule.url_match)?,
+            header_matchers: rule.header_mappings.iter().map(HeaderMatcher::new).collect::<io::Result<Vec<HeaderMatcher>>>()?,
+            action_patterns: rule.header_actions.iter().map(|action: &HeaderAction| match action.action {
+                HeaderActionType::REPLACE => compile_pattern(action.pattern.as_str(), false, "header action pattern").map(Some),
+                _ => Ok(None),
+            }).collect::<io::Result<Vec<Option<Regex>>>>()?,
+            url_action_patterns: rule.url_actions.iter().map(|action: &UrlAction| {
+                compile_pattern(action.pattern.as_str(), false, "URL action pattern")
+            }).collect::<io::Result<Vec<Regex>>>()?,
+            request_limiter: match (rule.kind, rule.rate_limit.requests_per_second) {
+                (RuleType::RATELIMIT, rate) if rate > 0 => Some(KeyedLimiter::new(rate, rule.rate_limit.request_burst)),
+                _ => None,
+            },
+            connection_limiter: match (rule.kind, rule.rate_limit.connections_per_second) {
+                (RuleType::RATELIMIT, rate) if rate > 0 => Some(KeyedLimiter::new(rate, rule.rate_limit.connection_burst)),
+                _ => None,
+            },
+            script: match rule.kind {
+                RuleType::SCRIPT => Some(RequestScript::compile(rule.script.as_str(), logger)
+                    .map_err(|e: String| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid script: {}", e)))?),
+                _ => None,
+            },
+            logger: logger.clone(),
+        })
+    }
+    pub(crate) fn matches(&self, headers: &HttpHeaders, request: Option<&HttpRequestHead>, version: &str) -> bool {
+        match self.rule.kind {
+            RuleType::HEADER => self.header_matchers.iter()
+                .all(|matcher: &HeaderMatcher| matcher.matches(headers.get_all(matcher.mapping.key.as_str()))),
+            RuleType::URL => request.map_or(false, |request: &HttpRequestHead| {
+                self.url_pattern.is_match(request.target.as_str()) && self.url_matcher.matches(request)
+            }),
+            RuleType::METHOD => request.map_or(false, |request: &HttpRequestHead| {
+                HttpMethod::from_str(request.method.as_str()).map_or(false, |method: HttpMethod| {
+                    self.rule.method_enum.as_ref().map_or(true, |expected: &HttpMethod| *expected == method) && self.is_method_permitted(&method)
+                })
+            }),
+            // Requests over the limit or denied by the script never reach matching, see enforce_rate_limit and enforce_script
+            RuleType::RATELIMIT | RuleType::SCRIPT => true,
+            RuleType::VERSION => HttpVersion::from_str(version).map_or(false, |version: HttpVersion| {
+                self.is_version_permitted(&version)
+            }),
+        }
+    }
+    fn is_method_permitted(&self, method: &HttpMethod) -> bool {
+        (self.rule.allowed_methods.is_empty() || self.rule.allowed_methods.contains(method))
+            && !self.rule.denied_methods.contains(method)
+    }
+    // Requests outside a METHOD rule's allowed or denied sets are refused rather than left unmatched
+    fn reject_method(&self, request: &HttpRequestHead) -> Option<Verdict> {
+        if self.rule.kind != RuleType::METHOD || (self.rule.allowed_methods.is_empty() && self.rule.denied_methods.is_empty()) {
+            return None;
+        }
+        let permitted: bool = HttpMethod::from_str(request.method.as_str())
+            .map_or(false, |method: HttpMethod| self.is_method_permitted(&method));
+        if permitted {
+            return None;
+        }
+        let mut response: HttpResponseHead = HttpResponseHead::local(405);
+        if !self.rule.allowed_methods.is_empty() {
+            let allowed: Vec<String> = self.rule.allowed_methods.iter().map(|method: &HttpMethod| method.to_string()).collect();
+            response.headers.set("Allow", allowed.join(", "));
+        }
+        Some(Verdict::RESPOND(response, vec![]))
+    }
+    fn is_version_permitted(&self, version: &HttpVersion) -> bool {
+        self.rule.version_float.map_or(true, |expected: HttpVersion| *version == expected)
+            && (self.rule.versions.is_empty() || self.rule.versions.contains(version))
+            && self.rule.min_version.map_or(true, |min: HttpVersion| *version >= min)
+            && self.rule.max_version.map_or(true, |max: HttpVersion| *version <= max)
+    }
+    fn enforce_version(&self, request: &mut HttpRequestHead) -> Option<Verdict> {
+        if self.rule.kind != RuleType::VERSION || (self.rule.version_float.is_none() && self.rule.versions.is_empty()
+            && self.rule.min_version.is_none() && self.rule.max_version.is_none()) {
+            return None;
+        }
+        let version: Option<HttpVersion> = HttpVersion::from_str(request.version.as_str()).ok();
+        if version.map_or(false, |version: HttpVersion| self.is_version_permitted(&version)) {
+            return None;
+        }
+        // Only HTTP/1.x can be rewritten in place, and HTTP/1.0 has no way to carry a chunked body.
+        // A rule with only version_float downgrades to that version
+        let max: Option<HttpVersion> = self.rule.max_version.or(self.rule.version_float);
+        if let (VersionAction::DOWNGRADE, Some(version), Some(max)) = (self.rule.version_action, version, max) {
+            let chunked: bool = HttpBodyFraming::of_request(request) == HttpBodyFraming::CHUNKED;
+            if version > max && version.major == 1 && max.major == 1 && self.is_version_permitted(&max) && (max.minor >= 1 || !chunked) {
+                request.version = max.to_string();
+                return None;
+            }
+        }
+        let mut response: HttpResponseHead = HttpResponseHead::local(505);
+        response.headers.set("Connection", String::from("close"));
+        Some(Verdict::RESPOND(response, vec![]))
+    }
+    fn enforce_rate_limit(&self, request: &HttpRequestHead, variables: &TemplateVariables) -> Option<Verdict> {
+        let limiter: &KeyedLimiter = self.request_limiter.as_ref()?;
+        let key: &str = match self.rule.rate_limit.key {
+            RateLimitKey::CLIENTIP => variables.get("client_ip").map_or("", |ip: &String| ip.as_str()),
+            RateLimitKey::HEADER => request.headers.get(self.rule.rate_limit.key_header.as_str()).unwrap_or(""),
+            RateLimitKey::BINDING => "",
+        };
+        match limiter.take(key) {
+            Ok(()) => None,
+            Err(retry_after) => Some(Verdict::RESPOND(too_many_requests(retry_after), vec![])),
+        }
+    }
+    // A script that fails, e.g. by running out of operations, refuses the request rather than letting it through unchecked
+    fn enforce_script(&self, request: &mut HttpRequestHead, variables: &TemplateVariables) -> Option<Verdict> {
+        let script: &RequestScript = self.script.as_ref()?;
+        match script.evaluate(request, variables) {
+            Ok(verdict) => verdict,
+            Err(e) => {
+                info!(self.logger, "SCRIPT rule failed on binding [{}]: {}", variables.get("binding_name").map_or("", |name: &String| name.as_str()), e);
+                Some(Verdict::RESPOND(HttpResponseHead::local(500), vec![]))
+            },
+        }
+    }
+    fn admit_connection(&self, client_addr: &SocketAddr) -> Result<(), Duration> {
+        let limiter: &KeyedLimiter = match self.connection_limiter.as_ref() {
+            Some(limiter) => limiter,
+            None => return Ok(()),
+        };
+        match self.rule.rate_limit.key {
+            RateLimitKey::BINDING => limiter.take(""),
+            _ => limiter.take(client_addr.ip().to_string().as_str()),
+        }
+    }
+    fn apply_header_actions(&self, headers: &mut HttpHeaders, variables: &TemplateVariables) {
+        for (action, pattern) in self.rule.header_actions.iter().zip(self.action_patterns.iter()) {
+            let key: &str = action.key.as_str();
+            match action.action {
+                HeaderActionType::SET => headers.set(key, template::render_header_value(action.value.as_str(), variables)),
+                HeaderActionType::APPEND => headers.append(key, template::render_header_value(action.value.as_str(), variables)),
+                HeaderActionType::REMOVE => { headers.remove(key); },
+                HeaderActionType::REPLACE => {
+                    let pattern: &Regex = pattern.as_ref().unwrap();
+                    let replacement: String = template::render_header_replacement(action.replacement.as_str(), variables);
+                    for header in headers.iter_mut().filter(|header| header.is_named(key)) {
+                        header.value = pattern.replace_all(header.value.as_str(), replacement.as_str()).into_owned();
+                    }
+                },
+            }
+        }
+    }
+    fn apply_url_actions(&self, request: &mut HttpRequestHead, variables: &TemplateVariables) -> Verdict {
+        for (action, pattern) in self.rule.url_actions.iter().zip(self.url_action_patterns.iter()) {
+            let replacement: String = template::render_replacement(action.replacement.as_str(), variables);
+            match action.action {
+                UrlActionType::REWRITE => {
+                    let rewritten: String = pattern.replace(request.target.as_str(), replacement.as_str()).into_owned();
+                    request.target = rewritten;
+                },
+                UrlActionType::REDIRECT => {
+                    let captures: Captures = match pattern.captures(request.target.as_str()) {
+                        Some(captures) => captures,
+                        None => continue,
+                    };
+                    let mut location: String = String::new();
+                    captures.expand(replacement.as_str(), &mut location);
+                    let mut response: HttpResponseHead = HttpResponseHead::local(action.status);
+                    response.headers.append("Location", location);
+                    return Verdict::RESPOND(response, vec![]);
+                },
+            }
+        }
+        Verdict::FORWARD
+    }
+    fn respond_with_mock(&self, request: &HttpRequestHead, variables: &TemplateVariables) -> Option<Verdict> {
+        let mock: &MockResponse = self.rule.mock.as_ref()?;
+        let mut variables: TemplateVariables = variables.clone();
+        if mock.template {
+            template::insert_field_variables(&mut variables, request);
+        }
+        let mut response: HttpResponseHead = HttpResponseHead::local(mock.status);
+        for (name, value) in mock.headers.iter() {
+            let value: String = match mock.template {
+                true => template::render_header_value(value.as_str(), &variables),
+                false => value.clone(),
+            };
+            response.headers.set(name.as_str(), value);
+        }
+        // Bodies that are not text, e.g. images read from a file, are sent as they are
+        let body: Vec<Byte> = match std::str::from_utf8(mock.body.as_slice()) {
+            Ok(text) if mock.template => template::render(text, &variables).into_bytes(),
+            _ => mock.body.clone(),
+        };
+        Some(Verdict::MOCK(response, body, Duration::from_millis(mock.delay_ms)))
+    }
+}
+
+// ---- Enforcer ----
 
 pub struct Enforcer {
     active: bool,
-    bindings: HashSet<BindingRule>,
+    egress: Vec<CompiledRule>,
+    ingress: Vec<CompiledRule>,
 }
 
 impl Enforcer {
-
+    pub fn new(rules: &RuleSet, logger: &Logger) -> io::Result<Enforcer> {
+        let compile = |rule: &Rule| CompiledRule::new(rule, logger);
+        Ok(Enforcer {
+            active: !rules.egress.is_empty() || !rules.ingress.is_empty(),
+            egress: rules.egress.iter().map(compile).collect::<io::Result<Vec<CompiledRule>>>()?,
+            ingress: rules.ingress.iter().map(compile).collect::<io::Result<Vec<CompiledRule>>>()?,
+        })
+    }
+    // Checked once per connection before the upstream is dialled, the error is how long until a retry would succeed
+    pub fn admit_connection(&self, client_addr: &SocketAddr) -> Result<(), Duration> {
+        for rule in self.egress.iter() {
+            rule.admit_connection(client_addr)?;
+        }
+        Ok(())
+    }
+    pub fn enforce_egress(&self, request: &mut HttpRequestHead, variables: &TemplateVariables) -> Verdict {
+        if !self.active {
+            return Verdict::FORWARD;
+        }
+        let mut variables: TemplateVariables = variables.clone();
+        for rule in self.egress.iter() {
+            if let Some(verdict) = rule.reject_method(request) {
+                return verdict;
+            }
+            if let Some(verdict) = rule.enforce_version(request) {
+                return verdict;
+            }
+            if let Some(verdict) = rule.enforce_rate_limit(request, &variables) {
+                return verdict;
+            }
+            if let Some(verdict) = rule.enforce_script(request, &variables) {
+                return verdict;
+            }
+            if !rule.matches(&request.headers, Some(request), request.version.as_str()) {
+                continue;
+            }
+            template::insert_request_variables(&mut variables, request);
+            rule.apply_header_actions(&mut request.headers, &variables);
+            if let Verdict::RESPOND(response, body) = rule.apply_url_actions(request, &variables) {
+                return Verdict::RESPOND(response, body);
+            }
+            if let Some(verdict) = rule.respond_with_mock(request, &variables) {
+                return verdict;
+            }
+        }
+        Verdict::FORWARD
+    }
+    pub fn enforce_ingress(&self, response: &mut HttpResponseHead, request: Option<&HttpRequestHead>, variables: &TemplateVariables) {
+        if !self.active {
+            return;
+        }
+        for rule in self.ingress.iter() {
+            if rule.matches(&response.headers, request, response.version.as_str()) {
+                rule.apply_header_actions(&mut response.headers, variables);
+            }
+        }
+    }
 }
 
 pub struct TransferFilterService {
     active: bool,
     bindings: HashSet<BindingRule>,
     packet_buf: VecDeque<TcpPacket<'static>>,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_line_breaks_do_not_split_mock_headers() {
+        let mut mock: MockResponse = MockResponse::new();
+        mock.headers.push((String::from("X-Echo"), String::from("${query.x}")));
+        mock.template = true;
+        let mut rule: Rule = Rule::new(RuleType::URL);
+        rule.mock = Some(mock);
+        let compiled: CompiledRule = CompiledRule::new(&rule, &Logger::root(slog::Discard, o!())).unwrap();
+        let request: HttpRequestHead = HttpRequestHead::parse(b"GET /?x=a%0d%0aSet-Cookie:%20evil=1 HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        let response: HttpResponseHead = match compiled.respond_with_mock(&request, &TemplateVariables::new()) {
+            Some(Verdict::MOCK(response, _, _)) => response,
+            _ => panic!("expected a mock response"),
+        };
+        assert_eq!(response.headers.get("x-echo"), Some("aSet-Cookie: evil=1"));
+        assert_eq!(response.headers.get("set-cookie"), None);
+        let head: String = String::from_utf8(response.to_bytes()).unwrap();
+        assert!(!head.contains("\r\nSet-Cookie"));
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use rand::Rng;
+use slog::Logger;
+use socket2::SockRef;
+
+use crate::servlet::binding_context::BindingContext;
+use crate::traffic::bindingset::{FaultRule, FaultType, LatencyDistribution, LatencyRule, Rule, TrafficDirection};
+use crate::traffic::enforcer::CompiledRule;
+use crate::traffic::http::message::{HttpHeaders, HttpRequestHead};
+
+type Byte = u8;
+
+fn roll(probability: u32) -> bool {
+    probability >= 1_000_000 || rand::thread_rng().gen_range(0..1_000_000) < probability
+}
+
+fn sample_latency(latency: &LatencyRule) -> Duration {
+    let mut rng = rand::thread_rng();
+    let ms: f64 = match latency.distribution {
+        LatencyDistribution::FIXED => latency.ms as f64,
+        LatencyDistribution::UNIFORM if latency.max_ms > latency.min_ms => rng.gen_range(latency.min_ms..=latency.max_ms) as f64,
+        LatencyDistribution::UNIFORM => latency.min_ms as f64,
+        LatencyDistribution::NORMAL => {
+            // Box-Muller, the first sample is shifted off zero so its log is finite
+            let (first, second): (f64, f64) = (1.0 - rng.gen::<f64>(), rng.gen::<f64>());
+            let standard: f64 = (-2.0 * first.ln()).sqrt() * (2.0 * std::f64::consts::PI * second).cos();
+            let sampled: f64 = (latency.ms as f64 + standard * latency.stddev_ms as f64).max(latency.min_ms as f64);
+            if latency.max_ms > 0 { sampled.min(latency.max_ms as f64) } else { sampled }
+        },
+    };
+    Duration::from_millis(ms.max(0.0) as u64)
+}
+
+// Aborts both sides so each peer sees a reset rather than a clean close
+pub fn reset(client: &TcpStream, upstream: &TcpStream) {
+    for stream in [client, upstream].iter() {
+        let _ = SockRef::from(*stream).set_linger(Some(Duration::from_secs(0)));
+        // Wakes the other direction's handler, the reset is sent once it drops its half of the streams
+        let _ = stream.shutdown(Shutdown::Read);
+    }
+}
+
+// ---- BindingFaults ----
+
+struct CompiledFault {
+    fault: FaultRule,
+    scope: Option<CompiledRule>,
+}
+
+pub(crate) struct BindingFaults {
+    faults: Vec<CompiledFault>,
+}
+
+impl BindingFaults {
+    pub fn new(faults: &Vec<FaultRule>, logger: &Logger) -> io::Result<BindingFaults> {
+        Ok(BindingFaults {
+            faults: faults.iter().map(|fault: &FaultRule| Ok(CompiledFault {
+                fault: fault.clone(),
+                scope: fault.scope.as_ref().map(|scope: &Rule| CompiledRule::new(scope, logger)).transpose()?,
+            })).collect::<io::Result<Vec<CompiledFault>>>()?,
+        })
+    }
+    // Rolled once per connection, before the upstream is dialled
+    pub fn drop_connect(&self) -> bool {
+        self.faults.iter().any(|compiled: &CompiledFault| compiled.fault.kind == FaultType::DROPCONNECT && roll(compiled.fault.probability))
+    }
+    // Which faults a message falls within, None when no fault applies to the direction at all
+    pub fn scope(&self, direction: TrafficDirection, headers: &HttpHeaders, request: Option<&HttpRequestHead>, version: &str) -> Option<Vec<bool>> {
+        if !self.applies_to(direction) {
+            return None;
+        }
+        Some(self.faults.iter().map(|compiled: &CompiledFault| {
+            compiled.scope.as_ref().map_or(true, |scope: &CompiledRule| scope.matches(headers, request, version))
+        }).collect())
+    }
+    fn applies_to(&self, direction: TrafficDirection) -> bool {
+        self.faults.iter().any(|compiled: &CompiledFault| compiled.fault.kind != FaultType::DROPCONNECT && compiled.fault.direction == direction)
+    }
+    fn unscoped(&self) -> Vec<bool> {
+        self.faults.iter().map(|compiled: &CompiledFault| compiled.scope.is_none()).collect()
+    }
+}
+
+// ---- FaultInjector ----
+
+// Where a message begins in a transformer's output and which faults it falls within
+pub(crate) struct MessageStart {
+    pub offset: usize,
+    pub scope: Vec<bool>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub(crate) enum FaultOutcome {
+    FORWARD,
+    CLOSE, // Close both sides cleanly
+    RESET, // Reset both sides, see reset
+}
+
+// Applies a binding's faults to one direction of a connection as it is written out
+pub(crate) struct FaultInjector {
+    direction: TrafficDirection,
+    context: Arc<BindingContext>,
+    id: uuid::Uuid,
+    started: bool,
+    message_bytes: u64, // Written so far for the current message
+    pending: Vec<usize>, // Faults rolled for the current message that have not struck yet
+}
+
+impl FaultInjector {
+    pub fn new(direction: TrafficDirection, context: &Arc<BindingContext>, id: uuid::Uuid) -> Option<FaultInjector> {
+        if !context.faults.applies_to(direction) {
+            return None;
+        }
+        Some(FaultInjector {
+            direction,
+            context: context.clone(),
+            id,
+            started: false,
+            message_bytes: 0,
+            pending: vec![],
+        })
+    }
+    pub fn forward(&mut self, writer: &mut TcpStream, mut output: Vec<Byte>, starts: Vec<MessageStart>) -> io::Result<FaultOutcome> {
+        let mut starts: VecDeque<MessageStart> = starts.into();
+        // Streams that are not parsed as HTTP are treated as one long message
+        if !self.started && starts.is_empty() && !output.is_empty() {
+            starts.push_back(MessageStart { offset: 0, scope: self.context.faults.unscoped() });
+        }
+        let mut position: usize = 0;
+        loop {
+            let end: usize = starts.front().map_or(output.len(), |start: &MessageStart| start.offset);
+            let outcome: FaultOutcome = self.forward_segment(writer, &mut output[position..end])?;
+            if outcome != FaultOutcome::FORWARD {
+                return Ok(outcome);
+            }
+            position = end;
+            match starts.pop_front() {
+                Some(start) => self.begin_message(start.scope),
+                None => return Ok(FaultOutcome::FORWARD),
+            }
+        }
+    }
+    fn begin_message(&mut self, scope: Vec<bool>) {
+        let context: Arc<BindingContext> = self.context.clone();
+        self.started = true;
+        self.message_bytes = 0;
+        self.pending = context.faults.faults.iter().enumerate()
+            .filter(|(index, compiled)| {
+                scope[*index] && compiled.fault.kind != FaultType::DROPCONNECT && compiled.fault.direction == self.direction
+            })
+            .filter(|(_, compiled)| roll(compiled.fault.probability))
+            .map(|(index, _)| index)
+            .collect();
+    }
+    fn forward_segment(&mut self, writer: &mut TcpStream, segment: &mut [Byte]) -> io::Result<FaultOutcome> {
+        let context: Arc<BindingContext> = self.context.clone();
+        let mut position: usize = 0;
+        loop {
+            let remaining: u64 = (segment.len() - position) as u64;
+            // Corruption needs a byte to land on, the rest can strike at the very end of what has been written
+            let due: Option<usize> = (0..self.pending.len())
+                .filter(|pending: &usize| {
+                    let fault: &FaultRule = &context.faults.faults[self.pending[*pending]].fault;
+                    match fault.kind {
+                        FaultType::CORRUPT => fault.after_bytes < self.message_bytes + remaining,
+                        _ => fault.after_bytes <= self.message_bytes + remaining,
+                    }
+                })
+                .min_by_key(|pending: &usize| context.faults.faults[self.pending[*pending]].fault.after_bytes);
+            let due: usize = match due {
+                Some(due) => due,
+                None => {
+                    writer.write_all(&segment[position..])?;
+                    self.message_bytes += remaining;
+                    return Ok(FaultOutcome::FORWARD);
+                },
+            };
+            let fault: &FaultRule = &context.faults.faults[self.pending.remove(due)].fault;
+            let until: usize = position + fault.after_bytes.saturating_sub(self.message_bytes) as usize;
+            writer.write_all(&segment[position..until])?;
+            self.message_bytes += (until - position) as u64;
+            position = until;
+            info!(self.context.logger, "FAULT INJECTED [{}] [{}] [{:?}] [After: {} bytes]", self.direction, self.id, fault.kind, self.message_bytes);
+            match fault.kind {
+                FaultType::LATENCY => {
+                    writer.flush()?;
+                    thread::sleep(sample_latency(&fault.latency));
+                },
+                FaultType::CORRUPT => {
+                    let mut rng = rand::thread_rng();
+                    for _ in 0..fault.corrupt_bytes {
+                        let index: usize = rng.gen_range(position..segment.len());
+                        segment[index] ^= rng.gen_range(1..=255);
+                    }
+                },
+                FaultType::STALL => {
+                    writer.flush()?;
+                    thread::sleep(Duration::from_millis(fault.stall_ms));
+                    return Ok(FaultOutcome::CLOSE);
+                },
+                FaultType::TRUNCATE => return Ok(FaultOutcome::CLOSE),
+                FaultType::RESET => return Ok(FaultOutcome::RESET),
+                FaultType::DROPCONNECT => {},
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use slog::Logger;
+
+use crate::traffic::bindingset::{BindingRule, FilterRule};
+use crate::traffic::http::message::{HttpRequestHead, HttpResponseHead};
+use crate::traffic::wasm::WasmFilter;
+
+type Byte = u8;
+
+// ---- FilterAction ----
+
+pub enum FilterAction {
+    CONTINUE,
+    RESPOND(HttpResponseHead, Vec<Byte>), // Answers the client directly, from on_connect and on_request_headers, elsewhere it closes the connection
+    CLOSE, // Closes both sides of the connection, nothing more is forwarded
+}
+
+// ---- FilterConnection ----
+
+pub struct FilterConnection {
+    pub id: uuid::Uuid,
+    pub binding: String,
+    pub client: SocketAddr, // As given by the PROXY protocol header when the binding accepts one
+    pub upstream: Option<SocketAddr>, // None when the binding answers every request from its rules
+}
+
+// ---- Filter ----
+
+// Custom processing for the traffic of the bindings it is chained to, one filter is shared by all their connections.
+// Every hook lets traffic through unless implemented, the HTTP hooks only run on HTTP bindings
+pub trait Filter: Send + Sync {
+    fn on_connect(&self, _connection: &FilterConnection) -> FilterAction {
+        FilterAction::CONTINUE
+    }
+    // Runs once the binding's rules have decided to forward the request
+    fn on_request_headers(&self, _connection: &FilterConnection, _request: &mut HttpRequestHead) -> FilterAction {
+        FilterAction::CONTINUE
+    }
+    // Chunks can be rewritten in place, a last empty chunk with end_of_body set lets held back content be written.
+    // Changing a body's length needs its head to switch to chunked framing, or the peer is left waiting for the bytes it was promised
+    fn on_request_body_chunk(&self, _connection: &FilterConnection, _request: &HttpRequestHead, _chunk: &mut Vec<Byte>, _end_of_body: bool) -> FilterAction {
+        FilterAction::CONTINUE
+    }
+    // Runs once the binding's ingress rules have been applied, request is None when the response answered nothing the proxy forwarded
+    fn on_response_headers(&self, _connection: &FilterConnection, _request: Option<&HttpRequestHead>, _response: &mut HttpResponseHead) -> FilterAction {
+        FilterAction::CONTINUE
+    }
+    fn on_response_body_chunk(&self, _connection: &FilterConnection, _response: &HttpResponseHead, _chunk: &mut Vec<Byte>, _end_of_body: bool) -> FilterAction {
+        FilterAction::CONTINUE
+    }
+    // Once both directions have finished, only for connections on_connect let through
+    fn on_close(&self, _connection: &FilterConnection) {}
+}
+
+// ---- FilterChain ----
+
+// A binding's filters, requests pass through them in the order they are listed and responses in reverse.
+// The first filter that does not continue decides, the filters after it never see the message
+#[derive(Clone)]
+pub(crate) struct FilterChain {
+    filters: Vec<Arc<dyn Filter>>,
+}
+
+impl FilterChain {
+    pub fn new() -> FilterChain {
+        FilterChain {
+            filters: vec![],
+        }
+    }
+    // Named filters come from the proxy's registry, WASM modules are read and compiled again on every call
+    pub fn resolve(rule: &BindingRule, registry: &HashMap<String, Arc<dyn Filter>>, logger: &Logger) -> io::Result<FilterChain> {
+        let mut chain: FilterChain = FilterChain::new();
+        for filter in rule.filters.iter() {
+            match filter {
+                FilterRule::NAMED(name) => chain.push(registry.get(name).cloned().ok_or(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown filter [{}] for binding [{}]", name, rule.name)
+                ))?),
+                FilterRule::WASM(wasm) => chain.push(Arc::new(WasmFilter::load(rule.name.as_str(), wasm, logger)?)),
+            }
+        }
+        Ok(chain)
+    }
+    pub fn push(&mut self, filter: Arc<dyn Filter>) {
+        self.filters.push(filter);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+    pub fn on_connect(&self, connection: &FilterConnection) -> FilterAction {
+        FilterChain::first_decision(self.filters.iter(), |filter: &Arc<dyn Filter>| filter.on_connect(connection))
+    }
+    pub fn on_request_headers(&self, connection: &FilterConnection, request: &mut HttpRequestHead) -> FilterAction {
+        FilterChain::first_decision(self.filters.iter(), |filter: &Arc<dyn Filter>| filter.on_request_headers(connection, request))
+    }
+    pub fn on_request_body_chunk(&self, connection: &FilterConnection, request: &HttpRequestHead, chunk: &mut Vec<Byte>, end_of_body: bool) -> FilterAction {
+        FilterChain::first_decision(self.filters.iter(), |filter: &Arc<dyn Filter>| filter.on_request_body_chunk(connection, request, chunk, end_of_body))
+    }
+    pub fn on_response_headers(&self, connection: &FilterConnection, request: Option<&HttpRequestHead>, response: &mut HttpResponseHead) -> FilterAction {
+        FilterChain::first_decision(self.filters.iter().rev(), |filter: &Arc<dyn Filter>| filter.on_response_headers(connection, request, response))
+    }
+    pub fn on_response_body_chunk(&self, connection: &FilterConnection, response: &HttpResponseHead, chunk: &mut Vec<Byte>, end_of_body: bool) -> FilterAction {
+        FilterChain::first_decision(self.filters.iter().rev(), |filter: &Arc<dyn Filter>| filter.on_response_body_chunk(connection, response, chunk, end_of_body))
+    }
+    fn first_decision<'a, I: Iterator<Item = &'a Arc<dyn Filter>>>(filters: I, mut hook: impl FnMut(&Arc<dyn Filter>) -> FilterAction) -> FilterAction {
+        for filter in filters {
+            match hook(filter) {
+                FilterAction::CONTINUE => {},
+                action => return action,
+            }
+        }
+        FilterAction::CONTINUE
+    }
+}
+
+// ---- FilteredConnection ----
+
+// Held in the connection's metadata, so on_close runs once both directions have finished
+pub(crate) struct FilteredConnection {
+    pub connection: FilterConnection,
+    chain: FilterChain,
+}
+
+impl FilteredConnection {
+    pub fn new(connection: FilterConnection, chain: &FilterChain) -> FilteredConnection {
+        FilteredConnection {
+            connection,
+            chain: chain.clone(),
+        }
+    }
+}
+
+impl Drop for FilteredConnection {
+    fn drop(&mut self) {
+        for filter in self.chain.filters.iter().rev() {
+            filter.on_close(&self.connection);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::Hasher;
+use serde_json::Value;
+use slog::Logger;
+
+use crate::traffic::bindingset::{BodyComparison, CompareRule, TrafficDirection};
+use crate::traffic::http::message::{HttpHeaders, HttpRequestHead, HttpResponseHead};
+use crate::traffic::http::stream::{HttpBodyFraming, HttpStreamEvent, HttpStreamParser};
+use crate::if_let_some;
+
+type Byte = u8;
+
+// Keeps a single diff log entry readable when two responses have nothing in common
+static MAX_REPORTED_DIFFERENCES: usize = 20;
+static MAX_VALUE_LENGTH: usize = 80;
+// Exchanges waiting on the other side's response, each can hold two bodies of up to max_body_bytes
+static MAX_PENDING_EXCHANGES: usize = 64;
+static FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+static FNV_PRIME: u64 = 0x100000001b3;
+
+// ---- BodyHash ----
+
+// FNV-1a, so a body hashes the same in every process and the hashes in the diff log can be compared across runs
+struct BodyHash(u64);
+
+impl BodyHash {
+    fn new() -> BodyHash {
+        BodyHash(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for BodyHash {
+    fn write(&mut self, bytes: &[Byte]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// ---- CapturedResponse ----
+
+// Everything about one response needed to compare it, bodies are only kept whole for JSON comparison
+pub(crate) struct CapturedResponse {
+    status: u16,
+    headers: HttpHeaders,
+    body: Vec<Byte>,
+    body_length: u64,
+    hasher: BodyHash,
+    keep_body: bool,
+    max_body_bytes: usize,
+}
+
+impl CapturedResponse {
+    pub fn new(head: &HttpResponseHead, rule: &CompareRule) -> CapturedResponse {
+        CapturedResponse {
+            status: head.status,
+            headers: head.headers.clone(),
+            body: vec![],
+            body_length: 0,
+            hasher: BodyHash::new(),
+            keep_body: rule.body == BodyComparison::JSON,
+            max_body_bytes: rule.max_body_bytes,
+        }
+    }
+    pub fn push_body(&mut self, content: &[Byte]) {
+        self.hasher.write(content);
+        self.body_length += content.len() as u64;
+        if !self.keep_body {
+            return;
+        }
+        if self.is_whole() {
+            self.body.extend_from_slice(content);
+        } else {
+            self.body = vec![];
+        }
+    }
+    fn is_whole(&self) -> bool {
+        self.body_length <= self.max_body_bytes as u64
+    }
+}
+
+// ---- ResponseReader ----
+
+// Captures the responses in one direction of a connection that nothing else parses, e.g. a shadow's or a replay's
+pub(crate) struct ResponseReader {
+    parser: HttpStreamParser,
+    rule: CompareRule,
+    capture: Option<(u64, CapturedResponse)>,
+    responses: u64,
+}
+
+impl ResponseReader {
+    pub fn new(rule: CompareRule) -> ResponseReader {
+        ResponseReader {
+            parser: HttpStreamParser::new(TrafficDirection::INGRESS),
+            rule,
+            capture: None,
+            responses: 0,
+        }
+    }
+    // next_request gives the exchange number and method of the request each final response answers
+    pub fn push<F: FnMut() -> Option<(u64, String)>>(&mut self, input: &[Byte], mut next_request: F) -> Vec<(u64, CapturedResponse)> {
+        let mut captured: Vec<(u64, CapturedResponse)> = vec![];
+        self.parser.push(input);
+        while let Some(event) = self.parser.next_event() {
+            match event {
+                // Interim responses share the request with the final response that follows them
+                HttpStreamEvent::RESPONSE(head) if head.is_informational() && head.status != 101 => {},
+                HttpStreamEvent::RESPONSE(head) => {
+                    self.responses += 1;
+                    let request: Option<(u64, String)> = next_request();
+                    let framing: HttpBodyFraming = HttpBodyFraming::of_response(
+                        &head,
+                        request.as_ref().map(|(_, method): &(u64, String)| method.as_str()),
+                    );
+                    self.parser.set_framing(framing);
+                    self.capture = match (request, framing) {
+                        (_, HttpBodyFraming::TUNNEL) | (None, _) => None,
+                        (Some((exchange, _)), _) => Some((exchange, CapturedResponse::new(&head, &self.rule))),
+                    };
+                },
+                HttpStreamEvent::BODY(content) => {
+                    if let Some((_, response)) = self.capture.as_mut() {
+                        response.push_body(content.as_slice());
+                    }
+                },
+                HttpStreamEvent::END => captured.extend(self.capture.take()),
+                HttpStreamEvent::REQUEST(_) | HttpStreamEvent::RAW(_) => {},
+            }
+        }
+        captured
+    }
+    // Called once the stream has closed, bodies delimited by the close end here
+    pub fn finish(&mut self) -> Option<(u64, CapturedResponse)> {
+        match self.parser.finish() {
+            Some(HttpStreamEvent::END) => self.capture.take(),
+            _ => None,
+        }
+    }
+    // Final responses seen so far, including any still being read
+    pub fn responses(&self) -> u64 {
+        self.responses
+    }
+    pub fn is_reading_body(&self) -> bool {
+        self.capture.is_some()
+    }
+}
+
+// ---- ResponseComparator ----
+
+struct Exchange {
+    request: String,
+    primary: Option<CapturedResponse>,
+    shadow: Option<CapturedResponse>,
+}
+
+// Pairs the primary and shadow responses to each mirrored request on a connection, exchanges are numbered in request order
+pub(crate) struct ResponseComparator {
+    id: uuid::Uuid,
+    binding_name: String,
+    rule: CompareRule,
+    exchanges: BTreeMap<u64, Exchange>, // Numbered in request order, so the first is the oldest
+    evicted: u64,
+    abandoned: bool, // Set once the shadow will send no more responses
+    logger: Logger,
+    diff_logger: Logger,
+}
+
+impl ResponseComparator {
+    pub fn new(id: uuid::Uuid, binding_name: String, rule: &CompareRule, logger: &Logger, diff_logger: &Logger) -> ResponseComparator {
+        ResponseComparator {
+            id,
+            binding_name,
+            rule: rule.clone(),
+            exchanges: BTreeMap::new(),
+            evicted: 0,
+            abandoned: false,
+            logger: logger.clone(),
+            diff_logger: diff_logger.clone(),
+        }
+    }
+    pub fn rule(&self) -> &CompareRule {
+        &self.rule
+    }
+    // A shadow that is slow or gone cannot grow the pending exchanges past the cap, the oldest are given up on
+    pub fn expect(&mut self, exchange: u64, request: &HttpRequestHead) {
+        if self.abandoned {
+            return;
+        }
+        while self.exchanges.len() >= MAX_PENDING_EXCHANGES {
+            let (oldest, _): (u64, Exchange) = self.exchanges.pop_first().unwrap();
+            self.evicted += 1;
+            info!(self.logger, "RESPONSE COMPARISON DROPPED [{}] [Exchange: {}] [Dropped: {}]", self.id, oldest, self.evicted);
+        }
+        self.exchanges.insert(exchange, Exchange {
+            request: format!("{} {}", request.method, request.target),
+            primary: None,
+            shadow: None,
+        });
+    }
+    pub fn is_expected(&self, exchange: u64) -> bool {
+        self.exchanges.contains_key(&exchange)
+    }
+    pub fn record_primary(&mut self, exchange: u64, response: CapturedResponse) {
+        if_let_some!{pending = self.exchanges.get_mut(&exchange)};
+        pending.primary = Some(response);
+        self.compare_if_complete(exchange);
+    }
+    pub fn record_shadow(&mut self, exchange: u64, response: CapturedResponse) {
+        if_let_some!{pending = self.exchanges.get_mut(&exchange)};
+        pending.shadow = Some(response);
+        self.compare_if_complete(exchange);
+    }
+    // Called once the mirror stops, exchanges the shadow has not answered are reported as such and released
+    pub fn abandon(&mut self) {
+        self.abandoned = true;
+        let unanswered: Vec<u64> = self.exchanges.iter()
+            .filter(|(_, pending): &(&u64, &Exchange)| pending.shadow.is_none())
+            .map(|(exchange, _): (&u64, &Exchange)| *exchange)
+            .collect();
+        for exchange in unanswered {
+            let pending: Exchange = self.exchanges.remove(&exchange).unwrap();
+            if pending.primary.is_some() {
+                self.report(exchange, pending.request.as_str(), vec![String::from("shadow: no response")]);
+            }
+        }
+    }
+    fn compare_if_complete(&mut self, exchange: u64) {
+        let complete: bool = self.exchanges.get(&exchange)
+            .map_or(false, |pending: &Exchange| pending.primary.is_some() && pending.shadow.is_some());
+        if !complete {
+            return;
+        }
+        let pending: Exchange = self.exchanges.remove(&exchange).unwrap();
+        let differences: Vec<String> = compare(&self.rule, pending.primary.as_ref().unwrap(), pending.shadow.as_ref().unwrap());
+        if differences.is_empty() {
+            debug!(self.logger, "RESPONSE MATCH [{}] [Exchange: {}]", self.id, exchange);
+            return;
+        }
+        self.report(exchange, pending.request.as_str(), differences);
+    }
+    fn report(&self, exchange: u64, request: &str, differences: Vec<String>) {
+        info!(self.logger, "RESPONSE DIFF [{}] [Exchange: {}] [Differences: {}]", self.id, exchange, differences.len());
+        info!(self.diff_logger, "RESPONSE DIFF";
+            "request_id" => %self.id,
+            "exchange" => exchange,
+            "binding" => self.binding_name.as_str(),
+            "request" => request,
+            "differences" => differences.join("; "));
+    }
+}
+
+impl Drop for ResponseComparator {
+    // Requests the shadow never answered, e.g. because it was down or fell behind, are differences too
+    fn drop(&mut self) {
+        self.abandon();
+    }
+}
+
+// ---- Comparison ----
+
+pub(crate) fn compare(rule: &CompareRule, primary: &CapturedResponse, shadow: &CapturedResponse) -> Vec<String> {
+    let mut differences: Vec<String> = vec![];
+    if primary.status != shadow.status {
+        differences.push(format!("status: {} != {}", primary.status, shadow.status));
+    }
+    for name in rule.headers.iter() {
+        let (primary_values, shadow_values): (Vec<&str>, Vec<&str>) = (primary.headers.get_all(name), shadow.headers.get_all(name));
+        if primary_values != shadow_values {
+            differences.push(format!("header {}: {:?} != {:?}", name.to_ascii_lowercase(), primary_values, shadow_values));
+        }
+    }
+    match rule.body {
+        BodyComparison::NONE => {},
+        BodyComparison::JSON if primary.is_whole() && shadow.is_whole() => {
+            match (serde_json::from_slice::<Value>(primary.body.as_slice()), serde_json::from_slice::<Value>(shadow.body.as_slice())) {
+                (Ok(primary_json), Ok(shadow_json)) => diff_json("", &primary_json, &shadow_json, &rule.ignore_fields, &mut differences),
+                (Err(_), Err(_)) => compare_hashes(primary, shadow, &mut differences),
+                (Ok(_), Err(_)) => differences.push(String::from("body: shadow is not JSON")),
+                (Err(_), Ok(_)) => differences.push(String::from("body: primary is not JSON")),
+            }
+        },
+        _ => compare_hashes(primary, shadow, &mut differences),
+    }
+    differences
+}
+
+fn compare_hashes(primary: &CapturedResponse, shadow: &CapturedResponse, differences: &mut Vec<String>) {
+    let (primary_hash, shadow_hash): (u64, u64) = (primary.hasher.finish(), shadow.hasher.finish());
+    if primary.body_length != shadow.body_length || primary_hash != shadow_hash {
+        differences.push(format!("body: {:016x} != {:016x} ({} != {} bytes)", primary_hash, shadow_hash, primary.body_length, shadow.body_length));
+    }
+}
+
+fn is_ignored(pointer: &str, key: &str, ignored: &Vec<String>) -> bool {
+    ignored.iter().any(|field: &String| if field.starts_with('/') { field == pointer } else { field == key })
+}
+
+fn summarize(value: &Value) -> String {
+    let rendered: String = value.to_string();
+    if rendered.chars().count() <= MAX_VALUE_LENGTH {
+        return rendered;
+    }
+    format!("{}...", rendered.chars().take(MAX_VALUE_LENGTH).collect::<String>())
+}
+
+// Differences are reported against JSON pointers into the primary body
+fn diff_json(pointer: &str, primary: &Value, shadow: &Value, ignored: &Vec<String>, differences: &mut Vec<String>) {
+    if differences.len() >= MAX_REPORTED_DIFFERENCES {
+        return;
+    }
+    let location: &str = if pointer.is_empty() { "/" } else { pointer };
+    match (primary, shadow) {
+        (Value::Object(primary_fields), Value::Object(shadow_fields)) => {
+            let keys: BTreeSet<&String> = primary_fields.keys().chain(shadow_fields.keys()).collect();
+            for key in keys {
+                let child: String = format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+                if is_ignored(child.as_str(), key.as_str(), ignored) || differences.len() >= MAX_REPORTED_DIFFERENCES {
+                    continue;
+                }
+                match (primary_fields.get(key), shadow_fields.get(key)) {
+                    (Some(primary_value), Some(shadow_value)) => diff_json(child.as_str(), primary_value, shadow_value, ignored, differences),
+                    (Some(_), None) => differences.push(format!("body {}: missing from shadow", child)),
+                    (None, Some(_)) => differences.push(format!("body {}: only in shadow", child)),
+                    (None, None) => {},
+                }
+            }
+        },
+        (Value::Array(primary_items), Value::Array(shadow_items)) => {
+            if primary_items.len() != shadow_items.len() {
+                differences.push(format!("body {}: {} != {} elements", location, primary_items.len(), shadow_items.len()));
+            }
+            for (index, (primary_item, shadow_item)) in primary_items.iter().zip(shadow_items.iter()).enumerate() {
+                let child: String = format!("{}/{}", pointer, index);
+                if !is_ignored(child.as_str(), "", ignored) {
+                    diff_json(child.as_str(), primary_item, shadow_item, ignored, differences);
+                }
+            }
+        },
+        _ if primary != shadow => differences.push(format!("body {}: {} != {}", location, summarize(primary), summarize(shadow))),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comparator() -> ResponseComparator {
+        let logger: Logger = Logger::root(slog::Discard, o!());
+        ResponseComparator::new(uuid::Uuid::nil(), String::from("test"), &CompareRule::new(), &logger, &logger)
+    }
+
+    #[test]
+    fn body_hash_is_fnv1a() {
+        let mut hash: BodyHash = BodyHash::new();
+        assert_eq!(hash.finish(), 0xcbf29ce484222325);
+        hash.write(b"a");
+        assert_eq!(hash.finish(), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn pending_exchanges_are_capped_oldest_first() {
+        let mut comparator: ResponseComparator = comparator();
+        let request: HttpRequestHead = HttpRequestHead::parse(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        for exchange in 1..=(MAX_PENDING_EXCHANGES as u64 + 10) {
+            comparator.expect(exchange, &request);
+        }
+        assert_eq!(comparator.exchanges.len(), MAX_PENDING_EXCHANGES);
+        assert_eq!(comparator.evicted, 10);
+        assert!(!comparator.is_expected(10));
+        assert!(comparator.is_expected(11));
+    }
+
+    #[test]
+    fn abandoning_releases_unanswered_exchanges() {
+        let mut comparator: ResponseComparator = comparator();
+        let request: HttpRequestHead = HttpRequestHead::parse(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        comparator.expect(1, &request);
+        comparator.abandon();
+        assert!(comparator.exchanges.is_empty());
+        comparator.expect(2, &request);
+        assert!(!comparator.is_expected(2));
+    }
+}
@@ -0,0 +1,86 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use ipnet::IpNet;
+
+use crate::traffic::bindingset::{ForwardingMode, ForwardingRule};
+use crate::traffic::http::message::{self, HttpRequestHead};
+
+static PROTO: &'static str = "http";
+
+// Injects X-Forwarded-*, Forwarded (RFC 7239) and Via headers on requests for a binding
+pub struct ForwardingHeaders {
+    rule: ForwardingRule,
+    trusted_proxies: Vec<IpNet>,
+}
+
+impl ForwardingHeaders {
+    pub fn new(rule: &ForwardingRule) -> io::Result<ForwardingHeaders> {
+        Ok(ForwardingHeaders {
+            rule: rule.clone(),
+            trusted_proxies: rule.trusted_proxies.iter().map(|proxy: &String| {
+                IpNet::from_str(proxy.as_str())
+                    .or_else(|_| IpAddr::from_str(proxy.as_str()).map(IpNet::from))
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid trusted proxy CIDR: {}", proxy)))
+            }).collect::<io::Result<Vec<IpNet>>>()?,
+        })
+    }
+    pub fn is_trusted(&self, addr: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net: &IpNet| net.contains(addr))
+    }
+    pub fn apply(&self, request: &mut HttpRequestHead, client_addr: &SocketAddr) {
+        let trusted: bool = self.is_trusted(&client_addr.ip());
+        let client_ip: String = client_addr.ip().to_string();
+        let host: Option<String> = request.headers.get("host").map(String::from);
+        match self.rule.x_forwarded_for {
+            ForwardingMode::NONE => {},
+            ForwardingMode::APPEND if trusted && request.headers.contains("x-forwarded-for") => {
+                let existing: String = request.headers.get_all("x-forwarded-for").join(", ");
+                request.headers.set("X-Forwarded-For", format!("{}, {}", existing, client_ip));
+            },
+            _ => request.headers.set("X-Forwarded-For", client_ip),
+        }
+        match self.rule.x_forwarded_proto {
+            ForwardingMode::NONE => {},
+            ForwardingMode::APPEND if trusted && request.headers.contains("x-forwarded-proto") => {},
+            _ => request.headers.set("X-Forwarded-Proto", String::from(PROTO)),
+        }
+        match (self.rule.x_forwarded_host, host.as_ref()) {
+            (ForwardingMode::NONE, _) => {},
+            (ForwardingMode::APPEND, _) if trusted && request.headers.contains("x-forwarded-host") => {},
+            (_, Some(host)) => request.headers.set("X-Forwarded-Host", host.clone()),
+            (_, None) => { request.headers.remove("x-forwarded-host"); },
+        }
+        match self.rule.forwarded {
+            ForwardingMode::NONE => {},
+            ForwardingMode::APPEND if trusted && request.headers.contains("forwarded") => {
+                let existing: String = request.headers.get_all("forwarded").join(", ");
+                request.headers.set("Forwarded", format!("{}, {}", existing, ForwardingHeaders::forwarded_element(client_addr, host.as_ref())));
+            },
+            _ => request.headers.set("Forwarded", ForwardingHeaders::forwarded_element(client_addr, host.as_ref())),
+        }
+        let via: String = format!("{} {}", request.version.trim_start_matches("HTTP/"), self.rule.via_pseudonym);
+        match self.rule.via {
+            ForwardingMode::NONE => {},
+            // Via lists every hop regardless of trust, see RFC 7230 5.7.1
+            ForwardingMode::APPEND => request.headers.append("Via", via),
+            ForwardingMode::OVERWRITE => request.headers.set("Via", via),
+        }
+    }
+    fn forwarded_element(client_addr: &SocketAddr, host: Option<&String>) -> String {
+        let node: String = match client_addr {
+            SocketAddr::V4(addr) => addr.ip().to_string(),
+            SocketAddr::V6(addr) => format!("\"[{}]\"", addr.ip()),
+        };
+        match host {
+            Some(host) => format!("for={};host={};proto={}", node, ForwardingHeaders::quote(host.as_str()), PROTO),
+            None => format!("for={};proto={}", node, PROTO),
+        }
+    }
+    fn quote(value: &str) -> String {
+        if message::is_token(value) {
+            return String::from(value);
+        }
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::VecDeque;
+use std::fs::{self, DirEntry, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Instant;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+use slog::Logger;
+
+use crate::servlet::request_metadata::RequestMetadata;
+use crate::traffic::bindingset::{HarBodies, HarRule};
+use crate::traffic::http::message::{HttpHeader, HttpHeaders, HttpRequestHead, HttpResponseHead};
+use crate::traffic::http::url::RequestUrl;
+
+type Byte = u8;
+
+// Entries waiting for the file writer, once full entries are left out of the file rather than holding up traffic
+static HAR_QUEUE_ENTRIES: usize = 1024;
+static HAR_FILE_EXTENSION: &'static str = ".har";
+// Every file ends with this so it is a complete document between writes, new entries are written over it
+static HAR_FILE_TAIL: &'static [Byte] = b"\n]}}\n";
+
+// ---- HarExchange ----
+
+struct HarBody {
+    bytes: Vec<Byte>,
+    size: u64,
+}
+
+impl HarBody {
+    fn new() -> HarBody {
+        HarBody {
+            bytes: vec![],
+            size: 0,
+        }
+    }
+    fn push(&mut self, content: &[Byte], rule: &HarRule) {
+        self.size += content.len() as u64;
+        if rule.bodies == HarBodies::NONE || self.bytes.len() >= rule.max_body_bytes {
+            return;
+        }
+        let kept: usize = std::cmp::min(content.len(), rule.max_body_bytes - self.bytes.len());
+        self.bytes.extend_from_slice(&content[..kept]);
+    }
+    fn is_truncated(&self) -> bool {
+        (self.bytes.len() as u64) < self.size
+    }
+    fn text(&self, rule: &HarRule) -> Option<String> {
+        match rule.bodies {
+            HarBodies::NONE => None,
+            HarBodies::TEXT => Some(String::from_utf8_lossy(self.bytes.as_slice()).into_owned()),
+            HarBodies::BASE64 => Some(base64::engine::general_purpose::STANDARD.encode(self.bytes.as_slice())),
+        }
+    }
+}
+
+// One request and its response as the binding forwarded them, becomes a HAR entry once the response ends
+pub(crate) struct HarExchange {
+    pub exchange: u64, // Numbered like the connection's other exchanges so the response finds its request
+    started: DateTime<Utc>,
+    start: Instant,
+    request: HttpRequestHead,
+    request_body: HarBody,
+    request_sent: Option<Instant>,
+    response: Option<HttpResponseHead>,
+    response_body: HarBody,
+    response_started: Option<Instant>,
+}
+
+impl HarExchange {
+    pub fn new(exchange: u64, request: &HttpRequestHead) -> HarExchange {
+        HarExchange {
+            exchange,
+            started: Utc::now(),
+            start: Instant::now(),
+            request: request.clone(),
+            request_body: HarBody::new(),
+            request_sent: None,
+            response: None,
+            response_body: HarBody::new(),
+            response_started: None,
+        }
+    }
+    pub fn push_request_body(&mut self, content: &[Byte], rule: &HarRule) {
+        self.request_body.push(content, rule);
+    }
+    pub fn request_sent(&mut self) {
+        self.request_sent = Some(Instant::now());
+    }
+    pub fn response(&mut self, head: &HttpResponseHead) {
+        self.response = Some(head.clone());
+        self.response_started = Some(Instant::now());
+    }
+    pub fn push_response_body(&mut self, content: &[Byte], rule: &HarRule) {
+        self.response_body.push(content, rule);
+    }
+    // Called once the response has ended, phases the proxy cannot see (DNS, connect, TLS) are reported as -1
+    pub fn to_entry(&self, rule: &HarRule, md: &RequestMetadata) -> Value {
+        let finished: Instant = Instant::now();
+        let response_started: Instant = self.response_started.unwrap_or(finished);
+        // A response can begin before the request has been sent in full
+        let request_sent: Instant = std::cmp::min(self.request_sent.unwrap_or(response_started), response_started);
+        let (send, wait, receive): (f64, f64, f64) = (
+            millis_between(self.start, request_sent),
+            millis_between(request_sent, response_started),
+            millis_between(response_started, finished),
+        );
+        let mut entry: Map<String, Value> = Map::new();
+        entry.insert(String::from("startedDateTime"), json!(self.started.to_rfc3339_opts(SecondsFormat::Millis, true)));
+        entry.insert(String::from("time"), json!(millis_between(self.start, finished)));
+        entry.insert(String::from("request"), self.request_json(rule, md));
+        entry.insert(String::from("response"), self.response_json(rule));
+        entry.insert(String::from("cache"), json!({}));
+        entry.insert(String::from("timings"), json!({
+            "blocked": -1,
+            "dns": -1,
+            "connect": -1,
+            "ssl": -1,
+            "send": send,
+            "wait": wait,
+            "receive": receive,
+        }));
+        if let Some(upstream) = md.upstream_addr {
+            entry.insert(String::from("serverIPAddress"), json!(upstream.ip().to_string()));
+        }
+        entry.insert(String::from("connection"), json!(md.id.to_string()));
+        entry.insert(String::from("_clientAddress"), json!(md.client_addr.to_string()));
+        entry.insert(String::from("_binding"), json!(md.binding_name));
+        Value::Object(entry)
+    }
+    fn request_json(&self, rule: &HarRule, md: &RequestMetadata) -> Value {
+        let request: &HttpRequestHead = &self.request;
+        let url: RequestUrl = RequestUrl::new(request);
+        let mut json: Map<String, Value> = Map::new();
+        json.insert(String::from("method"), json!(request.method));
+        json.insert(String::from("url"), json!(absolute_url(request, md)));
+        json.insert(String::from("httpVersion"), json!(request.version));
+        json.insert(String::from("cookies"), json!([]));
+        json.insert(String::from("headers"), headers_json(&request.headers));
+        json.insert(String::from("queryString"), Value::Array(url.query.iter()
+            .map(|(name, value): &(String, String)| json!({"name": name, "value": value}))
+            .collect()));
+        if self.request_body.size > 0 {
+            let mut post_data: Map<String, Value> = Map::new();
+            post_data.insert(String::from("mimeType"), json!(request.headers.get("content-type").unwrap_or("")));
+            if let Some(text) = self.request_body.text(rule) {
+                post_data.insert(String::from("text"), json!(text));
+                // HAR has no encoding for request bodies, the field mirrors the one on response content
+                if rule.bodies == HarBodies::BASE64 {
+                    post_data.insert(String::from("_encoding"), json!("base64"));
+                }
+                if self.request_body.is_truncated() {
+                    post_data.insert(String::from("comment"), json!(format!("Truncated to {} bytes", self.request_body.bytes.len())));
+                }
+            }
+            json.insert(String::from("postData"), Value::Object(post_data));
+        }
+        json.insert(String::from("headersSize"), json!(request.to_bytes().len()));
+        json.insert(String::from("bodySize"), json!(self.request_body.size));
+        Value::Object(json)
+    }
+    fn response_json(&self, rule: &HarRule) -> Value {
+        let response: HttpResponseHead = self.response.clone()
+            .unwrap_or_else(|| HttpResponseHead::new(String::from("HTTP/1.1"), 0, String::new()));
+        let mut content: Map<String, Value> = Map::new();
+        content.insert(String::from("size"), json!(self.response_body.size));
+        content.insert(String::from("mimeType"), json!(response.headers.get("content-type").unwrap_or("")));
+        if let Some(text) = self.response_body.text(rule).filter(|_| self.response_body.size > 0) {
+            content.insert(String::from("text"), json!(text));
+            if rule.bodies == HarBodies::BASE64 {
+                content.insert(String::from("encoding"), json!("base64"));
+            }
+            if self.response_body.is_truncated() {
+                content.insert(String::from("comment"), json!(format!("Truncated to {} bytes", self.response_body.bytes.len())));
+            }
+        }
+        json!({
+            "status": response.status,
+            "statusText": response.reason,
+            "httpVersion": response.version,
+            "cookies": [],
+            "headers": headers_json(&response.headers),
+            "content": Value::Object(content),
+            "redirectURL": response.headers.get("location").unwrap_or(""),
+            "headersSize": response.to_bytes().len(),
+            "bodySize": self.response_body.size,
+        })
+    }
+}
+
+fn millis_between(from: Instant, to: Instant) -> f64 {
+    to.saturating_duration_since(from).as_micros() as f64 / 1000.0
+}
+
+fn headers_json(headers: &HttpHeaders) -> Value {
+    Value::Array(headers.iter()
+        .map(|header: &HttpHeader| json!({"name": header.name, "value": header.value}))
+        .collect())
+}
+
+// Origin-form targets are completed with the Host header, or the upstream when there is none
+fn absolute_url(request: &HttpRequestHead, md: &RequestMetadata) -> String {
+    if request.target.contains("://") {
+        return request.target.clone();
+    }
+    let host: String = match request.headers.get("host") {
+        Some(host) => String::from(host),
+        None => md.upstream_addr.map_or(String::new(), |upstream| upstream.to_string()),
+    };
+    if request.method.eq_ignore_ascii_case("CONNECT") {
+        return format!("http://{}", request.target);
+    }
+    format!("http://{}{}", host, request.target)
+}
+
+fn creator() -> Value {
+    json!({"name": "tcp_proxy", "version": env!("CARGO_PKG_VERSION")})
+}
+
+pub fn document(entries: Vec<Value>) -> Value {
+    json!({
+        "log": {
+            "version": "1.2",
+            "creator": creator(),
+            "entries": entries,
+        }
+    })
+}
+
+// ---- BindingHar ----
+
+// Keeps a binding's latest exchanges in memory and hands every exchange to the file writer when one is configured
+pub(crate) struct BindingHar {
+    pub rule: HarRule,
+    entries: Mutex<VecDeque<Value>>,
+    writer: Option<SyncSender<Value>>,
+    logger: Logger,
+}
+
+impl BindingHar {
+    pub fn new(binding: &str, rule: Option<&HarRule>, logger: &Logger) -> Option<BindingHar> {
+        let rule: HarRule = rule?.clone();
+        let writer: Option<SyncSender<Value>> = if rule.directory.is_empty() {
+            None
+        } else {
+            let (sender, receiver): (SyncSender<Value>, Receiver<Value>) = mpsc::sync_channel(HAR_QUEUE_ENTRIES);
+            let file_writer: HarFileWriter = HarFileWriter::new(binding, &rule, logger);
+            thread::spawn(move || file_writer.run(receiver));
+            Some(sender)
+        };
+        Some(BindingHar {
+            rule,
+            entries: Mutex::new(VecDeque::new()),
+            writer,
+            logger: logger.clone(),
+        })
+    }
+    pub fn record(&self, entry: Value) {
+        if let Some(writer) = self.writer.as_ref() {
+            if let Err(TrySendError::Full(_)) = writer.try_send(entry.clone()) {
+                info!(self.logger, "HAR Writer is behind, an exchange was left out of the file");
+            }
+        }
+        if self.rule.buffer == 0 {
+            return;
+        }
+        let mut entries: MutexGuard<VecDeque<Value>> = self.entries.lock().unwrap();
+        if entries.len() == self.rule.buffer {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+    // The latest exchanges as a HAR document, oldest first, 0 returns everything buffered
+    pub fn last(&self, count: usize) -> Value {
+        let entries: MutexGuard<VecDeque<Value>> = self.entries.lock().unwrap();
+        let skip: usize = if count == 0 { 0 } else { entries.len().saturating_sub(count) };
+        document(entries.iter().skip(skip).cloned().collect())
+    }
+}
+
+// ---- HarFileWriter ----
+
+// Appends a binding's entries to rotating HAR files, each file is a valid document after every write
+struct HarFileWriter {
+    rule: HarRule,
+    prefix: String,
+    file: Option<File>,
+    file_bytes: u64,
+    file_entries: usize,
+    files: VecDeque<PathBuf>, // Oldest first, the last is being written
+    logger: Logger,
+}
+
+impl HarFileWriter {
+    fn new(binding: &str, rule: &HarRule, logger: &Logger) -> HarFileWriter {
+        let prefix: String = format!("{}_", binding);
+        HarFileWriter {
+            rule: rule.clone(),
+            files: HarFileWriter::existing_files(rule.directory.as_str(), prefix.as_str()),
+            prefix,
+            file: None,
+            file_bytes: 0,
+            file_entries: 0,
+            logger: logger.clone(),
+        }
+    }
+    // Files left by an earlier run count towards max_files
+    fn existing_files(directory: &str, prefix: &str) -> VecDeque<PathBuf> {
+        let mut files: Vec<PathBuf> = match fs::read_dir(directory) {
+            Ok(entries) => entries.filter_map(Result::ok)
+                .map(|entry: DirEntry| entry.path())
+                .filter(|path: &PathBuf| path.file_name().and_then(|name| name.to_str()).map_or(false, |name: &str| {
+                    name.starts_with(prefix) && name.ends_with(HAR_FILE_EXTENSION)
+                }))
+                .collect(),
+            Err(_) => vec![],
+        };
+        // Names sort by creation time
+        files.sort();
+        files.into()
+    }
+    fn run(mut self, receiver: Receiver<Value>) {
+        if let Err(e) = fs::create_dir_all(self.rule.directory.as_str()) {
+            info!(self.logger, "HAR Unable to create directory [{}], exchanges will not be written: {}", self.rule.directory, e);
+            receiver.iter().for_each(drop);
+            return;
+        }
+        while let Ok(entry) = receiver.recv() {
+            self.write(entry);
+        }
+    }
+    fn write(&mut self, entry: Value) {
+        let encoded: Vec<Byte> = entry.to_string().into_bytes();
+        if self.file.is_none() || (self.file_entries > 0 && self.file_bytes + encoded.len() as u64 + 2 > self.rule.max_file_bytes) {
+            self.rotate();
+        }
+        let mut appended: Vec<Byte> = Vec::with_capacity(encoded.len() + HAR_FILE_TAIL.len() + 2);
+        appended.extend_from_slice(if self.file_entries == 0 { b"\n" } else { b",\n" });
+        appended.extend(encoded);
+        appended.extend_from_slice(HAR_FILE_TAIL);
+        match self.append(appended.as_slice()) {
+            Ok(_) => self.file_entries += 1,
+            Err(e) => info!(self.logger, "HAR Failed to write exchange: {}", e),
+        }
+    }
+    // Writes over the current tail, which the new bytes end with
+    fn append(&mut self, bytes: &[Byte]) -> io::Result<()> {
+        let file: &mut File = self.file.as_mut().ok_or(io::ErrorKind::NotFound)?;
+        let position: u64 = self.file_bytes.saturating_sub(HAR_FILE_TAIL.len() as u64);
+        file.seek(SeekFrom::Start(position))?;
+        file.write_all(bytes)?;
+        self.file_bytes = position + bytes.len() as u64;
+        Ok(())
+    }
+    fn rotate(&mut self) {
+        self.file = None;
+        let path: PathBuf = Path::new(self.rule.directory.as_str()).join(format!(
+            "{}{}{}",
+            self.prefix,
+            Utc::now().format("%Y%m%d-%H%M%S%.6f"),
+            HAR_FILE_EXTENSION
+        ));
+        let mut file: File = match File::create(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                info!(self.logger, "HAR Unable to create [{}]: {}", path.display(), e);
+                return;
+            },
+        };
+        // Everything up to the entries array, so the document stays valid as entries are appended
+        let mut header: Vec<Byte> = format!("{{\"log\":{{\"version\":\"1.2\",\"creator\":{},\"entries\":[", creator()).into_bytes();
+        header.extend_from_slice(HAR_FILE_TAIL);
+        if let Err(e) = file.write_all(header.as_slice()) {
+            info!(self.logger, "HAR Unable to write [{}]: {}", path.display(), e);
+            return;
+        }
+        debug!(self.logger, "HAR Writing exchanges to [{}]", path.display());
+        self.file = Some(file);
+        self.file_bytes = header.len() as u64;
+        self.file_entries = 0;
+        self.files.push_back(path);
+        self.prune();
+    }
+    fn prune(&mut self) {
+        if self.rule.max_files == 0 {
+            return;
+        }
+        while self.files.len() > self.rule.max_files {
+            let path: PathBuf = self.files.pop_front().unwrap();
+            match fs::remove_file(&path) {
+                Ok(_) => debug!(self.logger, "HAR Removed [{}]", path.display()),
+                Err(e) => info!(self.logger, "HAR Unable to remove [{}]: {}", path.display(), e),
+            }
+        }
+    }
+}
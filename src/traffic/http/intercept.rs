@@ -0,0 +1,423 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::time::Duration;
+use base64::Engine;
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+use slog::Logger;
+
+use crate::traffic::bindingset::{InterceptExpiry, InterceptRule, Rule};
+use crate::traffic::enforcer::CompiledRule;
+use crate::traffic::http::message::{self, HttpHeader, HttpHeaders, HttpRequestHead, HttpResponseHead};
+use crate::traffic::http::stream::{HttpBodyFraming, HttpStreamEvent};
+
+type Byte = u8;
+
+// Unique across bindings so a held message can be addressed by its id alone
+static NEXT_INTERCEPT_ID: AtomicU64 = AtomicU64::new(1);
+
+// ---- InterceptedMessage ----
+
+#[derive(Clone)]
+pub(crate) enum InterceptedHead {
+    REQUEST(HttpRequestHead),
+    RESPONSE(HttpResponseHead),
+}
+
+#[derive(Clone)]
+pub(crate) struct InterceptedMessage {
+    pub head: InterceptedHead,
+    pub body: Vec<Byte>,
+    pub request: Option<HttpRequestHead>, // The request a held response answers
+    framing: HttpBodyFraming, // As the message arrived, decides how an edited body is framed
+}
+
+impl InterceptedMessage {
+    pub fn request(head: HttpRequestHead) -> InterceptedMessage {
+        InterceptedMessage {
+            framing: HttpBodyFraming::of_request(&head),
+            head: InterceptedHead::REQUEST(head),
+            body: vec![],
+            request: None,
+        }
+    }
+    pub fn response(head: HttpResponseHead, request: Option<HttpRequestHead>, framing: HttpBodyFraming) -> InterceptedMessage {
+        InterceptedMessage {
+            head: InterceptedHead::RESPONSE(head),
+            body: vec![],
+            request,
+            framing,
+        }
+    }
+    pub fn push_body(&mut self, content: &[Byte]) {
+        self.body.extend_from_slice(content);
+    }
+    fn headers_mut(&mut self) -> &mut HttpHeaders {
+        match &mut self.head {
+            InterceptedHead::REQUEST(head) => &mut head.headers,
+            InterceptedHead::RESPONSE(head) => &mut head.headers,
+        }
+    }
+    // The message as stream events again, Content-Length follows an edited body
+    pub fn into_events(mut self) -> Vec<HttpStreamEvent> {
+        let (is_request, chunked): (bool, bool) = (
+            matches!(self.head, InterceptedHead::REQUEST(_)),
+            self.framing == HttpBodyFraming::CHUNKED,
+        );
+        // Responses without a body (HEAD, 204, 304) keep their headers and stay empty
+        if !is_request && self.framing == HttpBodyFraming::NONE {
+            self.body = vec![];
+        }
+        let length: usize = self.body.len();
+        if matches!(self.framing, HttpBodyFraming::LENGTH(_)) || (is_request && !chunked && length > 0) {
+            self.headers_mut().set("Content-Length", length.to_string());
+        }
+        let mut events: Vec<HttpStreamEvent> = vec![match self.head {
+            InterceptedHead::REQUEST(head) => HttpStreamEvent::REQUEST(head),
+            InterceptedHead::RESPONSE(head) => HttpStreamEvent::RESPONSE(head),
+        }];
+        if length > 0 {
+            events.push(HttpStreamEvent::BODY(self.body));
+        }
+        events.push(HttpStreamEvent::END);
+        events
+    }
+    pub fn to_json(&self) -> Value {
+        let mut json: Map<String, Value> = Map::new();
+        let headers: &HttpHeaders = match &self.head {
+            InterceptedHead::REQUEST(head) => {
+                json.insert(String::from("direction"), json!("REQUEST"));
+                json.insert(String::from("method"), json!(head.method));
+                json.insert(String::from("target"), json!(head.target));
+                json.insert(String::from("version"), json!(head.version));
+                &head.headers
+            },
+            InterceptedHead::RESPONSE(head) => {
+                json.insert(String::from("direction"), json!("RESPONSE"));
+                json.insert(String::from("status"), json!(head.status));
+                json.insert(String::from("reason"), json!(head.reason));
+                json.insert(String::from("version"), json!(head.version));
+                if let Some(request) = self.request.as_ref() {
+                    json.insert(String::from("request"), json!(format!("{} {}", request.method, request.target)));
+                }
+                &head.headers
+            },
+        };
+        json.insert(String::from("headers"), Value::Array(headers.iter()
+            .map(|header: &HttpHeader| json!({"name": header.name, "value": header.value}))
+            .collect()));
+        match std::str::from_utf8(self.body.as_slice()) {
+            Ok(text) => { json.insert(String::from("body"), json!(text)); },
+            Err(_) => {
+                json.insert(String::from("body"), json!(base64::engine::general_purpose::STANDARD.encode(self.body.as_slice())));
+                json.insert(String::from("encoding"), json!("base64"));
+            },
+        }
+        Value::Object(json)
+    }
+    // Fields left out of the edits keep their values
+    fn edit(&mut self, edits: &Value) -> Result<(), String> {
+        match &mut self.head {
+            InterceptedHead::REQUEST(head) => {
+                if let Some(method) = token_field(edits, "method")? {
+                    head.method = method;
+                }
+                if let Some(target) = token_field(edits, "target")? {
+                    head.target = target;
+                }
+            },
+            InterceptedHead::RESPONSE(head) => {
+                if let Some(status) = status_field(edits)? {
+                    head.status = status;
+                    head.reason = String::from(message::reason_phrase(status));
+                }
+                if let Some(reason) = line_field(edits, "reason")? {
+                    head.reason = reason;
+                }
+            },
+        }
+        edit_headers(self.headers_mut(), edits)?;
+        if let Some(body) = body_field(edits)? {
+            self.body = body;
+        }
+        Ok(())
+    }
+}
+
+fn string_field(edits: &Value, name: &str) -> Result<Option<String>, String> {
+    match edits.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) if !value.is_empty() => Ok(Some(value.clone())),
+        Some(_) => Err(format!("{} must be a non-empty string", name)),
+    }
+}
+
+// Written into the start line, a line break would let an edit add headers of its own
+fn line_field(edits: &Value, name: &str) -> Result<Option<String>, String> {
+    match string_field(edits, name)? {
+        Some(value) if value.contains(['\r', '\n']) => Err(format!("{} cannot contain line breaks", name)),
+        value => Ok(value),
+    }
+}
+
+// Method and target are separated by spaces in the start line, so they cannot hold any
+fn token_field(edits: &Value, name: &str) -> Result<Option<String>, String> {
+    match line_field(edits, name)? {
+        Some(value) if value.contains(' ') => Err(format!("{} cannot contain spaces", name)),
+        value => Ok(value),
+    }
+}
+
+fn status_field(edits: &Value) -> Result<Option<u16>, String> {
+    match edits.get("status") {
+        None | Some(Value::Null) => Ok(None),
+        Some(status) => status.as_u64().filter(|status: &u64| *status >= 200 && *status <= 599)
+            .map(|status: u64| Some(status as u16))
+            .ok_or(String::from("status must be between 200 and 599")),
+    }
+}
+
+// "headers" replaces every header, "set_headers" and "remove_headers" change single ones
+fn edit_headers(headers: &mut HttpHeaders, edits: &Value) -> Result<(), String> {
+    if let Some(replacement) = edits.get("headers").filter(|value: &&Value| !value.is_null()) {
+        let mut replaced: HttpHeaders = HttpHeaders::new();
+        for header in replacement.as_array().ok_or("headers must be an array")? {
+            match (header.get("name").and_then(Value::as_str), header.get("value").and_then(Value::as_str)) {
+                (Some(name), Some(value)) => replaced.append(name, String::from(header_value(name, value)?)),
+                _ => return Err(String::from("headers must each have a name and a value")),
+            }
+        }
+        *headers = replaced;
+    }
+    if let Some(set) = edits.get("set_headers").filter(|value: &&Value| !value.is_null()) {
+        for (name, value) in set.as_object().ok_or("set_headers must be an object")? {
+            let value: &str = value.as_str().ok_or("set_headers values must be strings")?;
+            headers.set(name.as_str(), String::from(header_value(name.as_str(), value)?));
+        }
+    }
+    if let Some(remove) = edits.get("remove_headers").filter(|value: &&Value| !value.is_null()) {
+        for name in remove.as_array().ok_or("remove_headers must be an array")? {
+            headers.remove(name.as_str().ok_or("remove_headers must be names")?);
+        }
+    }
+    Ok(())
+}
+
+fn header_value<'a>(name: &str, value: &'a str) -> Result<&'a str, String> {
+    if !message::valid_header_name(name) {
+        return Err(format!("invalid header name [{}]", name));
+    }
+    match value.contains(['\r', '\n']) {
+        true => Err(format!("header [{}] cannot contain line breaks", name)),
+        false => Ok(value),
+    }
+}
+
+fn body_field(edits: &Value) -> Result<Option<Vec<Byte>>, String> {
+    if let Some(body) = string_field(edits, "body_base64")? {
+        return base64::engine::general_purpose::STANDARD.decode(body.as_bytes())
+            .map(Some)
+            .map_err(|e| format!("body_base64 is not base64: {}", e));
+    }
+    match edits.get("body") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(body)) => Ok(Some(body.clone().into_bytes())),
+        Some(_) => Err(String::from("body must be a string")),
+    }
+}
+
+// ---- InterceptDecision ----
+
+pub(crate) enum InterceptDecision {
+    FORWARD(InterceptedMessage), // Possibly edited
+    RESPOND(HttpResponseHead, Vec<Byte>), // Held requests only, the client is answered and nothing is forwarded
+    DROP, // The connection is closed
+}
+
+// ---- BindingIntercept ----
+
+struct HeldMessage {
+    connection: uuid::Uuid,
+    held_at: String,
+    message: InterceptedMessage,
+    sender: SyncSender<InterceptDecision>,
+}
+
+// Holds a binding's matching messages until they are decided on over the admin API
+pub(crate) struct BindingIntercept {
+    pub rule: InterceptRule,
+    binding: String,
+    requests: Option<CompiledRule>,
+    responses: Option<CompiledRule>,
+    held: Mutex<BTreeMap<u64, HeldMessage>>,
+    logger: Logger,
+}
+
+impl BindingIntercept {
+    pub fn new(binding: &str, rule: Option<&InterceptRule>, logger: &Logger) -> io::Result<Option<BindingIntercept>> {
+        let rule: &InterceptRule = match rule {
+            Some(rule) => rule,
+            None => return Ok(None),
+        };
+        let compile = |rule: &Rule| CompiledRule::new(rule, logger);
+        Ok(Some(BindingIntercept {
+            rule: rule.clone(),
+            binding: String::from(binding),
+            requests: rule.requests.as_ref().map(compile).transpose()?,
+            responses: rule.responses.as_ref().map(compile).transpose()?,
+            held: Mutex::new(BTreeMap::new()),
+            logger: logger.clone(),
+        }))
+    }
+    pub fn selects_request(&self, head: &HttpRequestHead) -> bool {
+        self.requests.as_ref().map_or(false, |rule: &CompiledRule| rule.matches(&head.headers, Some(head), head.version.as_str()))
+    }
+    pub fn selects_response(&self, head: &HttpResponseHead, request: Option<&HttpRequestHead>) -> bool {
+        self.responses.as_ref().map_or(false, |rule: &CompiledRule| rule.matches(&head.headers, request, head.version.as_str()))
+    }
+    // Blocks until the message is decided on or the timeout passes, so no lock may be held by the caller.
+    // Past "max_held" the message is not held, it gets the timeout's decision straight away
+    pub fn wait(&self, connection: uuid::Uuid, message: &InterceptedMessage) -> InterceptDecision {
+        let id: u64 = NEXT_INTERCEPT_ID.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver): (SyncSender<InterceptDecision>, Receiver<InterceptDecision>) = mpsc::sync_channel(1);
+        {
+            let mut held: MutexGuard<BTreeMap<u64, HeldMessage>> = self.held.lock().unwrap();
+            if held.len() >= self.rule.max_held {
+                drop(held);
+                info!(self.logger, "INTERCEPT OVERFLOW [{}] [{}] [Intercept: {}] [Held: {}]", connection, self.binding, id, self.rule.max_held);
+                return self.expire(message);
+            }
+            held.insert(id, HeldMessage {
+                connection,
+                held_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                message: message.clone(),
+                sender,
+            });
+        }
+        info!(self.logger, "INTERCEPT HELD [{}] [{}] [Intercept: {}]", connection, self.binding, id);
+        match receiver.recv_timeout(Duration::from_millis(self.rule.timeout_ms)) {
+            Ok(decision) => decision,
+            Err(_) => {
+                // A decision can still arrive between the timeout and the message leaving the queue
+                if self.held.lock().unwrap().remove(&id).is_none() {
+                    if let Ok(decision) = receiver.recv() {
+                        return decision;
+                    }
+                }
+                info!(self.logger, "INTERCEPT EXPIRED [{}] [{}] [Intercept: {}]", connection, self.binding, id);
+                self.expire(message)
+            },
+        }
+    }
+    fn expire(&self, message: &InterceptedMessage) -> InterceptDecision {
+        match self.rule.on_timeout {
+            InterceptExpiry::FORWARD => InterceptDecision::FORWARD(message.clone()),
+            InterceptExpiry::DROP => InterceptDecision::DROP,
+        }
+    }
+    // Oldest first
+    pub fn held(&self) -> Vec<Value> {
+        let held: MutexGuard<BTreeMap<u64, HeldMessage>> = self.held.lock().unwrap();
+        held.iter().map(|(id, held): (&u64, &HeldMessage)| self.held_json(*id, held)).collect()
+    }
+    pub fn get(&self, id: u64) -> Option<Value> {
+        let held: MutexGuard<BTreeMap<u64, HeldMessage>> = self.held.lock().unwrap();
+        held.get(&id).map(|held: &HeldMessage| self.held_json(id, held))
+    }
+    // None when the message is not held here, an error leaves it held
+    pub fn decide(&self, id: u64, decision: &Value) -> Option<Result<String, String>> {
+        let mut held: MutexGuard<BTreeMap<u64, HeldMessage>> = self.held.lock().unwrap();
+        let message: &HeldMessage = held.get(&id)?;
+        let action: String = decision.get("action").and_then(Value::as_str).unwrap_or("FORWARD").to_ascii_uppercase();
+        let is_request: bool = matches!(message.message.head, InterceptedHead::REQUEST(_));
+        let decided: Result<InterceptDecision, String> = match action.as_str() {
+            "DROP" => Ok(InterceptDecision::DROP),
+            "RESPOND" if is_request => {
+                let mut response: HttpResponseHead = HttpResponseHead::local(200);
+                Ok(status_field(decision)).and_then(|status: Result<Option<u16>, String>| {
+                    if let Some(status) = status? {
+                        response = HttpResponseHead::local(status);
+                    }
+                    edit_headers(&mut response.headers, decision)?;
+                    Ok(InterceptDecision::RESPOND(response, body_field(decision)?.unwrap_or_default()))
+                })
+            },
+            // Answering a held response is editing it
+            "FORWARD" | "RESPOND" => {
+                let mut edited: InterceptedMessage = message.message.clone();
+                edited.edit(decision).map(|_| InterceptDecision::FORWARD(edited))
+            },
+            _ => Err(format!("Unknown action {}", action)),
+        };
+        Some(decided.map(|decided: InterceptDecision| {
+            let message: HeldMessage = held.remove(&id).unwrap();
+            info!(self.logger, "INTERCEPT DECIDED [{}] [{}] [Intercept: {}] [{}]", message.connection, self.binding, id, action);
+            let _ = message.sender.send(decided);
+            action
+        }))
+    }
+    fn held_json(&self, id: u64, held: &HeldMessage) -> Value {
+        let mut json: Value = held.message.to_json();
+        json["id"] = json!(id);
+        json["binding"] = json!(self.binding);
+        json["connection"] = json!(held.connection.to_string());
+        json["held_at"] = json!(held.held_at);
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intercept(max_held: usize) -> BindingIntercept {
+        let mut rule: InterceptRule = InterceptRule::new();
+        rule.max_held = max_held;
+        rule.timeout_ms = 10;
+        BindingIntercept::new("api", Some(&rule), &Logger::root(slog::Discard, o!())).unwrap().unwrap()
+    }
+
+    fn hold(intercept: &BindingIntercept, id: u64) -> Receiver<InterceptDecision> {
+        let (sender, receiver): (SyncSender<InterceptDecision>, Receiver<InterceptDecision>) = mpsc::sync_channel(1);
+        let head: HttpRequestHead = HttpRequestHead::new(String::from("GET"), String::from("/"), String::from("HTTP/1.1"));
+        intercept.held.lock().unwrap().insert(id, HeldMessage {
+            connection: uuid::Uuid::new_v4(),
+            held_at: String::new(),
+            message: InterceptedMessage::request(head),
+            sender,
+        });
+        receiver
+    }
+
+    #[test]
+    fn edits_that_would_split_the_head_leave_the_message_held() {
+        let intercept: BindingIntercept = intercept(100);
+        let receiver: Receiver<InterceptDecision> = hold(&intercept, 1);
+        for edits in [
+            json!({"set_headers": {"x-a": "1\r\nSet-Cookie: evil=1"}}),
+            json!({"set_headers": {"bad name": "1"}}),
+            json!({"headers": [{"name": "x-a:", "value": "1"}]}),
+            json!({"method": "GET / HTTP/1.1\r\nHost: evil\r\n\r\nGET"}),
+            json!({"target": "/a b"}),
+            json!({"action": "RESPOND", "set_headers": {"x-a": "1\n2"}}),
+        ] {
+            assert!(matches!(intercept.decide(1, &edits), Some(Err(_))), "{}", edits);
+        }
+        assert!(intercept.get(1).is_some());
+        assert!(receiver.try_recv().is_err());
+        assert!(matches!(intercept.decide(1, &json!({"set_headers": {"x-a": "1"}})), Some(Ok(_))));
+        assert!(matches!(receiver.try_recv(), Ok(InterceptDecision::FORWARD(_))));
+    }
+
+    #[test]
+    fn messages_past_max_held_are_not_held() {
+        let intercept: BindingIntercept = intercept(1);
+        let _receiver: Receiver<InterceptDecision> = hold(&intercept, 1);
+        let head: HttpRequestHead = HttpRequestHead::new(String::from("GET"), String::from("/"), String::from("HTTP/1.1"));
+        assert!(matches!(intercept.wait(uuid::Uuid::new_v4(), &InterceptedMessage::request(head)), InterceptDecision::FORWARD(_)));
+        assert_eq!(intercept.held().len(), 1);
+    }
+}
@@ -0,0 +1,285 @@
+use std::slice::{Iter, IterMut};
+use std::str;
+
+type Byte = u8;
+
+static CRLF: &'static str = "\r\n";
+
+// ---- HttpHeader ----
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct HttpHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl HttpHeader {
+    pub fn new(name: String, value: String) -> HttpHeader {
+        HttpHeader {
+            name,
+            value,
+        }
+    }
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+}
+
+// ---- HttpHeaders ----
+
+// Header names are compared case-insensitively, original casing and ordering is kept when re-encoding
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct HttpHeaders {
+    entries: Vec<HttpHeader>,
+}
+
+impl HttpHeaders {
+    pub fn new() -> HttpHeaders {
+        HttpHeaders {
+            entries: vec![],
+        }
+    }
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|header: &&HttpHeader| header.is_named(name))
+            .map(|header: &HttpHeader| header.value.as_str())
+    }
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.entries.iter()
+            .filter(|header: &&HttpHeader| header.is_named(name))
+            .map(|header: &HttpHeader| header.value.as_str())
+            .collect()
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.iter().any(|header: &HttpHeader| header.is_named(name))
+    }
+    pub fn set(&mut self, name: &str, value: String) {
+        match self.entries.iter().position(|header: &HttpHeader| header.is_named(name)) {
+            Some(index) => {
+                self.entries[index].value = value;
+                let mut position: usize = 0;
+                self.entries.retain(|header: &HttpHeader| {
+                    let keep: bool = position <= index || !header.is_named(name);
+                    position += 1;
+                    keep
+                });
+            },
+            None => self.append(name, value),
+        }
+    }
+    pub fn append(&mut self, name: &str, value: String) {
+        self.entries.push(HttpHeader::new(String::from(name), value));
+    }
+    pub fn remove(&mut self, name: &str) -> bool {
+        let original_length: usize = self.entries.len();
+        self.entries.retain(|header: &HttpHeader| !header.is_named(name));
+        original_length != self.entries.len()
+    }
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name).iter()
+            .flat_map(|value: &&str| value.split(','))
+            .any(|value: &str| value.trim().eq_ignore_ascii_case(token))
+    }
+    pub fn iter(&self) -> Iter<'_, HttpHeader> {
+        self.entries.iter()
+    }
+    pub fn iter_mut(&mut self) -> IterMut<'_, HttpHeader> {
+        self.entries.iter_mut()
+    }
+    fn parse_lines<'a, I: Iterator<Item = &'a str>>(lines: I) -> Option<HttpHeaders> {
+        let mut headers: HttpHeaders = HttpHeaders::new();
+        for line in lines {
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with(' ') || line.starts_with('\t') {
+                // Obsolete line folding, merge into the previous header value
+                let previous: &mut HttpHeader = headers.entries.last_mut()?;
+                previous.value.push(' ');
+                previous.value.push_str(line.trim());
+                continue;
+            }
+            let separator: usize = line.find(':')?;
+            let name: &str = &line[..separator];
+            if name.is_empty() || name.ends_with(' ') {
+                return None;
+            }
+            headers.append(name, String::from(line[separator + 1..].trim()));
+        }
+        Some(headers)
+    }
+    fn write_to(&self, out: &mut String) {
+        for header in self.entries.iter() {
+            out.push_str(header.name.as_str());
+            out.push_str(": ");
+            out.push_str(header.value.as_str());
+            out.push_str(CRLF);
+        }
+        out.push_str(CRLF);
+    }
+}
+
+// ---- HttpRequestHead ----
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct HttpRequestHead {
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub headers: HttpHeaders,
+}
+
+impl HttpRequestHead {
+    pub fn new(method: String, target: String, version: String) -> HttpRequestHead {
+        HttpRequestHead {
+            method,
+            target,
+            version,
+            headers: HttpHeaders::new(),
+        }
+    }
+    pub fn parse(bytes: &[Byte]) -> Option<HttpRequestHead> {
+        let head: &str = str::from_utf8(bytes).ok()?;
+        let mut lines = head.split(CRLF);
+        let mut request_line = lines.next()?.split(' ');
+        let (method, target, version) = (request_line.next()?, request_line.next()?, request_line.next()?);
+        if method.is_empty() || target.is_empty() || !version.starts_with("HTTP/") || request_line.next().is_some() {
+            return None;
+        }
+        let mut request: HttpRequestHead = HttpRequestHead::new(
+            String::from(method),
+            String::from(target),
+            String::from(version),
+        );
+        request.headers = HttpHeaders::parse_lines(lines)?;
+        Some(request)
+    }
+    // HTTP/0.9 simple requests are only a method and target, e.g. "GET /index.html"
+    pub fn parse_simple(bytes: &[Byte]) -> Option<HttpRequestHead> {
+        let line: &str = str::from_utf8(bytes).ok()?;
+        let (method, target) = line.split_once(' ')?;
+        if method.is_empty() || !target.starts_with('/') || target.contains(' ') {
+            return None;
+        }
+        Some(HttpRequestHead::new(String::from(method), String::from(target), String::from("HTTP/0.9")))
+    }
+    // The first line of the HTTP/2 prior-knowledge connection preface parses as a request
+    pub fn is_h2_preface(&self) -> bool {
+        self.method == "PRI" && self.target == "*" && self.version == "HTTP/2.0"
+    }
+    pub fn to_bytes(&self) -> Vec<Byte> {
+        if self.version == "HTTP/0.9" {
+            return format!("{} {}{}", self.method, self.target, CRLF).into_bytes();
+        }
+        if self.is_h2_preface() {
+            return format!("{} {} {}{}{}", self.method, self.target, self.version, CRLF, CRLF).into_bytes();
+        }
+        let mut out: String = format!("{} {} {}{}", self.method, self.target, self.version, CRLF);
+        self.headers.write_to(&mut out);
+        out.into_bytes()
+    }
+}
+
+// ---- HttpResponseHead ----
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct HttpResponseHead {
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    pub headers: HttpHeaders,
+}
+
+impl HttpResponseHead {
+    pub fn new(version: String, status: u16, reason: String) -> HttpResponseHead {
+        HttpResponseHead {
+            version,
+            status,
+            reason,
+            headers: HttpHeaders::new(),
+        }
+    }
+    // A response generated by the proxy itself rather than an upstream
+    pub fn local(status: u16) -> HttpResponseHead {
+        let mut response: HttpResponseHead = HttpResponseHead::new(
+            String::from("HTTP/1.1"),
+            status,
+            String::from(reason_phrase(status)),
+        );
+        response.headers.append("Server", String::from("tcp_proxy"));
+        response
+    }
+    pub fn parse(bytes: &[Byte]) -> Option<HttpResponseHead> {
+        let head: &str = str::from_utf8(bytes).ok()?;
+        let mut lines = head.split(CRLF);
+        let mut status_line = lines.next()?.splitn(3, ' ');
+        let (version, status) = (status_line.next()?, status_line.next()?);
+        if !version.starts_with("HTTP/") || status.len() != 3 {
+            return None;
+        }
+        let mut response: HttpResponseHead = HttpResponseHead::new(
+            String::from(version),
+            status.parse::<u16>().ok()?,
+            String::from(status_line.next().unwrap_or("")),
+        );
+        response.headers = HttpHeaders::parse_lines(lines)?;
+        Some(response)
+    }
+    pub fn to_bytes(&self) -> Vec<Byte> {
+        let mut out: String = format!("{} {} {}{}", self.version, self.status, self.reason, CRLF);
+        self.headers.write_to(&mut out);
+        out.into_bytes()
+    }
+    pub fn to_bytes_with_body(&mut self, body: &[Byte]) -> Vec<Byte> {
+        self.headers.remove("transfer-encoding");
+        self.headers.set("Content-Length", body.len().to_string());
+        let mut out: Vec<Byte> = self.to_bytes();
+        out.extend_from_slice(body);
+        out
+    }
+    pub fn is_informational(&self) -> bool {
+        self.status >= 100 && self.status < 200
+    }
+}
+
+// RFC 9110 5.6.2
+pub fn is_token(input: &str) -> bool {
+    !input.is_empty() && input.bytes().all(|c: u8| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c))
+}
+
+// Field names are RFC 9110 tokens, anything else would corrupt the head it is written into
+pub fn valid_header_name(name: &str) -> bool {
+    is_token(name)
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,8 @@
+pub mod diff;
+pub mod forwarding;
+pub mod har;
+pub mod intercept;
+pub mod message;
+pub mod stream;
+pub mod transformer;
+pub mod url;
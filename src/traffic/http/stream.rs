@@ -0,0 +1,345 @@
+use crate::traffic::bindingset::TrafficDirection;
+use crate::traffic::http::message::{HttpRequestHead, HttpResponseHead, HttpHeaders};
+
+type Byte = u8;
+
+static MAX_HEAD_SIZE: usize = 64 * 1024;
+
+// ---- Enums ----
+
+pub enum HttpStreamEvent {
+    REQUEST(HttpRequestHead),
+    RESPONSE(HttpResponseHead),
+    BODY(Vec<Byte>), // Decoded body content, chunk framing is re-applied on encode
+    END, // End of the current message body
+    RAW(Vec<Byte>), // Bytes that are no longer HTTP/1.x (upgrades, tunnels, unparseable streams)
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum HttpBodyFraming {
+    NONE,
+    LENGTH(u64),
+    CHUNKED,
+    CLOSE, // Read until the connection closes
+    TUNNEL, // Everything after the head is opaque
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum ParseState {
+    HEAD,
+    LENGTH(u64),
+    CHUNKSIZE,
+    CHUNKDATA(u64),
+    CHUNKEND,
+    TRAILERS,
+    CLOSE,
+    TUNNEL,
+}
+
+impl HttpBodyFraming {
+    pub fn of_request(head: &HttpRequestHead) -> HttpBodyFraming {
+        // The rest of an HTTP/2 preface and the frames that follow are binary
+        if head.method.eq_ignore_ascii_case("CONNECT") || head.headers.contains("upgrade") || head.is_h2_preface() {
+            return HttpBodyFraming::TUNNEL;
+        }
+        HttpBodyFraming::of_headers(&head.headers).unwrap_or(HttpBodyFraming::NONE)
+    }
+    pub fn of_response(head: &HttpResponseHead, request_method: Option<&str>) -> HttpBodyFraming {
+        let method: &str = request_method.unwrap_or("");
+        if head.status == 101 || (method.eq_ignore_ascii_case("CONNECT") && head.status / 100 == 2) {
+            return HttpBodyFraming::TUNNEL;
+        }
+        if head.is_informational() || head.status == 204 || head.status == 304 || method.eq_ignore_ascii_case("HEAD") {
+            return HttpBodyFraming::NONE;
+        }
+        HttpBodyFraming::of_headers(&head.headers).unwrap_or(HttpBodyFraming::CLOSE)
+    }
+    // Framing the recipients behind the proxy could read differently from it, how requests are smuggled (RFC 9112 6.3)
+    pub fn is_ambiguous(headers: &HttpHeaders) -> bool {
+        let lengths: Vec<&str> = headers.get_all("content-length");
+        if lengths.is_empty() {
+            return false;
+        }
+        if headers.contains("transfer-encoding") {
+            return true;
+        }
+        // Repeated lengths are only tolerated when every one of them agrees
+        let mut values = lengths.iter().flat_map(|value: &&str| value.split(',')).map(|value: &str| value.trim());
+        let first: &str = values.next().unwrap_or("");
+        !first.bytes().all(|byte: Byte| byte.is_ascii_digit()) || first.parse::<u64>().is_err() || values.any(|value: &str| value != first)
+    }
+    fn of_headers(headers: &HttpHeaders) -> Option<HttpBodyFraming> {
+        if headers.has_token("transfer-encoding", "chunked") {
+            return Some(HttpBodyFraming::CHUNKED);
+        }
+        let length: u64 = headers.get("content-length")?.trim().parse::<u64>().ok()?;
+        Some(HttpBodyFraming::LENGTH(length))
+    }
+}
+
+// ---- HttpStreamParser ----
+
+// Incrementally splits one direction of a connection into HTTP/1.x message events
+pub struct HttpStreamParser {
+    direction: TrafficDirection,
+    state: ParseState,
+    buffer: Vec<Byte>,
+}
+
+impl HttpStreamParser {
+    pub fn new(direction: TrafficDirection) -> HttpStreamParser {
+        HttpStreamParser {
+            direction,
+            state: ParseState::HEAD,
+            buffer: vec![],
+        }
+    }
+    pub fn push(&mut self, input: &[Byte]) {
+        self.buffer.extend_from_slice(input);
+    }
+    // Overrides the framing derived from the last head, e.g. once the matching request method is known
+    pub fn set_framing(&mut self, framing: HttpBodyFraming) {
+        self.state = match framing {
+            HttpBodyFraming::NONE | HttpBodyFraming::LENGTH(0) => ParseState::LENGTH(0),
+            HttpBodyFraming::LENGTH(length) => ParseState::LENGTH(length),
+            HttpBodyFraming::CHUNKED => ParseState::CHUNKSIZE,
+            HttpBodyFraming::CLOSE => ParseState::CLOSE,
+            HttpBodyFraming::TUNNEL => ParseState::TUNNEL,
+        };
+    }
+    pub fn next_event(&mut self) -> Option<HttpStreamEvent> {
+        loop {
+            match self.state {
+                ParseState::HEAD => return self.next_head(),
+                ParseState::LENGTH(0) => {
+                    self.state = ParseState::HEAD;
+                    return Some(HttpStreamEvent::END);
+                },
+                ParseState::LENGTH(remaining) => {
+                    let content: Vec<Byte> = self.take_body(remaining)?;
+                    self.state = ParseState::LENGTH(remaining - content.len() as u64);
+                    return Some(HttpStreamEvent::BODY(content));
+                },
+                ParseState::CHUNKSIZE => {
+                    let line: String = self.take_line()?;
+                    let size_str: &str = line.split(';').next().unwrap_or("").trim();
+                    match u64::from_str_radix(size_str, 16) {
+                        Ok(0) => self.state = ParseState::TRAILERS,
+                        Ok(size) => self.state = ParseState::CHUNKDATA(size),
+                        Err(_) => return self.fail(),
+                    }
+                },
+                ParseState::CHUNKDATA(remaining) => {
+                    let content: Vec<Byte> = self.take_body(remaining)?;
+                    let left: u64 = remaining - content.len() as u64;
+                    self.state = if left == 0 { ParseState::CHUNKEND } else { ParseState::CHUNKDATA(left) };
+                    return Some(HttpStreamEvent::BODY(content));
+                },
+                ParseState::CHUNKEND => {
+                    if !self.take_line()?.is_empty() {
+                        return self.fail();
+                    }
+                    self.state = ParseState::CHUNKSIZE;
+                },
+                ParseState::TRAILERS => {
+                    // Trailer fields are consumed and dropped
+                    if self.take_line()?.is_empty() {
+                        self.state = ParseState::HEAD;
+                        return Some(HttpStreamEvent::END);
+                    }
+                },
+                ParseState::CLOSE => {
+                    if self.buffer.is_empty() {
+                        return None;
+                    }
+                    return Some(HttpStreamEvent::BODY(self.buffer.drain(..).collect()));
+                },
+                ParseState::TUNNEL => {
+                    if self.buffer.is_empty() {
+                        return None;
+                    }
+                    return Some(HttpStreamEvent::RAW(self.buffer.drain(..).collect()));
+                },
+            }
+        }
+    }
+    // Called once the remote has closed the stream
+    pub fn finish(&mut self) -> Option<HttpStreamEvent> {
+        match self.state {
+            ParseState::CLOSE => {
+                self.state = ParseState::HEAD;
+                Some(HttpStreamEvent::END)
+            },
+            _ if !self.buffer.is_empty() => Some(HttpStreamEvent::RAW(self.buffer.drain(..).collect())),
+            _ => None,
+        }
+    }
+    fn next_head(&mut self) -> Option<HttpStreamEvent> {
+        // Tolerate stray CRLFs between messages
+        while self.buffer.starts_with(b"\r\n") {
+            self.buffer.drain(..2);
+        }
+        // HTTP/0.9 requests end at the request line
+        if let (TrafficDirection::EGRESS, Some(line_end)) = (self.direction, find_subsequence(self.buffer.as_slice(), b"\r\n")) {
+            if let Some(head) = HttpRequestHead::parse_simple(&self.buffer[..line_end]) {
+                self.buffer.drain(..line_end + 2);
+                self.set_framing(HttpBodyFraming::NONE);
+                return Some(HttpStreamEvent::REQUEST(head));
+            }
+        }
+        let head_end: usize = match find_subsequence(self.buffer.as_slice(), b"\r\n\r\n") {
+            Some(index) => index,
+            None => {
+                if self.buffer.len() > MAX_HEAD_SIZE {
+                    return self.fail();
+                }
+                return None;
+            },
+        };
+        let head_bytes: Vec<Byte> = self.buffer.drain(..head_end + 4).collect();
+        let event: HttpStreamEvent = match self.direction {
+            TrafficDirection::EGRESS => match HttpRequestHead::parse(&head_bytes[..head_end]) {
+                Some(head) => {
+                    self.set_framing(HttpBodyFraming::of_request(&head));
+                    HttpStreamEvent::REQUEST(head)
+                },
+                None => {
+                    self.buffer.splice(0..0, head_bytes);
+                    return self.fail();
+                },
+            },
+            TrafficDirection::INGRESS => match HttpResponseHead::parse(&head_bytes[..head_end]) {
+                Some(head) => {
+                    self.set_framing(HttpBodyFraming::of_response(&head, None));
+                    HttpStreamEvent::RESPONSE(head)
+                },
+                None => {
+                    self.buffer.splice(0..0, head_bytes);
+                    return self.fail();
+                },
+            },
+        };
+        Some(event)
+    }
+    // Anything the parser cannot make sense of is passed through untouched from here on
+    fn fail(&mut self) -> Option<HttpStreamEvent> {
+        self.state = ParseState::TUNNEL;
+        Some(HttpStreamEvent::RAW(self.buffer.drain(..).collect()))
+    }
+    fn take_body(&mut self, remaining: u64) -> Option<Vec<Byte>> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let length: usize = std::cmp::min(remaining, self.buffer.len() as u64) as usize;
+        Some(self.buffer.drain(..length).collect())
+    }
+    fn take_line(&mut self) -> Option<String> {
+        let line_end: usize = find_subsequence(self.buffer.as_slice(), b"\r\n")?;
+        let line: Vec<Byte> = self.buffer.drain(..line_end + 2).collect();
+        Some(String::from_utf8_lossy(&line[..line_end]).into_owned())
+    }
+}
+
+pub fn find_subsequence(haystack: &[Byte], needle: &[Byte]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window: &[Byte]| window == needle)
+}
+
+// ---- HttpStreamEncoder ----
+
+// Serialises events back to the wire, chunk framing follows the last head that was encoded
+pub struct HttpStreamEncoder {
+    chunked: bool,
+}
+
+impl HttpStreamEncoder {
+    pub fn new() -> HttpStreamEncoder {
+        HttpStreamEncoder {
+            chunked: false,
+        }
+    }
+    pub fn encode(&mut self, event: &HttpStreamEvent) -> Vec<Byte> {
+        match event {
+            HttpStreamEvent::REQUEST(head) => {
+                self.chunked = HttpBodyFraming::of_request(head) == HttpBodyFraming::CHUNKED;
+                head.to_bytes()
+            },
+            HttpStreamEvent::RESPONSE(head) => {
+                self.chunked = head.headers.has_token("transfer-encoding", "chunked");
+                head.to_bytes()
+            },
+            HttpStreamEvent::BODY(content) => {
+                if !self.chunked {
+                    return content.clone();
+                }
+                if content.is_empty() {
+                    return vec![];
+                }
+                let mut out: Vec<Byte> = format!("{:x}\r\n", content.len()).into_bytes();
+                out.extend_from_slice(content.as_slice());
+                out.extend_from_slice(b"\r\n");
+                out
+            },
+            HttpStreamEvent::END => {
+                if !self.chunked {
+                    return vec![];
+                }
+                self.chunked = false;
+                b"0\r\n\r\n".to_vec()
+            },
+            HttpStreamEvent::RAW(content) => content.clone(),
+        }
+    }
+}
+
+impl Default for HttpStreamEncoder {
+    fn default() -> HttpStreamEncoder {
+        HttpStreamEncoder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_request(bytes: &[Byte]) -> (HttpRequestHead, Vec<HttpStreamEvent>) {
+        let mut parser: HttpStreamParser = HttpStreamParser::new(TrafficDirection::EGRESS);
+        parser.push(bytes);
+        let head: HttpRequestHead = match parser.next_event() {
+            Some(HttpStreamEvent::REQUEST(head)) => head,
+            _ => panic!("expected a request head"),
+        };
+        let mut events: Vec<HttpStreamEvent> = vec![];
+        while let Some(event) = parser.next_event() {
+            events.push(event);
+        }
+        (head, events)
+    }
+
+    #[test]
+    fn chunked_request_with_content_length_is_ambiguous() {
+        let (head, events) = parse_request(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /smuggled HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(HttpBodyFraming::is_ambiguous(&head.headers));
+        assert_eq!(HttpBodyFraming::of_request(&head), HttpBodyFraming::CHUNKED);
+        assert!(matches!(events.first(), Some(HttpStreamEvent::END)));
+    }
+
+    #[test]
+    fn conflicting_content_lengths_are_ambiguous() {
+        let (head, _) = parse_request(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nhello");
+        assert!(HttpBodyFraming::is_ambiguous(&head.headers));
+        let (head, _) = parse_request(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5, 6\r\n\r\nhello");
+        assert!(HttpBodyFraming::is_ambiguous(&head.headers));
+        let (head, _) = parse_request(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: +5\r\n\r\nhello");
+        assert!(HttpBodyFraming::is_ambiguous(&head.headers));
+    }
+
+    #[test]
+    fn single_framing_is_not_ambiguous() {
+        let (head, events) = parse_request(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello");
+        assert!(!HttpBodyFraming::is_ambiguous(&head.headers));
+        assert_eq!(HttpBodyFraming::of_request(&head), HttpBodyFraming::LENGTH(5));
+        assert!(matches!(events.as_slice(), [HttpStreamEvent::BODY(body), HttpStreamEvent::END] if body.as_slice() == b"hello"));
+        let (head, _) = parse_request(b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n");
+        assert!(!HttpBodyFraming::is_ambiguous(&head.headers));
+    }
+}
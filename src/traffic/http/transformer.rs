@@ -0,0 +1,470 @@
+use std::sync::{Arc, MutexGuard};
+use std::time::Duration;
+use slog::Logger;
+
+use crate::servlet::binding_context::BindingContext;
+use crate::servlet::request_metadata::RequestMetadata;
+use crate::traffic::bindingset::TrafficDirection;
+use crate::traffic::http::message::{HttpRequestHead, HttpResponseHead};
+use crate::traffic::enforcer::Verdict;
+use crate::traffic::faults::MessageStart;
+use crate::traffic::filter::FilterAction;
+use crate::traffic::http::diff::{CapturedResponse, ResponseComparator};
+use crate::traffic::http::har::HarExchange;
+use crate::traffic::http::intercept::{BindingIntercept, InterceptDecision, InterceptedHead, InterceptedMessage};
+use crate::traffic::mirror::{BindingMirror, MirrorChunk, MirroredRequest};
+use crate::traffic::http::stream::{HttpStreamParser, HttpStreamEncoder, HttpStreamEvent, HttpBodyFraming};
+
+use crate::if_let_some;
+
+type Byte = u8;
+
+// Applies a binding's rules to one direction of an HTTP connection as bytes pass through
+pub(crate) struct HttpStreamTransformer {
+    direction: TrafficDirection,
+    context: Arc<BindingContext>,
+    parser: HttpStreamParser,
+    encoder: HttpStreamEncoder,
+    discarding: bool, // Set while skipping the body of a request that was answered locally
+    local_responses: Vec<Byte>,
+    local_delay: Duration, // How long mocked responses ask to be held back before they are written
+    close: bool,
+    fault_scope: Option<Vec<bool>>, // Faults the message being emitted falls within
+    message_starts: Vec<MessageStart>,
+    mirroring: bool, // Set while the request being emitted is copied to the binding's mirror
+    mirrored: Vec<Byte>,
+    mirrored_requests: Vec<MirroredRequest>,
+    responses: u64, // Final responses seen so far, each answers the exchange of the same number
+    capture: Option<(u64, CapturedResponse)>, // Response being captured for comparison with the shadow's
+    har: Option<HarExchange>, // Exchange whose response is being emitted, when the binding keeps a HAR
+    intercepting: Option<InterceptedMessage>, // Message being collected to be held
+    held: Option<InterceptedMessage>, // Nothing after it is emitted until it is released
+    after_held: Vec<HttpStreamEvent>,
+    releasing: bool,
+    filtered_request: Option<HttpRequestHead>, // Message whose body is passing through the binding's filters
+    filtered_response: Option<HttpResponseHead>,
+    body_tail: Option<Vec<Byte>>, // Written by a filter at the end of a body, goes out just before it ends
+}
+
+impl HttpStreamTransformer {
+    pub fn new(direction: TrafficDirection, context: Arc<BindingContext>) -> HttpStreamTransformer {
+        HttpStreamTransformer {
+            direction,
+            context,
+            parser: HttpStreamParser::new(direction),
+            encoder: HttpStreamEncoder::new(),
+            discarding: false,
+            local_responses: vec![],
+            local_delay: Duration::ZERO,
+            close: false,
+            fault_scope: None,
+            message_starts: vec![],
+            mirroring: false,
+            mirrored: vec![],
+            mirrored_requests: vec![],
+            responses: 0,
+            capture: None,
+            har: None,
+            intercepting: None,
+            held: None,
+            after_held: vec![],
+            releasing: false,
+            filtered_request: None,
+            filtered_response: None,
+            body_tail: None,
+        }
+    }
+    pub fn logger(&self) -> &Logger {
+        &self.context.logger
+    }
+    pub fn transform(&mut self, input: &[Byte], md: &mut RequestMetadata) -> Vec<Byte> {
+        self.parser.push(input);
+        let mut output: Vec<Byte> = Vec::with_capacity(input.len());
+        self.drain(md, &mut output);
+        output
+    }
+    pub fn finish(&mut self, md: &mut RequestMetadata) -> Vec<Byte> {
+        let mut output: Vec<Byte> = vec![];
+        if let Some(event) = self.parser.finish() {
+            if let Some(event) = self.intercept(event, md) {
+                self.emit(event, md, &mut output);
+            }
+        }
+        output
+    }
+    // Blocks until the held message is decided on, the caller must not hold the metadata meanwhile
+    pub fn await_decision(&self, connection: uuid::Uuid) -> Option<InterceptDecision> {
+        let message: &InterceptedMessage = self.held.as_ref()?;
+        let intercept: &BindingIntercept = self.context.intercept.as_deref()?;
+        Some(intercept.wait(connection, message))
+    }
+    pub fn is_holding(&self) -> bool {
+        self.held.is_some()
+    }
+    // Emits the held message as decided and carries on with whatever was parsed after it
+    pub fn release(&mut self, decision: InterceptDecision, md: &mut RequestMetadata) -> Vec<Byte> {
+        let mut output: Vec<Byte> = vec![];
+        if_let_some!{held = self.held.take(), output};
+        match (decision, held.head) {
+            (InterceptDecision::FORWARD(message), _) => {
+                self.releasing = true;
+                for event in message.into_events() {
+                    self.emit(event, md, &mut output);
+                }
+                self.releasing = false;
+            },
+            (InterceptDecision::RESPOND(response, body), InterceptedHead::REQUEST(request)) => {
+                self.respond_locally(&request, response, body, md);
+            },
+            (_, _) => {
+                info!(self.context.logger, "TRAFFIC LOG [{:?}] [{}] [Intercepted message dropped]", self.direction, md.id);
+                self.close = true;
+                return output;
+            },
+        }
+        for event in std::mem::take(&mut self.after_held) {
+            self.emit(event, md, &mut output);
+        }
+        self.drain(md, &mut output);
+        output
+    }
+    // Stops at a held message, what follows stays in the parser until it is released.
+    // Nothing more is emitted once the connection is to be closed
+    fn drain(&mut self, md: &mut RequestMetadata, output: &mut Vec<Byte>) {
+        while self.held.is_none() && !self.close {
+            if_let_some!{event = self.parser.next_event()};
+            if let Some(event) = self.intercept(event, md) {
+                self.emit(event, md, output);
+            }
+        }
+    }
+    fn emit(&mut self, event: HttpStreamEvent, md: &mut RequestMetadata, output: &mut Vec<Byte>) {
+        if_let_some!{event = self.process_event(event, md)};
+        if let Some(scope) = self.fault_scope.take() {
+            self.message_starts.push(MessageStart { offset: output.len(), scope });
+        }
+        if let Some(tail) = self.body_tail.take() {
+            self.write(&HttpStreamEvent::BODY(tail), output);
+        }
+        self.write(&event, output);
+    }
+    fn write(&mut self, event: &HttpStreamEvent, output: &mut Vec<Byte>) {
+        let encoded: Vec<Byte> = self.encoder.encode(event);
+        if self.mirroring {
+            self.mirrored.extend_from_slice(encoded.as_slice());
+        }
+        output.extend(encoded);
+    }
+    // Collects the messages the binding intercepts, they reach the rules only once released
+    fn intercept(&mut self, event: HttpStreamEvent, md: &RequestMetadata) -> Option<HttpStreamEvent> {
+        if let Some(message) = self.intercepting.as_mut() {
+            match event {
+                HttpStreamEvent::BODY(content) => message.push_body(content.as_slice()),
+                HttpStreamEvent::END => self.held = self.intercepting.take(),
+                // The stream stopped being HTTP mid-message, what was collected is held and the rest follows it
+                other => {
+                    self.held = self.intercepting.take();
+                    self.after_held.push(other);
+                },
+            }
+            return None;
+        }
+        if_let_some!{intercept = self.context.intercept.as_ref(), Some(event)};
+        match event {
+            HttpStreamEvent::REQUEST(head) if self.direction == TrafficDirection::EGRESS
+                && HttpBodyFraming::of_request(&head) != HttpBodyFraming::TUNNEL && intercept.selects_request(&head) => {
+                self.intercepting = Some(InterceptedMessage::request(head));
+                None
+            },
+            HttpStreamEvent::RESPONSE(head) if !head.is_informational() => {
+                let request: Option<&HttpRequestHead> = md.pending_requests.front();
+                let framing: HttpBodyFraming = HttpBodyFraming::of_response(
+                    &head,
+                    request.map(|request: &HttpRequestHead| request.method.as_str()),
+                );
+                if framing == HttpBodyFraming::TUNNEL || !intercept.selects_response(&head, request) {
+                    return Some(HttpStreamEvent::RESPONSE(head));
+                }
+                self.parser.set_framing(framing);
+                self.intercepting = Some(InterceptedMessage::response(head, request.cloned(), framing));
+                None
+            },
+            other => Some(other),
+        }
+    }
+    // Responses generated by the proxy that must be written back to the client
+    pub fn take_local_responses(&mut self) -> Vec<Byte> {
+        std::mem::take(&mut self.local_responses)
+    }
+    pub fn take_local_delay(&mut self) -> Duration {
+        std::mem::replace(&mut self.local_delay, Duration::ZERO)
+    }
+    pub fn should_close(&self) -> bool {
+        self.close
+    }
+    // Requests emitted by the last transform that should also go to the mirror
+    pub fn take_mirrored(&mut self) -> MirrorChunk {
+        MirrorChunk {
+            bytes: std::mem::take(&mut self.mirrored),
+            requests: std::mem::take(&mut self.mirrored_requests),
+        }
+    }
+    // Where each message emitted by the last transform begins, for the fault injector
+    pub fn take_message_starts(&mut self) -> Vec<MessageStart> {
+        std::mem::take(&mut self.message_starts)
+    }
+    fn process_event(&mut self, event: HttpStreamEvent, md: &mut RequestMetadata) -> Option<HttpStreamEvent> {
+        match event {
+            HttpStreamEvent::REQUEST(mut head) => {
+                // Where the body ends is in doubt, so is where the next request starts, neither can be forwarded
+                if HttpBodyFraming::is_ambiguous(&head.headers) {
+                    info!(self.context.logger, "TRAFFIC LOG [EGRESS] [{}] [Ambiguous request framing]", md.id);
+                    let mut response: HttpResponseHead = HttpResponseHead::local(400);
+                    response.headers.set("Connection", String::from("close"));
+                    self.discarding = true;
+                    self.mirroring = false;
+                    self.respond_locally(&head, response, vec![], md);
+                    return None;
+                }
+                if !head.is_h2_preface() {
+                    self.context.forwarding.apply(&mut head, &md.client_addr);
+                }
+                let verdict: Verdict = match self.context.enforcer.enforce_egress(&mut head, &md.template_variables()) {
+                    Verdict::FORWARD if !head.is_h2_preface() => match self.filter_request(&mut head, md) {
+                        Some(verdict) => verdict,
+                        None => return None,
+                    },
+                    verdict => verdict,
+                };
+                let verdict: Verdict = match verdict {
+                    // Bindings without an upstream only serve what their rules answer
+                    Verdict::FORWARD if self.context.rule.to.is_empty() => Verdict::RESPOND(HttpResponseHead::local(404), vec![]),
+                    verdict => verdict,
+                };
+                if !head.is_h2_preface() {
+                    self.context.monitor.verdict(md.id, &head, &verdict);
+                }
+                match verdict {
+                    Verdict::FORWARD => {
+                        self.discarding = false;
+                        md.pending_requests.push_back(head.clone());
+                        md.exchanges += 1;
+                        self.mirroring = self.context.mirror.as_ref().map_or(false, |mirror: &BindingMirror| mirror.selects(&head));
+                        if self.mirroring {
+                            self.mirrored_requests.push(MirroredRequest { exchange: md.exchanges, method: head.method.clone() });
+                            if let Some(comparator) = md.comparator.as_ref() {
+                                comparator.lock().unwrap().expect(md.exchanges, &head);
+                            }
+                        }
+                        if self.context.har.is_some() && !head.is_h2_preface() {
+                            md.har_exchanges.push_back(HarExchange::new(md.exchanges, &head));
+                        }
+                        self.fault_scope = self.context.faults.scope(self.direction, &head.headers, Some(&head), head.version.as_str());
+                        Some(HttpStreamEvent::REQUEST(head))
+                    },
+                    Verdict::RESPOND(response, body) => {
+                        self.discarding = true;
+                        self.mirroring = false;
+                        self.respond_locally(&head, response, body, md);
+                        None
+                    },
+                    Verdict::MOCK(response, body, delay) => {
+                        self.discarding = true;
+                        self.mirroring = false;
+                        self.local_delay += delay;
+                        self.respond_locally(&head, response, body, md);
+                        None
+                    },
+                }
+            },
+            HttpStreamEvent::RESPONSE(mut head) => {
+                // Interim responses share the request with the final response that follows them
+                let request: Option<HttpRequestHead> = if head.is_informational() && head.status != 101 {
+                    md.pending_requests.front().cloned()
+                } else {
+                    md.pending_requests.pop_front()
+                };
+                let framing: HttpBodyFraming = HttpBodyFraming::of_response(
+                    &head,
+                    request.as_ref().map(|request: &HttpRequestHead| request.method.as_str()),
+                );
+                // A released response was framed when it was held
+                if !self.releasing {
+                    self.parser.set_framing(framing);
+                }
+                md.response_in_progress = !head.is_informational();
+                if !head.is_informational() || head.status == 101 {
+                    self.responses += 1;
+                    self.capture_response(&head, framing, md);
+                }
+                self.context.enforcer.enforce_ingress(&mut head, request.as_ref(), &md.template_variables());
+                if !self.filter_response(request.as_ref(), &mut head, md) {
+                    return None;
+                }
+                if !head.is_informational() || head.status == 101 {
+                    self.start_har_response(&head, framing, md);
+                }
+                self.fault_scope = self.context.faults.scope(self.direction, &head.headers, request.as_ref(), head.version.as_str());
+                Some(HttpStreamEvent::RESPONSE(head))
+            },
+            HttpStreamEvent::BODY(mut content) if self.direction == TrafficDirection::INGRESS => {
+                if let Some((_, response)) = self.capture.as_mut() {
+                    response.push_body(content.as_slice());
+                }
+                if !self.filter_body(&mut content, false, md) {
+                    return None;
+                }
+                if let (Some(exchange), Some(har)) = (self.har.as_mut(), self.context.har.as_ref()) {
+                    exchange.push_response_body(content.as_slice(), &har.rule);
+                }
+                Some(HttpStreamEvent::BODY(content))
+            },
+            HttpStreamEvent::END if self.direction == TrafficDirection::INGRESS => {
+                if !self.filter_body_end(md) {
+                    return None;
+                }
+                if let (Some(exchange), Some(har), Some(tail)) = (self.har.as_mut(), self.context.har.as_ref(), self.body_tail.as_ref()) {
+                    exchange.push_response_body(tail.as_slice(), &har.rule);
+                }
+                md.response_in_progress = false;
+                if let (Some((exchange, response)), Some(comparator)) = (self.capture.take(), md.comparator.as_ref()) {
+                    comparator.lock().unwrap().record_primary(exchange, response);
+                }
+                self.finish_har_response(md);
+                Some(HttpStreamEvent::END)
+            },
+            _ if self.discarding => None,
+            HttpStreamEvent::BODY(mut content) => {
+                if !self.filter_body(&mut content, false, md) {
+                    return None;
+                }
+                if let (Some(exchange), Some(har)) = (md.har_exchanges.back_mut(), self.context.har.as_ref()) {
+                    if exchange.exchange == md.exchanges {
+                        exchange.push_request_body(content.as_slice(), &har.rule);
+                    }
+                }
+                Some(HttpStreamEvent::BODY(content))
+            },
+            HttpStreamEvent::END => {
+                if !self.filter_body_end(md) {
+                    return None;
+                }
+                if let Some(exchange) = md.har_exchanges.back_mut() {
+                    if exchange.exchange == md.exchanges {
+                        if let (Some(har), Some(tail)) = (self.context.har.as_ref(), self.body_tail.as_ref()) {
+                            exchange.push_request_body(tail.as_slice(), &har.rule);
+                        }
+                        exchange.request_sent();
+                    }
+                }
+                Some(HttpStreamEvent::END)
+            },
+            other => Some(other),
+        }
+    }
+    // None when a filter closed the connection instead
+    fn filter_request(&mut self, head: &mut HttpRequestHead, md: &RequestMetadata) -> Option<Verdict> {
+        self.filtered_request = None;
+        if_let_some!{filtered = md.filtered.as_ref(), Some(Verdict::FORWARD)};
+        match self.context.filters.on_request_headers(&filtered.connection, head) {
+            FilterAction::CONTINUE => {
+                self.filtered_request = Some(head.clone());
+                Some(Verdict::FORWARD)
+            },
+            FilterAction::RESPOND(response, body) => Some(Verdict::RESPOND(response, body)),
+            FilterAction::CLOSE => {
+                self.closed_by_filter(md);
+                None
+            },
+        }
+    }
+    // False when a filter closed the connection instead
+    fn filter_response(&mut self, request: Option<&HttpRequestHead>, head: &mut HttpResponseHead, md: &RequestMetadata) -> bool {
+        self.filtered_response = None;
+        if_let_some!{filtered = md.filtered.as_ref(), true};
+        match self.context.filters.on_response_headers(&filtered.connection, request, head) {
+            FilterAction::CONTINUE => {
+                self.filtered_response = Some(head.clone());
+                true
+            },
+            _ => {
+                self.closed_by_filter(md);
+                false
+            },
+        }
+    }
+    fn filter_body(&mut self, chunk: &mut Vec<Byte>, end_of_body: bool, md: &RequestMetadata) -> bool {
+        if_let_some!{filtered = md.filtered.as_ref(), true};
+        let action: FilterAction = match (self.filtered_request.as_ref(), self.filtered_response.as_ref()) {
+            (Some(request), _) if self.direction == TrafficDirection::EGRESS => self.context.filters.on_request_body_chunk(&filtered.connection, request, chunk, end_of_body),
+            (_, Some(response)) if self.direction == TrafficDirection::INGRESS => self.context.filters.on_response_body_chunk(&filtered.connection, response, chunk, end_of_body),
+            _ => FilterAction::CONTINUE,
+        };
+        match action {
+            FilterAction::CONTINUE => true,
+            _ => {
+                self.closed_by_filter(md);
+                false
+            },
+        }
+    }
+    // Whatever the filters write into the last chunk is kept to go out before the end of the body
+    fn filter_body_end(&mut self, md: &RequestMetadata) -> bool {
+        let mut tail: Vec<Byte> = vec![];
+        if !self.filter_body(&mut tail, true, md) {
+            return false;
+        }
+        self.filtered_request = None;
+        self.filtered_response = None;
+        self.body_tail = Some(tail).filter(|tail: &Vec<Byte>| !tail.is_empty());
+        true
+    }
+    fn closed_by_filter(&mut self, md: &RequestMetadata) {
+        info!(self.context.logger, "TRAFFIC LOG [{:?}] [{}] [Closed by filter]", self.direction, md.id);
+        self.discarding = true;
+        self.close = true;
+    }
+    // Captured as the upstream sent it, the shadow's response never passes through the binding's rules
+    fn capture_response(&mut self, head: &HttpResponseHead, framing: HttpBodyFraming, md: &RequestMetadata) {
+        self.capture = None;
+        if_let_some!{comparator = md.comparator.as_ref()};
+        let comparator: MutexGuard<ResponseComparator> = comparator.lock().unwrap();
+        if framing != HttpBodyFraming::TUNNEL && comparator.is_expected(self.responses) {
+            self.capture = Some((self.responses, CapturedResponse::new(head, comparator.rule())));
+        }
+    }
+    // Recorded as the client receives it, after the binding's rules
+    fn start_har_response(&mut self, head: &HttpResponseHead, framing: HttpBodyFraming, md: &mut RequestMetadata) {
+        self.har = None;
+        if_let_some!{har = self.context.har.as_ref()};
+        while md.har_exchanges.front().map_or(false, |exchange: &HarExchange| exchange.exchange < self.responses) {
+            md.har_exchanges.pop_front();
+        }
+        if md.har_exchanges.front().map_or(true, |exchange: &HarExchange| exchange.exchange != self.responses) {
+            return;
+        }
+        let mut exchange: HarExchange = md.har_exchanges.pop_front().unwrap();
+        exchange.response(head);
+        // Nothing that follows a tunnel's head is HTTP, so the exchange ends here
+        if framing == HttpBodyFraming::TUNNEL {
+            har.record(exchange.to_entry(&har.rule, md));
+            return;
+        }
+        self.har = Some(exchange);
+    }
+    fn finish_har_response(&mut self, md: &RequestMetadata) {
+        if let (Some(exchange), Some(har)) = (self.har.take(), self.context.har.as_ref()) {
+            har.record(exchange.to_entry(&har.rule, md));
+        }
+    }
+    fn respond_locally(&mut self, request: &HttpRequestHead, mut response: HttpResponseHead, body: Vec<Byte>, md: &mut RequestMetadata) {
+        // Writing now would overtake responses the upstream still owes the client, so finish the connection instead
+        if !md.pending_requests.is_empty() || md.response_in_progress || request.headers.has_token("connection", "close")
+            || response.headers.has_token("connection", "close") {
+            response.headers.set("Connection", String::from("close"));
+            self.close = true;
+        }
+        info!(self.context.logger, "TRAFFIC LOG [EGRESS] [{}] [Answered locally: {}]", md.id, response.status);
+        self.local_responses.extend(response.to_bytes_with_body(body.as_slice()));
+    }
+}
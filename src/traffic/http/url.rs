@@ -0,0 +1,195 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::traffic::http::message::HttpRequestHead;
+
+// The parts of a request target that URL rules match against, decoded and normalised
+pub struct RequestUrl {
+    pub host: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+}
+
+impl RequestUrl {
+    pub fn new(request: &HttpRequestHead) -> RequestUrl {
+        let target: &str = request.target.as_str();
+        // Absolute-form targets (sent to proxies) carry their own authority, which takes precedence over Host
+        let (authority, path_and_query): (Option<&str>, &str) = match target.find("://") {
+            Some(index) => {
+                let rest: &str = &target[index + 3..];
+                match rest.find(|c: char| c == '/' || c == '?') {
+                    Some(end) => (Some(&rest[..end]), &rest[end..]),
+                    None => (Some(rest), "/"),
+                }
+            },
+            // Authority-form, only used by CONNECT
+            None if request.method == "CONNECT" => (Some(target), ""),
+            None => (None, target),
+        };
+        let (path, query): (&str, &str) = match path_and_query.find('?') {
+            Some(index) => (&path_and_query[..index], &path_and_query[index + 1..]),
+            None => (path_and_query, ""),
+        };
+        RequestUrl {
+            host: normalize_host(authority.or_else(|| request.headers.get("host")).unwrap_or("")),
+            path: match path {
+                "" | "*" => String::from(path),
+                _ => normalize_path(percent_decode(path, false).as_str()),
+            },
+            query: query.split('&').filter(|pair: &&str| !pair.is_empty()).map(|pair: &str| {
+                match pair.find('=') {
+                    Some(index) => (percent_decode(&pair[..index], true), percent_decode(&pair[index + 1..], true)),
+                    None => (percent_decode(pair, true), String::new()),
+                }
+            }).collect(),
+        }
+    }
+    pub fn query_values(&self, name: &str) -> Vec<&str> {
+        self.query.iter()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+}
+
+// Lowercases and strips userinfo, port and any trailing dot
+fn normalize_host(authority: &str) -> String {
+    let host: &str = match authority.rfind('@') {
+        Some(index) => &authority[index + 1..],
+        None => authority,
+    };
+    let host: &str = if host.starts_with('[') {
+        match host.find(']') {
+            Some(index) => &host[..index + 1],
+            None => host,
+        }
+    } else {
+        match host.rfind(':') {
+            Some(index) => &host[..index],
+            None => host,
+        }
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+// Invalid escapes are kept as-is, invalid UTF-8 is replaced rather than rejected
+pub fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes: &[u8] = input.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut index: usize = 0;
+    while index < bytes.len() {
+        let hex = |byte: u8| -> Option<u8> { (byte as char).to_digit(16).map(|digit: u32| digit as u8) };
+        match bytes[index] {
+            b'%' if index + 2 < bytes.len() => {
+                match (hex(bytes[index + 1]), hex(bytes[index + 2])) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high << 4 | low);
+                        index += 3;
+                        continue;
+                    },
+                    _ => decoded.push(b'%'),
+                }
+            },
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(decoded.as_slice()).into_owned()
+}
+
+// Resolves "." and ".." segments and collapses repeated slashes, ".." never climbs above the root
+pub fn normalize_path(path: &str) -> String {
+    let mut segments: Vec<&str> = vec![];
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." => { segments.pop(); },
+            segment => segments.push(segment),
+        }
+    }
+    let trailing_slash: bool = !segments.is_empty()
+        && (path.ends_with('/') || path.ends_with("/.") || path.ends_with("/.."));
+    let mut normalized: String = format!("/{}", segments.join("/"));
+    if trailing_slash {
+        normalized.push('/');
+    }
+    normalized
+}
+
+// Translates a glob into an anchored regex, "*" and "?" stop at the separator and "**" crosses it
+pub fn glob_to_regex(glob: &str, separator: char) -> String {
+    let not_separator: String = format!("[^{}]", regex::escape(separator.to_string().as_str()));
+    let mut pattern: String = String::from("^");
+    let mut chars: Peekable<Chars> = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            },
+            '*' => pattern.push_str(format!("{}*", not_separator).as_str()),
+            '?' => pattern.push_str(not_separator.as_str()),
+            c => pattern.push_str(regex::escape(c.to_string().as_str()).as_str()),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    fn url(target: &str) -> RequestUrl {
+        let mut request: HttpRequestHead = HttpRequestHead::new(String::from("GET"), String::from(target), String::from("HTTP/1.1"));
+        request.headers.set("Host", String::from("Example.com:8080"));
+        RequestUrl::new(&request)
+    }
+
+    #[test]
+    fn dot_segments_are_removed() {
+        assert_eq!(normalize_path("/a/./b/../c"), "/a/c");
+        assert_eq!(normalize_path("/a//b///c"), "/a/b/c");
+        assert_eq!(normalize_path("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(normalize_path("/a/b/.."), "/a/");
+        assert_eq!(normalize_path("/a/b/"), "/a/b/");
+        assert_eq!(normalize_path(""), "/");
+    }
+
+    #[test]
+    fn encoded_dot_segments_are_removed_after_decoding() {
+        assert_eq!(percent_decode("%2e%2E", false), "..");
+        assert_eq!(url("/public/%2e%2e/admin").path, "/admin");
+        assert_eq!(url("/public/%2E%2e/%2e/admin/").path, "/admin/");
+    }
+
+    #[test]
+    fn invalid_escapes_are_kept() {
+        assert_eq!(percent_decode("%zz", false), "%zz");
+        assert_eq!(percent_decode("a%", false), "a%");
+        assert_eq!(percent_decode("a%4", false), "a%4");
+        assert_eq!(percent_decode("%41%", false), "A%");
+        assert_eq!(percent_decode("%ff", false), "\u{FFFD}");
+    }
+
+    #[test]
+    fn plus_is_a_space_only_in_queries() {
+        assert_eq!(percent_decode("a+b", false), "a+b");
+        assert_eq!(url("/search?q=a+b%26c&flag").query_values("q"), vec!["a b&c"]);
+        assert_eq!(url("/search?q=a+b%26c&flag").query_values("flag"), vec![""]);
+    }
+
+    #[test]
+    fn globs_match_the_normalized_path() {
+        let admin: Regex = Regex::new(glob_to_regex("/admin/**", '/').as_str()).unwrap();
+        let public: Regex = Regex::new(glob_to_regex("/public/*", '/').as_str()).unwrap();
+        let path: String = url("/public/%2e%2e/admin/users").path;
+        assert!(admin.is_match(path.as_str()));
+        assert!(!public.is_match(path.as_str()));
+        assert!(!public.is_match(url("/public/a/b").path.as_str()));
+        assert_eq!(url("http://User@API.example.com.:443/a/../b?x=1").host, "api.example.com");
+        assert_eq!(url("/").host, "example.com");
+    }
+}
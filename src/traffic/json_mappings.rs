@@ -1,10 +1,86 @@
+use std::collections::BTreeMap;
 use std::vec::Vec;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
 pub struct JSONHeaderMapping {
     pub key: String,
+    #[serde(default)]
+    pub value: String,
+    #[serde(default)]
+    pub mode: String, // Converted to HeaderMatchMode
+    #[serde(default)]
+    pub all_values: bool,
+    #[serde(default)]
+    pub ignore_case: bool,
+    #[serde(default)]
+    pub negate: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JSONHeaderAction {
+    pub action: String, // Converted to HeaderActionType
+    pub key: String,
+    #[serde(default)]
     pub value: String,
+    #[serde(default)]
+    pub pattern: String, // Converted to a regex expression
+    #[serde(default)]
+    pub replacement: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JSONUrlAction {
+    pub action: String, // Converted to UrlActionType
+    #[serde(default)]
+    pub pattern: String, // Converted to a regex expression, defaults to the rule's url_wildcard
+    pub replacement: String,
+    #[serde(default)]
+    pub status: u16,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONMock {
+    #[serde(default)]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub body_file: String, // Read when the bindings are loaded, instead of "body"
+    #[serde(default)]
+    pub template: bool,
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONUrlMatch {
+    #[serde(default)]
+    pub host: String,
+    #[serde(default)]
+    pub path: String,
+    #[serde(default)]
+    pub path_mode: String, // Converted to PathMatchMode
+    #[serde(default)]
+    pub query: Vec<JSONHeaderMapping>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONRateLimit {
+    #[serde(default)]
+    pub key: String, // Converted to RateLimitKey
+    #[serde(default)]
+    pub key_header: String,
+    #[serde(default)]
+    pub requests_per_second: u32,
+    #[serde(default)]
+    pub request_burst: u32,
+    #[serde(default)]
+    pub connections_per_second: u32,
+    #[serde(default)]
+    pub connection_burst: u32,
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -15,9 +91,35 @@ pub struct JSONRule {
     #[serde(default)]
     pub url_wildcard: String, // Converted to a regex expression
     #[serde(default)]
+    pub url_match: JSONUrlMatch,
+    #[serde(default)]
     pub method_enum: String, // Converted to HTTP method enum
     #[serde(default)]
+    pub allowed_methods: Vec<String>, // Each converted to HTTP method enum
+    #[serde(default)]
+    pub denied_methods: Vec<String>,
+    #[serde(default)]
     pub version_float: f32,
+    #[serde(default)]
+    pub min_version: f32,
+    #[serde(default)]
+    pub max_version: f32,
+    #[serde(default)]
+    pub versions: Vec<f32>,
+    #[serde(default)]
+    pub version_action: String, // Converted to VersionAction
+    #[serde(default)]
+    pub rate_limit: JSONRateLimit,
+    #[serde(default)]
+    pub header_actions: Vec<JSONHeaderAction>,
+    #[serde(default)]
+    pub url_actions: Vec<JSONUrlAction>,
+    #[serde(default)]
+    pub mock: Option<JSONMock>,
+    #[serde(default)]
+    pub script: String,
+    #[serde(default)]
+    pub script_file: String,
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -28,11 +130,188 @@ pub struct JSONRuleSet {
     pub ingress: Vec<JSONRule>,
 }
 
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONForwarding {
+    // Each converted to ForwardingMode
+    #[serde(default)]
+    pub x_forwarded_for: String,
+    #[serde(default)]
+    pub x_forwarded_proto: String,
+    #[serde(default)]
+    pub x_forwarded_host: String,
+    #[serde(default)]
+    pub forwarded: String,
+    #[serde(default)]
+    pub via: String,
+    #[serde(default)]
+    pub via_pseudonym: String,
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>, // CIDR ranges or single addresses
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONProxyProtocol {
+    // Each converted to ProxyProtocolVersion
+    #[serde(default)]
+    pub accept: String,
+    #[serde(default)]
+    pub send: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONConnectionLimits {
+    #[serde(default)]
+    pub max_connections: usize,
+    #[serde(default)]
+    pub max_connections_per_ip: usize,
+    #[serde(default)]
+    pub overflow: String, // Converted to OverflowPolicy
+    #[serde(default)]
+    pub queue_timeout_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONBandwidth {
+    #[serde(default)]
+    pub egress_bytes_per_second: u64,
+    #[serde(default)]
+    pub ingress_bytes_per_second: u64,
+    #[serde(default)]
+    pub connection_egress_bytes_per_second: u64,
+    #[serde(default)]
+    pub connection_ingress_bytes_per_second: u64,
+    #[serde(default)]
+    pub burst_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONLatency {
+    #[serde(default)]
+    pub distribution: String, // Converted to LatencyDistribution
+    #[serde(default)]
+    pub ms: u64,
+    #[serde(default)]
+    pub min_ms: u64,
+    #[serde(default)]
+    pub max_ms: u64,
+    #[serde(default)]
+    pub stddev_ms: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JSONFault {
+    pub kind: String, // Converted to FaultType
+    #[serde(default)]
+    pub direction: String, // Converted to TrafficDirection
+    #[serde(default)]
+    pub probability: Option<f64>, // Defaults to always
+    #[serde(default)]
+    pub after_bytes: u64,
+    #[serde(default)]
+    pub latency: JSONLatency,
+    #[serde(default)]
+    pub corrupt_bytes: u32,
+    #[serde(default)]
+    pub stall_ms: u64,
+    #[serde(default)]
+    pub scope: Option<JSONRule>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONCompare {
+    #[serde(default)]
+    pub headers: Vec<String>,
+    #[serde(default)]
+    pub body: String, // Converted to BodyComparison
+    #[serde(default)]
+    pub ignore_fields: Vec<String>,
+    #[serde(default)]
+    pub max_body_bytes: usize,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONMirror {
+    #[serde(default)]
+    pub to: String,
+    #[serde(default)]
+    pub scope: Option<JSONRule>,
+    #[serde(default)]
+    pub compare: Option<JSONCompare>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONIntercept {
+    #[serde(default)]
+    pub requests: Option<JSONRule>,
+    #[serde(default)]
+    pub responses: Option<JSONRule>,
+    #[serde(default)]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub on_timeout: String, // Converted to InterceptExpiry
+    #[serde(default)]
+    pub max_held: usize,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONHar {
+    #[serde(default)]
+    pub directory: String,
+    #[serde(default)]
+    pub max_file_bytes: u64,
+    #[serde(default)]
+    pub max_files: Option<usize>,
+    #[serde(default)]
+    pub buffer: Option<usize>,
+    #[serde(default)]
+    pub bodies: String, // Converted to HarBodies
+    #[serde(default)]
+    pub max_body_bytes: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct JSONWasmFilter {
+    pub wasm: String, // Path to the module
+    #[serde(default)]
+    pub max_memory_bytes: usize,
+    #[serde(default)]
+    pub max_fuel: u64,
+}
+
+// A filter registered with the proxy is listed by name, a WASM filter as an object
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum JSONFilter {
+    NAMED(String),
+    WASM(JSONWasmFilter),
+}
+
 #[derive(Serialize, Deserialize, Default)]
 pub struct JSONBinding {
     pub name: String,
     pub from: String,
-    pub to: String,
+    #[serde(default)]
+    pub to: String, // Empty when every request is answered by the binding's rules
+    #[serde(default)]
+    pub protocol: String, // Converted to BindingProtocol
+    #[serde(default)]
+    pub forwarding: JSONForwarding,
+    #[serde(default)]
+    pub proxy_protocol: JSONProxyProtocol,
+    #[serde(default)]
+    pub connection_limits: JSONConnectionLimits,
+    #[serde(default)]
+    pub bandwidth: JSONBandwidth,
+    #[serde(default)]
+    pub faults: Vec<JSONFault>,
+    #[serde(default)]
+    pub mirror: JSONMirror,
+    #[serde(default)]
+    pub har: Option<JSONHar>,
+    #[serde(default)]
+    pub intercept: Option<JSONIntercept>,
+    #[serde(default)]
+    pub filters: Vec<JSONFilter>, // In the order requests pass through them
     #[serde(default)]
     pub rules: JSONRuleSet
 }
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// Buckets are pruned once this many keys are tracked, full buckets first since dropping those gives no client tokens
+static MAX_TRACKED_KEYS: usize = 10_000;
+// When too few are full, the least recently used of the rest are evicted in one go, a tenth of the cap at a time
+static EVICTED_KEYS: usize = MAX_TRACKED_KEYS / 10;
+
+// ---- TokenBucket ----
+
+pub struct TokenBucket {
+    rate: f64, // Tokens added per second
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, capacity: f64) -> TokenBucket {
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+    // Takes one token, or returns how long until one is available
+    pub fn take(&mut self) -> Result<(), Duration> {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+    }
+    // Always takes the tokens, going into debt if needed, and returns how long until the debt is repaid
+    pub fn reserve(&mut self, amount: f64) -> Duration {
+        self.refill();
+        self.tokens -= amount;
+        if self.tokens >= 0.0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64(-self.tokens / self.rate)
+    }
+    pub fn capacity(&self) -> f64 {
+        self.capacity
+    }
+    fn refill(&mut self) {
+        let now: Instant = Instant::now();
+        let elapsed: f64 = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+}
+
+// ---- KeyedLimiter ----
+
+// One token bucket per key, e.g. per client IP or API key, with when the key was last seen
+pub struct KeyedLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, (TokenBucket, Instant)>>,
+}
+
+impl KeyedLimiter {
+    pub fn new(rate: u32, burst: u32) -> KeyedLimiter {
+        KeyedLimiter {
+            rate: rate as f64,
+            // Without an explicit burst a second's worth of tokens can be spent at once
+            burst: if burst == 0 { rate as f64 } else { burst as f64 },
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+    pub fn take(&self, key: &str) -> Result<(), Duration> {
+        let mut buckets: MutexGuard<HashMap<String, (TokenBucket, Instant)>> = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_KEYS && !buckets.contains_key(key) {
+            buckets.retain(|_, (bucket, _): &mut (TokenBucket, Instant)| !bucket.is_full());
+            if buckets.len() >= MAX_TRACKED_KEYS {
+                KeyedLimiter::evict_least_recent(&mut buckets);
+            }
+        }
+        let (rate, burst): (f64, f64) = (self.rate, self.burst);
+        let now: Instant = Instant::now();
+        let (bucket, last_seen): &mut (TokenBucket, Instant) = buckets.entry(String::from(key))
+            .or_insert_with(|| (TokenBucket::new(rate, burst), now));
+        *last_seen = now;
+        bucket.take()
+    }
+    // A key flood cannot grow the map past the cap. Evicted keys start again with a full bucket,
+    // which only favours clients that have been quiet the longest
+    fn evict_least_recent(buckets: &mut HashMap<String, (TokenBucket, Instant)>) {
+        let mut last_seen: Vec<Instant> = buckets.values().map(|(_, last_seen): &(TokenBucket, Instant)| *last_seen).collect();
+        let (_, cutoff, _) = last_seen.select_nth_unstable(EVICTED_KEYS);
+        let cutoff: Instant = *cutoff;
+        buckets.retain(|_, (_, last_seen): &mut (TokenBucket, Instant)| *last_seen > cutoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flooding_distinct_keys_stays_under_the_cap() {
+        // Slow enough that no bucket refills during the test, so none can be pruned as full
+        let limiter: KeyedLimiter = KeyedLimiter::new(1, 1);
+        for index in 0..MAX_TRACKED_KEYS * 3 {
+            assert!(limiter.take(format!("client-{}", index).as_str()).is_ok());
+            assert!(limiter.buckets.lock().unwrap().len() <= MAX_TRACKED_KEYS);
+        }
+    }
+
+    #[test]
+    fn recently_seen_keys_survive_eviction() {
+        let limiter: KeyedLimiter = KeyedLimiter::new(1, 1);
+        assert!(limiter.take("busy").is_ok());
+        for index in 0..MAX_TRACKED_KEYS * 2 {
+            limiter.take(format!("client-{}", index).as_str()).ok();
+            if index % 100 == 0 {
+                assert!(limiter.take("busy").is_err());
+            }
+        }
+        assert!(limiter.take("busy").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+use slog::Logger;
+
+use crate::servlet::binding_context::BindingContext;
+use crate::traffic::bindingset::{MirrorRule, Rule};
+use crate::traffic::enforcer::CompiledRule;
+use crate::traffic::http::diff::{CapturedResponse, ResponseComparator, ResponseReader};
+use crate::traffic::http::message::HttpRequestHead;
+
+type Byte = u8;
+
+// Chunks waiting for the shadow before the mirror is abandoned, so a slow shadow never holds up the primary
+static MIRROR_QUEUE_CHUNKS: usize = 256;
+static SHADOW_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+// Bounds how long a shadow that never closes can keep its reader alive
+static SHADOW_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// ---- BindingMirror ----
+
+pub(crate) struct BindingMirror {
+    pub to: String, // Resolved per mirrored connection, a shadow that cannot be resolved is only logged
+    scope: Option<CompiledRule>,
+}
+
+impl BindingMirror {
+    pub fn new(rule: &MirrorRule, logger: &Logger) -> io::Result<Option<BindingMirror>> {
+        if rule.to.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(BindingMirror {
+            to: rule.to.clone(),
+            scope: rule.scope.as_ref().map(|scope: &Rule| CompiledRule::new(scope, logger)).transpose()?,
+        }))
+    }
+    pub fn selects(&self, request: &HttpRequestHead) -> bool {
+        self.scope.as_ref().map_or(true, |scope: &CompiledRule| scope.matches(&request.headers, Some(request), request.version.as_str()))
+    }
+}
+
+// ---- MirrorConnection ----
+
+// A request copied to the shadow, numbered like the primary's exchanges so the two responses can be paired
+pub(crate) struct MirroredRequest {
+    pub exchange: u64,
+    pub method: String,
+}
+
+pub(crate) struct MirrorChunk {
+    pub bytes: Vec<Byte>,
+    pub requests: Vec<MirroredRequest>, // Requests that begin in these bytes
+}
+
+// Copies one connection's egress to the shadow upstream, which is dialled and written on its own thread
+pub(crate) struct MirrorConnection {
+    to: String,
+    sender: Option<SyncSender<MirrorChunk>>, // Created with the first mirrored chunk
+    abandoned: bool,
+    id: uuid::Uuid,
+    comparator: Option<Arc<Mutex<ResponseComparator>>>,
+    logger: Logger,
+}
+
+impl MirrorConnection {
+    pub fn open(context: &Arc<BindingContext>, id: uuid::Uuid, comparator: Option<Arc<Mutex<ResponseComparator>>>) -> Option<MirrorConnection> {
+        Some(MirrorConnection {
+            to: context.mirror.as_ref()?.to.clone(),
+            sender: None,
+            abandoned: false,
+            id,
+            comparator,
+            logger: context.logger.clone(),
+        })
+    }
+    // Never blocks, once a chunk cannot be queued the rest of the connection is not mirrored so the shadow never sees a gap
+    pub fn send(&mut self, chunk: MirrorChunk) {
+        if chunk.bytes.is_empty() || self.abandoned {
+            return;
+        }
+        let (to, id): (String, uuid::Uuid) = (self.to.clone(), self.id);
+        let comparator: Option<Arc<Mutex<ResponseComparator>>> = self.comparator.clone();
+        let logger: Logger = self.logger.clone();
+        let sender: &SyncSender<MirrorChunk> = self.sender.get_or_insert_with(|| {
+            let (sender, receiver): (SyncSender<MirrorChunk>, Receiver<MirrorChunk>) = mpsc::sync_channel(MIRROR_QUEUE_CHUNKS);
+            thread::spawn(move || MirrorConnection::forward_to_shadow(to, receiver, id, comparator, logger));
+            sender
+        });
+        match sender.try_send(chunk) {
+            Ok(()) => return,
+            Err(TrySendError::Full(_)) => info!(self.logger, "MIRROR [{}] Shadow fell behind, no longer mirroring", self.id),
+            Err(TrySendError::Disconnected(_)) => {},
+        }
+        self.abandoned = true;
+        self.sender = None;
+        MirrorConnection::abandon(&self.comparator);
+    }
+    // Nothing more will reach the shadow, so the comparator stops waiting on its responses
+    fn abandon(comparator: &Option<Arc<Mutex<ResponseComparator>>>) {
+        if let Some(comparator) = comparator.as_ref() {
+            comparator.lock().unwrap().abandon();
+        }
+    }
+    fn forward_to_shadow(to: String, receiver: Receiver<MirrorChunk>, id: uuid::Uuid, comparator: Option<Arc<Mutex<ResponseComparator>>>, logger: Logger) {
+        // Resolved here rather than at load, so a shadow that is briefly unresolvable costs only the mirrored copies
+        let address: SocketAddr = match to.to_socket_addrs().map(|mut addresses| addresses.next()) {
+            Ok(Some(address)) => address,
+            Ok(None) => {
+                info!(logger, "MIRROR [{}] Shadow [{}] resolved to no addresses", id, to);
+                MirrorConnection::abandon(&comparator);
+                return;
+            },
+            Err(e) => {
+                info!(logger, "MIRROR [{}] Failed to resolve shadow [{}]: {}", id, to, e);
+                MirrorConnection::abandon(&comparator);
+                return;
+            },
+        };
+        let mut shadow: TcpStream = match TcpStream::connect_timeout(&address, SHADOW_CONNECT_TIMEOUT) {
+            Ok(shadow) => shadow,
+            Err(e) => {
+                info!(logger, "MIRROR [{}] Failed to connect to shadow [{}]: {}", id, address, e);
+                MirrorConnection::abandon(&comparator);
+                return;
+            },
+        };
+        let mut responses: TcpStream = match shadow.try_clone() {
+            Ok(responses) => responses,
+            Err(e) => {
+                info!(logger, "MIRROR [{}] Failed to clone shadow stream: {}", id, e);
+                MirrorConnection::abandon(&comparator);
+                return;
+            },
+        };
+        let _ = responses.set_read_timeout(Some(SHADOW_READ_TIMEOUT));
+        let requests: Arc<Mutex<VecDeque<MirroredRequest>>> = Arc::new(Mutex::new(VecDeque::new()));
+        let requests_clone: Arc<Mutex<VecDeque<MirroredRequest>>> = requests.clone();
+        // Responses are always read so the shadow is never blocked writing them, and thrown away unless compared
+        thread::spawn(move || match comparator {
+            Some(comparator) => MirrorConnection::compare_shadow_responses(responses, requests_clone, comparator),
+            None => { let _ = io::copy(&mut responses, &mut io::sink()); },
+        });
+        for chunk in receiver.iter() {
+            // Queued before the bytes are written so the request is known by the time its response arrives
+            requests.lock().unwrap().extend(chunk.requests);
+            if let Err(e) = shadow.write_all(chunk.bytes.as_slice()) {
+                info!(logger, "MIRROR [{}] Failed to write to shadow [{}]: {}", id, address, e);
+                break;
+            }
+        }
+        debug!(logger, "MIRROR [{}] Finished mirroring to [{}]", id, address);
+        let _ = shadow.shutdown(Shutdown::Write);
+    }
+    fn compare_shadow_responses(mut responses: TcpStream, requests: Arc<Mutex<VecDeque<MirroredRequest>>>, comparator: Arc<Mutex<ResponseComparator>>) {
+        let mut reader: ResponseReader = ResponseReader::new(comparator.lock().unwrap().rule().clone());
+        let mut buffer: [Byte; 8192] = [0; 8192];
+        loop {
+            let read: usize = match responses.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+            let captured: Vec<(u64, CapturedResponse)> = reader.push(&buffer[..read], || {
+                requests.lock().unwrap().pop_front().map(|request: MirroredRequest| (request.exchange, request.method))
+            });
+            for (exchange, response) in captured {
+                comparator.lock().unwrap().record_shadow(exchange, response);
+            }
+        }
+        let mut comparator: MutexGuard<ResponseComparator> = comparator.lock().unwrap();
+        if let Some((exchange, response)) = reader.finish() {
+            comparator.record_shadow(exchange, response);
+        }
+        comparator.abandon();
+    }
+}
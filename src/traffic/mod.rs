@@ -1,5 +1,17 @@
+pub mod bandwidth;
 pub mod bindingset;
 pub mod enforcer;
+pub mod faults;
+pub mod filter;
+pub mod http;
 pub mod json_mappings;
+pub mod limiter;
+pub mod mirror;
+pub mod monitor;
 pub mod packet_handler;
 pub mod packet;
+pub mod proxy_protocol;
+pub mod recording;
+pub mod script;
+pub mod template;
+pub mod wasm;
@@ -0,0 +1,139 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+
+use crate::traffic::bindingset::TrafficDirection;
+use crate::traffic::enforcer::Verdict;
+use crate::traffic::http::message::HttpRequestHead;
+
+// Verdicts kept per binding for the admin API, older ones are forgotten
+static MONITOR_VERDICTS: usize = 200;
+
+// ---- BindingMonitor ----
+
+// Live traffic counts for a binding, read by the admin API
+pub(crate) struct BindingMonitor {
+    egress_bytes: AtomicU64,
+    ingress_bytes: AtomicU64,
+    accepted: AtomicU64,
+    connections: Mutex<HashMap<uuid::Uuid, Arc<ConnectionStats>>>,
+    verdicts: Mutex<VecDeque<Value>>,
+}
+
+impl BindingMonitor {
+    pub fn new() -> BindingMonitor {
+        BindingMonitor {
+            egress_bytes: AtomicU64::new(0),
+            ingress_bytes: AtomicU64::new(0),
+            accepted: AtomicU64::new(0),
+            connections: Mutex::new(HashMap::new()),
+            verdicts: Mutex::new(VecDeque::new()),
+        }
+    }
+    // The connection is listed until the returned handle is dropped
+    pub fn open(monitor: &Arc<BindingMonitor>, id: uuid::Uuid, client: SocketAddr, upstream: Option<SocketAddr>) -> MonitoredConnection {
+        let stats: Arc<ConnectionStats> = Arc::new(ConnectionStats {
+            id,
+            client,
+            upstream,
+            opened: Utc::now(),
+            egress_bytes: AtomicU64::new(0),
+            ingress_bytes: AtomicU64::new(0),
+            monitor: monitor.clone(),
+        });
+        monitor.accepted.fetch_add(1, Ordering::Relaxed);
+        monitor.connections.lock().unwrap().insert(id, stats.clone());
+        MonitoredConnection {
+            stats,
+        }
+    }
+    pub fn verdict(&self, connection: uuid::Uuid, request: &HttpRequestHead, verdict: &Verdict) {
+        let (name, status): (&str, Option<u16>) = match verdict {
+            Verdict::FORWARD => ("FORWARD", None),
+            Verdict::RESPOND(response, _) => ("RESPOND", Some(response.status)),
+            Verdict::MOCK(response, _, _) => ("MOCK", Some(response.status)),
+        };
+        let mut verdicts: MutexGuard<VecDeque<Value>> = self.verdicts.lock().unwrap();
+        if verdicts.len() >= MONITOR_VERDICTS {
+            verdicts.pop_front();
+        }
+        verdicts.push_back(json!({
+            "time": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "connection": connection.to_string(),
+            "request": format!("{} {}", request.method, request.target),
+            "verdict": name,
+            "status": status,
+        }));
+    }
+    pub fn totals(&self) -> Value {
+        json!({
+            "open": self.connections.lock().unwrap().len(),
+            "accepted": self.accepted.load(Ordering::Relaxed),
+            "egress_bytes": self.egress_bytes.load(Ordering::Relaxed),
+            "ingress_bytes": self.ingress_bytes.load(Ordering::Relaxed),
+        })
+    }
+    // Oldest first
+    pub fn connections(&self) -> Vec<Value> {
+        let mut connections: Vec<Arc<ConnectionStats>> = self.connections.lock().unwrap().values().cloned().collect();
+        connections.sort_by_key(|stats: &Arc<ConnectionStats>| stats.opened);
+        connections.iter().map(|stats: &Arc<ConnectionStats>| stats.to_json()).collect()
+    }
+    // The latest verdicts, oldest first, every verdict kept when count is 0
+    pub fn verdicts(&self, count: usize) -> Vec<Value> {
+        let verdicts: MutexGuard<VecDeque<Value>> = self.verdicts.lock().unwrap();
+        let skip: usize = match count {
+            0 => 0,
+            count => verdicts.len().saturating_sub(count),
+        };
+        verdicts.iter().skip(skip).cloned().collect()
+    }
+}
+
+// ---- ConnectionStats ----
+
+pub(crate) struct ConnectionStats {
+    pub id: uuid::Uuid,
+    client: SocketAddr,
+    upstream: Option<SocketAddr>,
+    opened: DateTime<Utc>,
+    egress_bytes: AtomicU64,
+    ingress_bytes: AtomicU64,
+    monitor: Arc<BindingMonitor>,
+}
+
+impl ConnectionStats {
+    // Counted as read from each peer, before the binding's rules
+    pub fn count(&self, direction: TrafficDirection, bytes: usize) {
+        let (connection, binding): (&AtomicU64, &AtomicU64) = match direction {
+            TrafficDirection::EGRESS => (&self.egress_bytes, &self.monitor.egress_bytes),
+            TrafficDirection::INGRESS => (&self.ingress_bytes, &self.monitor.ingress_bytes),
+        };
+        connection.fetch_add(bytes as u64, Ordering::Relaxed);
+        binding.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "id": self.id.to_string(),
+            "client": self.client.to_string(),
+            "upstream": self.upstream.map(|upstream: SocketAddr| upstream.to_string()),
+            "opened": self.opened.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "egress_bytes": self.egress_bytes.load(Ordering::Relaxed),
+            "ingress_bytes": self.ingress_bytes.load(Ordering::Relaxed),
+        })
+    }
+}
+
+// Held in the connection's metadata, so the connection leaves the list once both directions have finished
+pub(crate) struct MonitoredConnection {
+    pub stats: Arc<ConnectionStats>,
+}
+
+impl Drop for MonitoredConnection {
+    fn drop(&mut self) {
+        self.stats.monitor.connections.lock().unwrap().remove(&self.stats.id);
+    }
+}
@@ -1 +1,2 @@
+pub mod pcapng;
 pub mod stream_packet_collector;
\ No newline at end of file
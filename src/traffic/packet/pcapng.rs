@@ -0,0 +1,327 @@
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use pnet::packet::ethernet::{EtherTypes, MutableEthernetPacket};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::{self, Ipv4Flags, MutableIpv4Packet};
+use pnet::packet::ipv6::MutableIpv6Packet;
+use pnet::packet::tcp::{self, MutableTcpPacket, TcpFlags};
+use pnet::util::MacAddr;
+
+use crate::traffic::bindingset::TrafficDirection;
+use crate::traffic::recording::reader::RecordedSession;
+
+type Byte = u8;
+
+static ETHERNET_HEADER_BYTES: usize = 14;
+static IPV4_HEADER_BYTES: usize = 20;
+static IPV6_HEADER_BYTES: usize = 40;
+static TCP_HEADER_BYTES: usize = 20;
+// Recorded chunks are split into segments no larger than a typical Ethernet MSS
+static MAX_SEGMENT_BYTES: usize = 1460;
+static CLIENT_MAC: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 0x01);
+static SERVER_MAC: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 0x02);
+
+static SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+static INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+static ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+static BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+static LINKTYPE_ETHERNET: u16 = 1;
+
+// ---- PcapngWriter ----
+
+// One section with a single Ethernet interface, timestamps use the default microsecond resolution
+pub struct PcapngWriter<W: Write> {
+    output: W,
+    pub packets: usize,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    pub fn new(mut output: W) -> io::Result<PcapngWriter<W>> {
+        let mut section: Vec<Byte> = vec![];
+        section.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend_from_slice(&1u16.to_le_bytes());
+        section.extend_from_slice(&0u16.to_le_bytes());
+        section.extend_from_slice(&(-1i64).to_le_bytes()); // Section length is not known up front
+        write_block(&mut output, SECTION_HEADER_BLOCK, section.as_slice())?;
+        let mut interface: Vec<Byte> = vec![];
+        interface.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        interface.extend_from_slice(&0u16.to_le_bytes());
+        interface.extend_from_slice(&0u32.to_le_bytes()); // No snapshot length, packets are never cut short
+        write_block(&mut output, INTERFACE_DESCRIPTION_BLOCK, interface.as_slice())?;
+        Ok(PcapngWriter { output, packets: 0 })
+    }
+    pub fn write_packet(&mut self, timestamp: u64, frame: &[Byte]) -> io::Result<()> {
+        let mut packet: Vec<Byte> = vec![];
+        packet.extend_from_slice(&0u32.to_le_bytes());
+        packet.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        packet.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        packet.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        packet.extend_from_slice(frame);
+        write_block(&mut self.output, ENHANCED_PACKET_BLOCK, packet.as_slice())?;
+        self.packets += 1;
+        Ok(())
+    }
+    pub fn finish(mut self) -> io::Result<usize> {
+        self.output.flush()?;
+        Ok(self.packets)
+    }
+}
+
+fn write_block<W: Write>(output: &mut W, block_type: u32, body: &[Byte]) -> io::Result<()> {
+    let padding: usize = (4 - body.len() % 4) % 4;
+    let length: u32 = (12 + body.len() + padding) as u32;
+    output.write_all(&block_type.to_le_bytes())?;
+    output.write_all(&length.to_le_bytes())?;
+    output.write_all(body)?;
+    output.write_all(&[0; 3][..padding])?;
+    output.write_all(&length.to_le_bytes())
+}
+
+// ---- TcpConversation ----
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Segment {
+    SYN,
+    SYNACK,
+    ACK,
+    DATA,
+    FIN,
+}
+
+// Synthesizes the packets of one connection between the recorded client and upstream, tracking both sides' sequence numbers
+struct TcpConversation {
+    client: SocketAddr,
+    server: SocketAddr,
+    client_sequence: u32, // Next sequence number each side sends
+    server_sequence: u32,
+    identification: u16,
+}
+
+impl TcpConversation {
+    fn new(session: &RecordedSession) -> Option<TcpConversation> {
+        let (client, server): (SocketAddr, SocketAddr) = same_family(
+            session.header.client.parse::<SocketAddr>().ok()?,
+            session.header.upstream.parse::<SocketAddr>().ok()?,
+        );
+        // Initial sequence numbers come from the session id so exports are repeatable
+        let id: &[Byte; 16] = session.header.id.as_bytes();
+        Some(TcpConversation {
+            client,
+            server,
+            client_sequence: u32::from_be_bytes([id[0], id[1], id[2], id[3]]),
+            server_sequence: u32::from_be_bytes([id[4], id[5], id[6], id[7]]),
+            identification: 0,
+        })
+    }
+    fn frame(&mut self, from_client: bool, segment: Segment, payload: &[Byte]) -> Vec<Byte> {
+        let (source, destination): (SocketAddr, SocketAddr) = if from_client { (self.client, self.server) } else { (self.server, self.client) };
+        let (sequence, acknowledgement): (u32, u32) = if from_client {
+            (self.client_sequence, self.server_sequence)
+        } else {
+            (self.server_sequence, self.client_sequence)
+        };
+        let consumed: u32 = match segment {
+            Segment::SYN | Segment::SYNACK | Segment::FIN => 1,
+            Segment::ACK => 0,
+            Segment::DATA => payload.len() as u32,
+        };
+        if from_client {
+            self.client_sequence = self.client_sequence.wrapping_add(consumed);
+        } else {
+            self.server_sequence = self.server_sequence.wrapping_add(consumed);
+        }
+        self.identification = self.identification.wrapping_add(1);
+        let ip_header_bytes: usize = if source.is_ipv4() { IPV4_HEADER_BYTES } else { IPV6_HEADER_BYTES };
+        let tcp_bytes: usize = TCP_HEADER_BYTES + payload.len();
+        let mut frame: Vec<Byte> = vec![0; ETHERNET_HEADER_BYTES + ip_header_bytes + tcp_bytes];
+
+        let mut ethernet: MutableEthernetPacket = MutableEthernetPacket::new(&mut frame[..]).unwrap();
+        ethernet.set_source(if from_client { CLIENT_MAC } else { SERVER_MAC });
+        ethernet.set_destination(if from_client { SERVER_MAC } else { CLIENT_MAC });
+        ethernet.set_ethertype(if source.is_ipv4() { EtherTypes::Ipv4 } else { EtherTypes::Ipv6 });
+
+        let mut tcp: MutableTcpPacket = MutableTcpPacket::new(&mut frame[ETHERNET_HEADER_BYTES + ip_header_bytes..]).unwrap();
+        tcp.set_source(source.port());
+        tcp.set_destination(destination.port());
+        tcp.set_sequence(sequence);
+        tcp.set_acknowledgement(if segment == Segment::SYN { 0 } else { acknowledgement });
+        tcp.set_data_offset((TCP_HEADER_BYTES / 4) as u8);
+        tcp.set_flags(match segment {
+            Segment::SYN => TcpFlags::SYN,
+            Segment::SYNACK => TcpFlags::SYN | TcpFlags::ACK,
+            Segment::ACK => TcpFlags::ACK,
+            Segment::DATA => TcpFlags::PSH | TcpFlags::ACK,
+            Segment::FIN => TcpFlags::FIN | TcpFlags::ACK,
+        });
+        tcp.set_window(u16::MAX);
+        tcp.set_payload(payload);
+        let checksum: u16 = match (source.ip(), destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => tcp::ipv4_checksum(&tcp.to_immutable(), &source, &destination),
+            (IpAddr::V6(source), IpAddr::V6(destination)) => tcp::ipv6_checksum(&tcp.to_immutable(), &source, &destination),
+            _ => 0,
+        };
+        tcp.set_checksum(checksum);
+
+        match (source.ip(), destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                let mut ip: MutableIpv4Packet = MutableIpv4Packet::new(&mut frame[ETHERNET_HEADER_BYTES..]).unwrap();
+                ip.set_version(4);
+                ip.set_header_length((IPV4_HEADER_BYTES / 4) as u8);
+                ip.set_total_length((IPV4_HEADER_BYTES + tcp_bytes) as u16);
+                ip.set_identification(self.identification);
+                ip.set_flags(Ipv4Flags::DontFragment);
+                ip.set_ttl(64);
+                ip.set_next_level_protocol(IpNextHeaderProtocols::Tcp);
+                ip.set_source(source);
+                ip.set_destination(destination);
+                let checksum: u16 = ipv4::checksum(&ip.to_immutable());
+                ip.set_checksum(checksum);
+            },
+            (source, destination) => {
+                let mut ip: MutableIpv6Packet = MutableIpv6Packet::new(&mut frame[ETHERNET_HEADER_BYTES..]).unwrap();
+                ip.set_version(6);
+                ip.set_payload_length(tcp_bytes as u16);
+                ip.set_next_header(IpNextHeaderProtocols::Tcp);
+                ip.set_hop_limit(64);
+                ip.set_source(to_ipv6(source));
+                ip.set_destination(to_ipv6(destination));
+            },
+        }
+        frame
+    }
+}
+
+// Wireshark needs both ends of a conversation in one family, IPv4 is mapped into IPv6 when they differ
+fn same_family(client: SocketAddr, server: SocketAddr) -> (SocketAddr, SocketAddr) {
+    if client.is_ipv4() == server.is_ipv4() {
+        return (client, server);
+    }
+    (
+        SocketAddr::new(IpAddr::V6(to_ipv6(client.ip())), client.port()),
+        SocketAddr::new(IpAddr::V6(to_ipv6(server.ip())), server.port()),
+    )
+}
+
+fn to_ipv6(address: IpAddr) -> Ipv6Addr {
+    match address {
+        IpAddr::V4(address) => address.to_ipv6_mapped(),
+        IpAddr::V6(address) => address,
+    }
+}
+
+// ---- Export ----
+
+// Every session's packets as (micros since the epoch, Ethernet frame): a handshake at the open, the recorded chunks in
+// MSS sized segments and a FIN from each side at the close
+fn session_packets(session: &RecordedSession) -> Option<Vec<(u64, Vec<Byte>)>> {
+    let mut conversation: TcpConversation = TcpConversation::new(session)?;
+    let opened: u64 = session.header.started;
+    let mut packets: Vec<(u64, Vec<Byte>)> = vec![
+        (opened, conversation.frame(true, Segment::SYN, &[])),
+        (opened, conversation.frame(false, Segment::SYNACK, &[])),
+        (opened, conversation.frame(true, Segment::ACK, &[])),
+    ];
+    for chunk in session.chunks.iter() {
+        let from_client: bool = chunk.direction == TrafficDirection::EGRESS;
+        for payload in chunk.bytes.chunks(MAX_SEGMENT_BYTES) {
+            packets.push((opened + chunk.offset, conversation.frame(from_client, Segment::DATA, payload)));
+        }
+    }
+    if let Some(closed) = session.closed {
+        packets.push((opened + closed, conversation.frame(true, Segment::FIN, &[])));
+        packets.push((opened + closed, conversation.frame(false, Segment::FIN, &[])));
+        packets.push((opened + closed, conversation.frame(true, Segment::ACK, &[])));
+    }
+    Some(packets)
+}
+
+// Packets from every session are interleaved by time, returns how many were written
+pub fn export_sessions<W: Write>(sessions: &Vec<RecordedSession>, output: W) -> io::Result<usize> {
+    let mut packets: Vec<(u64, Vec<Byte>)> = vec![];
+    for session in sessions.iter() {
+        match session_packets(session) {
+            Some(session_packets) => packets.extend(session_packets),
+            None => eprintln!("EXPORT Skipped session [{}] with unreadable addresses", session.header.id),
+        }
+    }
+    // Stable, so each session's own packets keep their order when they share a timestamp
+    packets.sort_by_key(|(timestamp, _): &(u64, Vec<Byte>)| *timestamp);
+    let mut writer: PcapngWriter<W> = PcapngWriter::new(output)?;
+    for (timestamp, frame) in packets.iter() {
+        writer.write_packet(*timestamp, frame.as_slice())?;
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traffic::recording::format::{SessionChunk, SessionHeader};
+
+    fn u32_at(bytes: &[Byte], offset: usize) -> u32 {
+        u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    fn session() -> RecordedSession {
+        let id: uuid::Uuid = uuid::Uuid::new_v4();
+        let chunk = |offset: u64, direction: TrafficDirection, bytes: Vec<Byte>| SessionChunk { id, offset, direction, bytes };
+        RecordedSession {
+            header: SessionHeader {
+                id,
+                started: 1_700_000_000_000_000,
+                binding: String::from("api"),
+                client: String::from("127.0.0.1:5000"),
+                upstream: String::from("[::1]:80"),
+            },
+            chunks: vec![
+                chunk(10, TrafficDirection::EGRESS, b"hello".to_vec()),
+                chunk(20, TrafficDirection::INGRESS, vec![b'x'; 3000]),
+            ],
+            closed: Some(30),
+        }
+    }
+
+    #[test]
+    fn blocks_are_framed_and_padded() {
+        let mut output: Vec<Byte> = vec![];
+        // Handshake, one request segment, three response segments and the close
+        assert_eq!(export_sessions(&vec![session()], &mut output).unwrap(), 10);
+        let mut block_types: Vec<u32> = vec![];
+        let mut offset: usize = 0;
+        while offset < output.len() {
+            let block_type: u32 = u32_at(&output, offset);
+            let length: usize = u32_at(&output, offset + 4) as usize;
+            assert_eq!(length % 4, 0);
+            assert!(offset + length <= output.len());
+            assert_eq!(u32_at(&output, offset + length - 4) as usize, length);
+            if block_type == ENHANCED_PACKET_BLOCK {
+                let captured: usize = u32_at(&output, offset + 20) as usize;
+                assert_eq!(u32_at(&output, offset + 24) as usize, captured);
+                let padding: usize = length - 32 - captured;
+                assert!(padding < 4);
+                assert!(output[offset + 28 + captured..offset + length - 4].iter().all(|byte: &Byte| *byte == 0));
+            }
+            block_types.push(block_type);
+            offset += length;
+        }
+        assert_eq!(offset, output.len());
+        assert_eq!(block_types[..2], [SECTION_HEADER_BLOCK, INTERFACE_DESCRIPTION_BLOCK]);
+        assert_eq!(block_types[2..], [ENHANCED_PACKET_BLOCK; 10]);
+        assert_eq!(u32_at(&output, 8), BYTE_ORDER_MAGIC);
+    }
+
+    #[test]
+    fn odd_sized_frames_are_padded_to_32_bits() {
+        let mut output: Vec<Byte> = vec![];
+        let mut writer: PcapngWriter<&mut Vec<Byte>> = PcapngWriter::new(&mut output).unwrap();
+        let header_bytes: usize = 28 + 20;
+        writer.write_packet(1, &[0xAB; 5]).unwrap();
+        writer.finish().unwrap();
+        let packet: &[Byte] = &output[header_bytes..];
+        assert_eq!(u32_at(packet, 4), 40);
+        assert_eq!(&packet[28..36], &[0xAB, 0xAB, 0xAB, 0xAB, 0xAB, 0, 0, 0]);
+        assert_eq!(u32_at(packet, 36), 40);
+        assert_eq!(packet.len(), 40);
+    }
+}
@@ -1,6 +1,9 @@
 use std::io::{Write, BufReader, BufRead};
 use std::net::TcpStream;
+use slog::Logger;
 
+use crate::traffic::bindingset::TrafficDirection;
+use crate::traffic::recording::recorder::SessionRecording;
 use crate::{try_except_return, inc, option_same_block};
 
 type Byte = u8;
@@ -10,6 +13,7 @@ pub struct StreamPacketCollector {
     receiver: TcpStream,
     sender: BufReader<TcpStream>,
     pub packet_count: i32,
+    recording: Option<(SessionRecording, TrafficDirection)>,
 }
 
 impl StreamPacketCollector {
@@ -19,8 +23,13 @@ impl StreamPacketCollector {
             receiver,
             sender: BufReader::new(sender),
             packet_count: 0,
+            recording: None,
         }
     }
+    // Records each packet as it is read, the direction is the one being collected
+    pub(crate) fn record(&mut self, recording: Option<SessionRecording>, direction: TrafficDirection) {
+        self.recording = recording.map(|recording: SessionRecording| (recording, direction));
+    }
     pub fn read_all_packets_from_stream(&mut self) {
         loop {
             let buffer: &[Byte] = self.sender.fill_buf().unwrap();
@@ -28,6 +37,9 @@ impl StreamPacketCollector {
                 return;
             }
             let length: usize = buffer.len();
+            if let Some((recording, direction)) = self.recording.as_ref() {
+                recording.record(*direction, buffer);
+            }
             self.packet_content_buffer.extend_from_slice(buffer);
             self.sender.consume(length);
             inc!{self.packet_count};
@@ -39,8 +51,9 @@ impl StreamPacketCollector {
             self.packet_content_buffer.len()
         }
     }
-    pub fn flush_stream_to_remote(&mut self) {
+    pub fn flush_stream_to_remote(&mut self, logger: &Logger) {
         try_except_return!{
+            logger,
             self.receiver.flush(),
             "Failed to flush to remote"
         }
@@ -0,0 +1,285 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::traffic::bindingset::ProxyProtocolVersion;
+
+type Byte = u8;
+
+static V2_SIGNATURE: [Byte; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+static V1_MAX_LENGTH: usize = 107;
+static HEADER_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct ProxyProtocolError {
+    pub reason: String,
+}
+
+impl ProxyProtocolError {
+    fn new(reason: &str) -> ProxyProtocolError {
+        ProxyProtocolError {
+            reason: String::from(reason),
+        }
+    }
+}
+
+impl fmt::Display for ProxyProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid PROXY protocol header: {}", self.reason)
+    }
+}
+
+impl From<std::io::Error> for ProxyProtocolError {
+    fn from(error: std::io::Error) -> ProxyProtocolError {
+        ProxyProtocolError {
+            reason: error.to_string(),
+        }
+    }
+}
+
+// Addresses are absent for UNKNOWN (v1) and LOCAL (v2) headers, the socket addresses should be used as-is
+pub struct ProxyHeader {
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+}
+
+// Consumes exactly the header from the stream so the payload that follows is left untouched
+pub fn read_header(mut stream: &TcpStream, version: ProxyProtocolVersion) -> Result<ProxyHeader, ProxyProtocolError> {
+    stream.set_read_timeout(Some(HEADER_READ_TIMEOUT))?;
+    let mut prefix: [Byte; 5] = [0; 5];
+    stream.read_exact(&mut prefix)?;
+    let header: Result<ProxyHeader, ProxyProtocolError> = match (&prefix, version) {
+        (b"PROXY", ProxyProtocolVersion::V1) | (b"PROXY", ProxyProtocolVersion::ANY) => read_v1(stream),
+        (_, ProxyProtocolVersion::V2) | (_, ProxyProtocolVersion::ANY) if prefix[..] == V2_SIGNATURE[..5] => read_v2(stream),
+        _ => Err(ProxyProtocolError::new("missing header signature")),
+    };
+    stream.set_read_timeout(None)?;
+    header
+}
+
+fn read_v1(mut stream: &TcpStream) -> Result<ProxyHeader, ProxyProtocolError> {
+    let mut line: Vec<Byte> = b"PROXY".to_vec();
+    let mut next: [Byte; 1] = [0];
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(ProxyProtocolError::new("v1 header exceeds 107 bytes"));
+        }
+        stream.read_exact(&mut next)?;
+        line.push(next[0]);
+    }
+    let line: String = String::from_utf8(line).map_err(|_| ProxyProtocolError::new("v1 header is not ASCII"))?;
+    let fields: Vec<&str> = line.trim_end().split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(ProxyHeader { source: None, destination: None }),
+        ["PROXY", family, source, destination, source_port, destination_port] => {
+            let parse = |addr: &str, port: &str| -> Result<SocketAddr, ProxyProtocolError> {
+                let ip: IpAddr = addr.parse::<IpAddr>().map_err(|_| ProxyProtocolError::new("v1 address is invalid"))?;
+                let port: u16 = port.parse::<u16>().map_err(|_| ProxyProtocolError::new("v1 port is invalid"))?;
+                match (*family, ip) {
+                    ("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_)) => Ok(SocketAddr::new(ip, port)),
+                    _ => Err(ProxyProtocolError::new("v1 address does not match protocol family")),
+                }
+            };
+            Ok(ProxyHeader {
+                source: Some(parse(source, source_port)?),
+                destination: Some(parse(destination, destination_port)?),
+            })
+        },
+        _ => Err(ProxyProtocolError::new("v1 header is malformed")),
+    }
+}
+
+fn read_v2(mut stream: &TcpStream) -> Result<ProxyHeader, ProxyProtocolError> {
+    let mut fixed: [Byte; 11] = [0; 11];
+    stream.read_exact(&mut fixed)?;
+    if fixed[..7] != V2_SIGNATURE[5..] {
+        return Err(ProxyProtocolError::new("v2 signature mismatch"));
+    }
+    let (version_command, family) = (fixed[7], fixed[8]);
+    let length: usize = u16::from_be_bytes([fixed[9], fixed[10]]) as usize;
+    let mut payload: Vec<Byte> = vec![0; length];
+    stream.read_exact(payload.as_mut_slice())?;
+    if version_command >> 4 != 2 {
+        return Err(ProxyProtocolError::new("v2 version is not 2"));
+    }
+    // LOCAL commands (health checks from the balancer itself) carry no client
+    if version_command & 0x0F == 0 {
+        return Ok(ProxyHeader { source: None, destination: None });
+    }
+    match family {
+        0x11 if length >= 12 => {
+            let source: Ipv4Addr = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            let destination: Ipv4Addr = Ipv4Addr::new(payload[4], payload[5], payload[6], payload[7]);
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(IpAddr::V4(source), u16::from_be_bytes([payload[8], payload[9]]))),
+                destination: Some(SocketAddr::new(IpAddr::V4(destination), u16::from_be_bytes([payload[10], payload[11]]))),
+            })
+        },
+        0x21 if length >= 36 => {
+            let mut source: [Byte; 16] = [0; 16];
+            let mut destination: [Byte; 16] = [0; 16];
+            source.copy_from_slice(&payload[0..16]);
+            destination.copy_from_slice(&payload[16..32]);
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(source)), u16::from_be_bytes([payload[32], payload[33]]))),
+                destination: Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(destination)), u16::from_be_bytes([payload[34], payload[35]]))),
+            })
+        },
+        // UNSPEC, UDP and unix sockets have no meaningful client address for us
+        _ => Ok(ProxyHeader { source: None, destination: None }),
+    }
+}
+
+pub fn write_header(mut stream: &TcpStream, version: ProxyProtocolVersion, source: &SocketAddr, destination: &SocketAddr) -> Result<(), ProxyProtocolError> {
+    let (source_ip, destination_ip) = match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)),
+        (source_ip, destination_ip) => (IpAddr::V6(to_ipv6(source_ip)), IpAddr::V6(to_ipv6(destination_ip))),
+    };
+    let header: Vec<Byte> = match version {
+        ProxyProtocolVersion::V1 => format!(
+            "PROXY {} {} {} {} {}\r\n",
+            if source_ip.is_ipv4() { "TCP4" } else { "TCP6" },
+            source_ip,
+            destination_ip,
+            source.port(),
+            destination.port(),
+        ).into_bytes(),
+        ProxyProtocolVersion::V2 => {
+            let mut header: Vec<Byte> = V2_SIGNATURE.to_vec();
+            header.push(0x21);
+            match (source_ip, destination_ip) {
+                (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+                    header.extend_from_slice(&[0x11, 0x00, 12]);
+                    header.extend_from_slice(&source_ip.octets());
+                    header.extend_from_slice(&destination_ip.octets());
+                },
+                _ => {
+                    header.extend_from_slice(&[0x21, 0x00, 36]);
+                    header.extend_from_slice(&to_ipv6(source_ip).octets());
+                    header.extend_from_slice(&to_ipv6(destination_ip).octets());
+                },
+            }
+            header.extend_from_slice(&source.port().to_be_bytes());
+            header.extend_from_slice(&destination.port().to_be_bytes());
+            header
+        },
+        _ => return Err(ProxyProtocolError::new("only V1 or V2 headers can be sent")),
+    };
+    stream.write_all(header.as_slice())?;
+    Ok(())
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Shutdown, TcpListener};
+
+    // Reads a header from a connection the given bytes were sent on, then whatever followed it
+    fn read(bytes: &[Byte], version: ProxyProtocolVersion) -> (Result<ProxyHeader, ProxyProtocolError>, Vec<Byte>) {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client: TcpStream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(bytes).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let (mut server, _): (TcpStream, SocketAddr) = listener.accept().unwrap();
+        let header: Result<ProxyHeader, ProxyProtocolError> = read_header(&server, version);
+        let mut rest: Vec<Byte> = vec![];
+        let _ = server.read_to_end(&mut rest);
+        (header, rest)
+    }
+
+    fn addresses(header: ProxyHeader) -> (Option<SocketAddr>, Option<SocketAddr>) {
+        (header.source, header.destination)
+    }
+
+    fn v2(version_command: Byte, family: Byte, length: u16, payload: &[Byte]) -> Vec<Byte> {
+        let mut header: Vec<Byte> = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[version_command, family]);
+        header.extend_from_slice(&length.to_be_bytes());
+        header.extend_from_slice(payload);
+        header
+    }
+
+    #[test]
+    fn v1_tcp4_leaves_the_payload() {
+        let (header, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.2 5000 80\r\nGET / HTTP/1.1\r\n", ProxyProtocolVersion::V1);
+        assert_eq!(addresses(header.ok().unwrap()), (
+            Some("192.0.2.1:5000".parse::<SocketAddr>().unwrap()),
+            Some("198.51.100.2:80".parse::<SocketAddr>().unwrap()),
+        ));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let (header, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 5000 443\r\n", ProxyProtocolVersion::ANY);
+        assert_eq!(addresses(header.ok().unwrap()), (
+            Some("[2001:db8::1]:5000".parse::<SocketAddr>().unwrap()),
+            Some("[2001:db8::2]:443".parse::<SocketAddr>().unwrap()),
+        ));
+    }
+
+    #[test]
+    fn v1_unknown_has_no_addresses() {
+        let (header, rest) = read(b"PROXY UNKNOWN\r\npayload", ProxyProtocolVersion::V1);
+        assert_eq!(addresses(header.ok().unwrap()), (None, None));
+        assert_eq!(rest, b"payload");
+    }
+
+    #[test]
+    fn v1_family_must_match_the_addresses() {
+        let (header, _) = read(b"PROXY TCP4 2001:db8::1 2001:db8::2 5000 443\r\n", ProxyProtocolVersion::V1);
+        assert!(header.is_err());
+    }
+
+    #[test]
+    fn v1_longer_than_107_bytes_is_rejected() {
+        let mut line: Vec<Byte> = b"PROXY UNKNOWN ".to_vec();
+        line.extend_from_slice(&[b'x'; 100]);
+        line.extend_from_slice(b"\r\n");
+        let (header, _) = read(line.as_slice(), ProxyProtocolVersion::V1);
+        assert_eq!(header.err().unwrap().reason, "v1 header exceeds 107 bytes");
+    }
+
+    #[test]
+    fn v2_tcp4() {
+        let payload: [Byte; 12] = [192, 0, 2, 1, 198, 51, 100, 2, 0x13, 0x88, 0x00, 0x50];
+        let mut bytes: Vec<Byte> = v2(0x21, 0x11, 12, &payload);
+        bytes.extend_from_slice(b"payload");
+        let (header, rest) = read(bytes.as_slice(), ProxyProtocolVersion::V2);
+        assert_eq!(addresses(header.ok().unwrap()), (
+            Some("192.0.2.1:5000".parse::<SocketAddr>().unwrap()),
+            Some("198.51.100.2:80".parse::<SocketAddr>().unwrap()),
+        ));
+        assert_eq!(rest, b"payload");
+    }
+
+    #[test]
+    fn v2_local_has_no_addresses() {
+        let (header, rest) = read(v2(0x20, 0x00, 0, &[]).as_slice(), ProxyProtocolVersion::V2);
+        assert_eq!(addresses(header.ok().unwrap()), (None, None));
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn v2_bad_signature_is_rejected() {
+        let mut bytes: Vec<Byte> = v2(0x21, 0x11, 12, &[0; 12]);
+        bytes[8] = 0x00;
+        let (header, _) = read(bytes.as_slice(), ProxyProtocolVersion::V2);
+        assert_eq!(header.err().unwrap().reason, "v2 signature mismatch");
+        let (header, _) = read(b"GET / HTTP/1.1\r\n", ProxyProtocolVersion::V2);
+        assert_eq!(header.err().unwrap().reason, "missing header signature");
+    }
+
+    #[test]
+    fn v2_length_past_the_end_is_rejected() {
+        let (header, _) = read(v2(0x21, 0x11, 12, &[192, 0, 2, 1]).as_slice(), ProxyProtocolVersion::V2);
+        assert!(header.is_err());
+    }
+}
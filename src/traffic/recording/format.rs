@@ -0,0 +1,152 @@
+use crate::traffic::bindingset::TrafficDirection;
+
+type Byte = u8;
+
+// A recording file is MAGIC and VERSION followed by records until the end of the file. Every record starts with its
+// kind and the session's request id, numbers are LEB128 varints and strings are length prefixed UTF-8:
+//   OPEN:  started (micros since the epoch), binding, client, upstream
+//   DATA:  offset (micros since the session opened), direction, length, bytes as read from that direction's peer
+//   CLOSE: offset
+// A file only ever holds whole records, sessions still open when a file is rotated are opened again in the next one.
+pub static MAGIC: &'static [Byte] = b"TPREC";
+pub static VERSION: Byte = 1;
+
+static OPEN_KIND: Byte = 1;
+static DATA_KIND: Byte = 2;
+static CLOSE_KIND: Byte = 3;
+
+#[derive(Debug, Clone)]
+pub struct SessionHeader {
+    pub id: uuid::Uuid,
+    pub started: u64,
+    pub binding: String,
+    pub client: String,
+    pub upstream: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionChunk {
+    pub id: uuid::Uuid,
+    pub offset: u64,
+    pub direction: TrafficDirection,
+    pub bytes: Vec<Byte>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SessionRecord {
+    OPEN(SessionHeader),
+    DATA(SessionChunk),
+    CLOSE(uuid::Uuid, u64),
+}
+
+impl SessionRecord {
+    pub fn id(&self) -> uuid::Uuid {
+        match self {
+            SessionRecord::OPEN(header) => header.id,
+            SessionRecord::DATA(chunk) => chunk.id,
+            SessionRecord::CLOSE(id, _) => *id,
+        }
+    }
+    pub fn encode(&self) -> Vec<Byte> {
+        let mut encoded: Vec<Byte> = vec![];
+        match self {
+            SessionRecord::OPEN(header) => {
+                encoded.push(OPEN_KIND);
+                encoded.extend_from_slice(header.id.as_bytes());
+                write_varint(&mut encoded, header.started);
+                write_string(&mut encoded, header.binding.as_str());
+                write_string(&mut encoded, header.client.as_str());
+                write_string(&mut encoded, header.upstream.as_str());
+            },
+            SessionRecord::DATA(chunk) => {
+                encoded.push(DATA_KIND);
+                encoded.extend_from_slice(chunk.id.as_bytes());
+                write_varint(&mut encoded, chunk.offset);
+                encoded.push(match chunk.direction {
+                    TrafficDirection::EGRESS => 0,
+                    TrafficDirection::INGRESS => 1,
+                });
+                write_varint(&mut encoded, chunk.bytes.len() as u64);
+                encoded.extend_from_slice(chunk.bytes.as_slice());
+            },
+            SessionRecord::CLOSE(id, offset) => {
+                encoded.push(CLOSE_KIND);
+                encoded.extend_from_slice(id.as_bytes());
+                write_varint(&mut encoded, *offset);
+            },
+        }
+        encoded
+    }
+}
+
+// Reads one record from the start of input, returning it with the number of bytes it took up. None when input holds no
+// complete record, e.g. the tail of a file that was still being written
+pub fn decode(input: &[Byte]) -> Option<(SessionRecord, usize)> {
+    let (kind, rest): (&Byte, &[Byte]) = input.split_first()?;
+    let id: uuid::Uuid = uuid::Uuid::from_slice(rest.get(..16)?).ok()?;
+    let mut position: usize = 17;
+    let record: SessionRecord = match *kind {
+        kind if kind == OPEN_KIND => SessionRecord::OPEN(SessionHeader {
+            id,
+            started: read_varint(input, &mut position)?,
+            binding: read_string(input, &mut position)?,
+            client: read_string(input, &mut position)?,
+            upstream: read_string(input, &mut position)?,
+        }),
+        kind if kind == DATA_KIND => {
+            let offset: u64 = read_varint(input, &mut position)?;
+            let direction: TrafficDirection = match *input.get(position)? {
+                0 => TrafficDirection::EGRESS,
+                1 => TrafficDirection::INGRESS,
+                _ => return None,
+            };
+            position += 1;
+            let length: usize = read_varint(input, &mut position)? as usize;
+            let bytes: Vec<Byte> = input.get(position..position.checked_add(length)?)?.to_vec();
+            position += length;
+            SessionRecord::DATA(SessionChunk { id, offset, direction, bytes })
+        },
+        kind if kind == CLOSE_KIND => SessionRecord::CLOSE(id, read_varint(input, &mut position)?),
+        _ => return None,
+    };
+    Some((record, position))
+}
+
+pub fn file_header() -> Vec<Byte> {
+    let mut header: Vec<Byte> = MAGIC.to_vec();
+    header.push(VERSION);
+    header
+}
+
+fn write_varint(output: &mut Vec<Byte>, mut value: u64) {
+    while value >= 0x80 {
+        output.push((value as Byte & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as Byte);
+}
+
+fn write_string(output: &mut Vec<Byte>, value: &str) {
+    write_varint(output, value.len() as u64);
+    output.extend_from_slice(value.as_bytes());
+}
+
+fn read_varint(input: &[Byte], position: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte: Byte = *input.get(*position)?;
+        *position += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn read_string(input: &[Byte], position: &mut usize) -> Option<String> {
+    let length: usize = read_varint(input, position)? as usize;
+    let value: &[Byte] = input.get(*position..position.checked_add(length)?)?;
+    *position += length;
+    String::from_utf8(value.to_vec()).ok()
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::fs::{self, DirEntry};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::traffic::bindingset::TrafficDirection;
+use crate::traffic::recording::format::{self, SessionChunk, SessionHeader, SessionRecord};
+use crate::traffic::recording::recorder;
+
+type Byte = u8;
+
+// One recorded connection put back together from every file it was written to
+pub struct RecordedSession {
+    pub header: SessionHeader,
+    pub chunks: Vec<SessionChunk>, // In the order they were read, both directions
+    pub closed: Option<u64>, // Micros after opening, None when the recording ended first
+}
+
+impl RecordedSession {
+    pub fn bytes(&self, direction: TrafficDirection) -> Vec<Byte> {
+        self.chunks.iter()
+            .filter(|chunk: &&SessionChunk| chunk.direction == direction)
+            .flat_map(|chunk: &SessionChunk| chunk.bytes.iter().cloned())
+            .collect()
+    }
+}
+
+// Accepts a single recording file or a directory of them, sessions come back in the order they opened
+pub fn read_sessions(path: &Path) -> io::Result<Vec<RecordedSession>> {
+    let files: Vec<PathBuf> = if path.is_dir() {
+        let mut files: Vec<PathBuf> = fs::read_dir(path)?
+            .filter_map(Result::ok)
+            .map(|entry: DirEntry| entry.path())
+            .filter(|file: &PathBuf| recorder::is_recording_file(file))
+            .collect();
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+    let mut sessions: HashMap<uuid::Uuid, RecordedSession> = HashMap::new();
+    for file in files {
+        for record in read_records(&file)? {
+            match record {
+                SessionRecord::OPEN(header) => {
+                    // Sessions open across a rotation are opened again in the next file
+                    sessions.entry(header.id).or_insert(RecordedSession { header, chunks: vec![], closed: None });
+                },
+                SessionRecord::DATA(chunk) => {
+                    if let Some(session) = sessions.get_mut(&chunk.id) {
+                        session.chunks.push(chunk);
+                    }
+                },
+                SessionRecord::CLOSE(id, offset) => {
+                    if let Some(session) = sessions.get_mut(&id) {
+                        session.closed = Some(offset);
+                    }
+                },
+            }
+        }
+    }
+    let mut sessions: Vec<RecordedSession> = sessions.into_iter().map(|(_, session)| session).collect();
+    sessions.sort_by_key(|session: &RecordedSession| session.header.started);
+    Ok(sessions)
+}
+
+pub fn read_records(file: &Path) -> io::Result<Vec<SessionRecord>> {
+    let contents: Vec<Byte> = fs::read(file)?;
+    let header: Vec<Byte> = format::file_header();
+    if !contents.starts_with(format::MAGIC) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a session recording", file.display())));
+    }
+    if !contents.starts_with(header.as_slice()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} was recorded with an unsupported version", file.display())));
+    }
+    let mut records: Vec<SessionRecord> = vec![];
+    let mut position: usize = header.len();
+    while let Some((record, length)) = format::decode(&contents[position..]) {
+        records.push(record);
+        position += length;
+    }
+    if position < contents.len() {
+        eprintln!("RECORDING Ignored {} bytes at the end of [{}]", contents.len() - position, file.display());
+    }
+    Ok(records)
+}
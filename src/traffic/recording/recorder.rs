@@ -0,0 +1,230 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, DirEntry, File};
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use slog::Logger;
+
+use crate::traffic::bindingset::TrafficDirection;
+use crate::traffic::recording::format::{self, SessionChunk, SessionHeader, SessionRecord};
+use crate::if_let_some;
+
+type Byte = u8;
+
+// Records waiting for the writer, a full queue holds up traffic rather than leaving gaps in the recording
+static RECORDING_QUEUE_RECORDS: usize = 4096;
+pub static RECORDING_FILE_PREFIX: &'static str = "sessions_";
+pub static RECORDING_FILE_EXTENSION: &'static str = ".rec";
+
+#[derive(Debug, Clone)]
+pub struct RecordingSettings {
+    pub directory: String,
+    pub max_file_bytes: u64,
+    pub max_total_bytes: u64, // Oldest files are deleted past this, 0 keeps every file
+}
+
+// ---- SessionRecorder ----
+
+// Writes every session on every binding to rotating files in one directory, from its own thread
+pub(crate) struct SessionRecorder {
+    sender: SyncSender<SessionRecord>,
+}
+
+impl SessionRecorder {
+    pub fn new(settings: RecordingSettings, logger: &Logger) -> SessionRecorder {
+        let (sender, receiver): (SyncSender<SessionRecord>, Receiver<SessionRecord>) = mpsc::sync_channel(RECORDING_QUEUE_RECORDS);
+        let writer: RecordingWriter = RecordingWriter::new(settings, logger);
+        thread::spawn(move || writer.run(receiver));
+        SessionRecorder { sender }
+    }
+    // Sessions on bindings without an upstream are recorded with an empty one
+    pub fn open(&self, id: uuid::Uuid, binding: &str, client: &SocketAddr, upstream: Option<&SocketAddr>) -> SessionRecording {
+        let started: u64 = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since: Duration| since.as_micros() as u64);
+        let _ = self.sender.send(SessionRecord::OPEN(SessionHeader {
+            id,
+            started,
+            binding: String::from(binding),
+            client: client.to_string(),
+            upstream: upstream.map_or(String::new(), |upstream: &SocketAddr| upstream.to_string()),
+        }));
+        SessionRecording {
+            session: Arc::new(LiveSession {
+                id,
+                opened: Instant::now(),
+                sender: self.sender.clone(),
+            }),
+        }
+    }
+}
+
+// ---- SessionRecording ----
+
+struct LiveSession {
+    id: uuid::Uuid,
+    opened: Instant,
+    sender: SyncSender<SessionRecord>,
+}
+
+impl LiveSession {
+    fn offset(&self) -> u64 {
+        self.opened.elapsed().as_micros() as u64
+    }
+}
+
+impl Drop for LiveSession {
+    // The session closes once both directions have let go of it
+    fn drop(&mut self) {
+        let _ = self.sender.send(SessionRecord::CLOSE(self.id, self.offset()));
+    }
+}
+
+// One connection's handle on the recorder, shared by both directions
+#[derive(Clone)]
+pub(crate) struct SessionRecording {
+    session: Arc<LiveSession>,
+}
+
+impl SessionRecording {
+    pub fn record(&self, direction: TrafficDirection, bytes: &[Byte]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let _ = self.session.sender.send(SessionRecord::DATA(SessionChunk {
+            id: self.session.id,
+            offset: self.session.offset(),
+            direction,
+            bytes: bytes.to_vec(),
+        }));
+    }
+}
+
+// ---- RecordingWriter ----
+
+struct RecordingWriter {
+    settings: RecordingSettings,
+    file: Option<BufWriter<File>>,
+    file_bytes: u64,
+    file_has_data: bool, // Set once the current file holds more than the sessions carried over into it
+    files: VecDeque<(PathBuf, u64)>, // Every recording file in the directory, oldest first
+    open: HashMap<uuid::Uuid, SessionRecord>,
+    logger: Logger,
+}
+
+impl RecordingWriter {
+    fn new(settings: RecordingSettings, logger: &Logger) -> RecordingWriter {
+        RecordingWriter {
+            files: RecordingWriter::existing_files(settings.directory.as_str()),
+            settings,
+            file: None,
+            file_bytes: 0,
+            file_has_data: false,
+            open: HashMap::new(),
+            logger: logger.clone(),
+        }
+    }
+    fn existing_files(directory: &str) -> VecDeque<(PathBuf, u64)> {
+        let mut files: Vec<(PathBuf, u64)> = match fs::read_dir(directory) {
+            Ok(entries) => entries.filter_map(Result::ok)
+                .filter(|entry: &DirEntry| is_recording_file(&entry.path()))
+                .map(|entry: DirEntry| (entry.path(), entry.metadata().map_or(0, |metadata: fs::Metadata| metadata.len())))
+                .collect(),
+            Err(_) => vec![],
+        };
+        // Names sort by creation time
+        files.sort();
+        files.into()
+    }
+    fn run(mut self, receiver: Receiver<SessionRecord>) {
+        if let Err(e) = fs::create_dir_all(self.settings.directory.as_str()) {
+            info!(self.logger, "RECORDING Unable to create directory [{}], sessions will not be recorded: {}", self.settings.directory, e);
+            receiver.iter().for_each(drop);
+            return;
+        }
+        while let Ok(record) = receiver.recv() {
+            self.write(record);
+            // Flushed whenever the queue runs dry so a recording is never far behind the traffic
+            while let Ok(record) = receiver.try_recv() {
+                self.write(record);
+            }
+            if let Some(file) = self.file.as_mut() {
+                let _ = file.flush();
+            }
+        }
+    }
+    fn write(&mut self, record: SessionRecord) {
+        let encoded: Vec<Byte> = record.encode();
+        if self.file.is_none() || (self.file_has_data && self.file_bytes + encoded.len() as u64 > self.settings.max_file_bytes) {
+            self.rotate();
+        }
+        match &record {
+            SessionRecord::OPEN(_) => { self.open.insert(record.id(), record.clone()); },
+            SessionRecord::CLOSE(id, _) => { self.open.remove(id); },
+            SessionRecord::DATA(_) => {},
+        }
+        self.append(encoded.as_slice());
+        self.file_has_data = true;
+    }
+    fn rotate(&mut self) {
+        if let Some(mut file) = self.file.take() {
+            let _ = file.flush();
+        }
+        let path: PathBuf = Path::new(self.settings.directory.as_str()).join(format!(
+            "{}{}{}",
+            RECORDING_FILE_PREFIX,
+            chrono::Utc::now().format("%Y%m%d-%H%M%S%.6f"),
+            RECORDING_FILE_EXTENSION
+        ));
+        let file: File = match File::create(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                info!(self.logger, "RECORDING Unable to create [{}]: {}", path.display(), e);
+                return;
+            },
+        };
+        debug!(self.logger, "RECORDING Writing sessions to [{}]", path.display());
+        self.file = Some(BufWriter::new(file));
+        self.file_bytes = 0;
+        self.file_has_data = false;
+        self.files.push_back((path, 0));
+        self.append(format::file_header().as_slice());
+        let carried_over: Vec<Vec<Byte>> = self.open.values().map(SessionRecord::encode).collect();
+        for encoded in carried_over {
+            self.append(encoded.as_slice());
+        }
+        self.prune();
+    }
+    fn append(&mut self, encoded: &[Byte]) {
+        if_let_some!{file = self.file.as_mut()};
+        if let Err(e) = file.write_all(encoded) {
+            info!(self.logger, "RECORDING Failed to write recording: {}", e);
+            return;
+        }
+        self.file_bytes += encoded.len() as u64;
+        if let Some((_, size)) = self.files.back_mut() {
+            *size = self.file_bytes;
+        }
+    }
+    // Deletes the oldest files until the directory is back under its cap, never the file being written
+    fn prune(&mut self) {
+        if self.settings.max_total_bytes == 0 {
+            return;
+        }
+        while self.files.len() > 1 && self.files.iter().map(|(_, size): &(PathBuf, u64)| size).sum::<u64>() > self.settings.max_total_bytes {
+            let (path, _): (PathBuf, u64) = self.files.pop_front().unwrap();
+            match fs::remove_file(&path) {
+                Ok(_) => debug!(self.logger, "RECORDING Removed [{}]", path.display()),
+                Err(e) => info!(self.logger, "RECORDING Unable to remove [{}]: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+pub fn is_recording_file(path: &Path) -> bool {
+    path.file_name().and_then(|name| name.to_str()).map_or(false, |name: &str| {
+        name.starts_with(RECORDING_FILE_PREFIX) && name.ends_with(RECORDING_FILE_EXTENSION)
+    })
+}
@@ -0,0 +1,168 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use rhai::{Dynamic, Engine, Map, Scope, AST, INT};
+use slog::Logger;
+
+use crate::traffic::enforcer::Verdict;
+use crate::traffic::http::message::{self, HttpRequestHead, HttpResponseHead};
+use crate::traffic::template::{self, TemplateVariables};
+
+type Byte = u8;
+
+// Scripts run inline on every request they see, these keep a runaway one from stalling its connection
+static MAX_OPERATIONS: u64 = 100_000;
+static MAX_CALL_LEVELS: usize = 16;
+static MAX_EXPR_DEPTH: usize = 64;
+static MAX_STRING_SIZE: usize = 64 * 1024;
+static MAX_COLLECTION_SIZE: usize = 1024;
+static DENIED_STATUS: u16 = 403;
+
+// ---- RequestScript ----
+
+// A SCRIPT rule's source, compiled once per binding. Every request is evaluated in a fresh scope
+pub(crate) struct RequestScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl RequestScript {
+    pub(crate) fn compile(source: &str, logger: &Logger) -> Result<RequestScript, String> {
+        let (print_logger, debug_logger): (Logger, Logger) = (logger.clone(), logger.clone());
+        let mut engine: Engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_COLLECTION_SIZE)
+            .set_max_map_size(MAX_COLLECTION_SIZE)
+            .on_print(move |text: &str| info!(print_logger, "SCRIPT {}", text))
+            .on_debug(move |text: &str, _, _| debug!(debug_logger, "SCRIPT {}", text));
+        // Scripts are fixed at load, nothing should be able to compile more of them per request
+        engine.disable_symbol("eval");
+        let ast: AST = engine.compile(source).map_err(|e| e.to_string())?;
+        Ok(RequestScript { engine, ast })
+    }
+    // None lets the request through, with whatever the script transformed already applied
+    pub(crate) fn evaluate(&self, request: &mut HttpRequestHead, variables: &TemplateVariables) -> Result<Option<Verdict>, String> {
+        let mut scope: Scope = request_scope(request, variables);
+        let result: Dynamic = self.engine.eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast).map_err(|e| e.to_string())?;
+        if result.is_unit() {
+            return Ok(None);
+        }
+        if let Ok(allowed) = result.as_bool() {
+            return Ok(match allowed {
+                true => None,
+                false => Some(Verdict::RESPOND(HttpResponseHead::local(DENIED_STATUS), vec![])),
+            });
+        }
+        let type_name: &str = result.type_name();
+        let outcome: Map = result.try_cast::<Map>()
+            .ok_or(format!("script returned a {}, expected a bool or a map", type_name))?;
+        match outcome.contains_key("deny") {
+            true => deny(outcome).map(Some),
+            false => transform(outcome, request).map(|_| None),
+        }
+    }
+}
+
+// ---- Scope ----
+
+fn request_scope(request: &HttpRequestHead, variables: &TemplateVariables) -> Scope<'static> {
+    let mut fields: TemplateVariables = TemplateVariables::new();
+    template::insert_request_variables(&mut fields, request);
+    // Repeated headers are folded into one value, the way a single header would have carried them
+    let mut headers: Map = Map::new();
+    for header in request.headers.iter() {
+        let name: String = header.name.to_ascii_lowercase();
+        let value: String = match headers.get(name.as_str()) {
+            Some(existing) => format!("{}, {}", existing, header.value),
+            None => header.value.clone(),
+        };
+        headers.insert(name.into(), value.into());
+    }
+    let now: DateTime<Utc> = Utc::now();
+    let mut scope: Scope = Scope::new();
+    for name in ["method", "target", "path", "query", "host"] {
+        scope.push(name, fields.remove(name).unwrap_or_default());
+    }
+    scope.push("version", request.version.clone())
+        .push("headers", headers)
+        .push("client_ip", variables.get("client_ip").cloned().unwrap_or_default())
+        .push("binding", variables.get("binding_name").cloned().unwrap_or_default())
+        .push("time", now.timestamp() as INT)
+        .push("hour", now.hour() as INT)
+        .push("weekday", now.weekday().num_days_from_monday() as INT);
+    scope
+}
+
+// ---- Outcomes ----
+
+fn deny(outcome: Map) -> Result<Verdict, String> {
+    let mut status: u16 = DENIED_STATUS;
+    let mut body: Vec<Byte> = vec![];
+    let mut headers: Vec<(String, String)> = vec![];
+    for (key, value) in outcome {
+        match key.as_str() {
+            "deny" if value.is_bool() => {},
+            "deny" => status = match value.as_int() {
+                Ok(code) if (100..=599).contains(&code) => code as u16,
+                _ => return Err(format!("deny must be true or a status between 100 and 599, got {}", value)),
+            },
+            "body" => body = string_value(&key, value)?.into_bytes(),
+            "headers" => headers = header_values(&key, value)?,
+            _ => return Err(format!("unknown key [{}] in a deny", key)),
+        }
+    }
+    let mut response: HttpResponseHead = HttpResponseHead::local(status);
+    for (name, value) in headers {
+        response.headers.set(name.as_str(), value);
+    }
+    Ok(Verdict::RESPOND(response, body))
+}
+
+// Applied to a copy first, so a transform with one bad field changes nothing
+fn transform(outcome: Map, request: &mut HttpRequestHead) -> Result<(), String> {
+    let mut transformed: HttpRequestHead = request.clone();
+    for (key, value) in outcome {
+        match key.as_str() {
+            "method" | "target" => {
+                let field: String = string_value(&key, value)?;
+                if field.is_empty() || field.contains(' ') {
+                    return Err(format!("{} cannot be empty or contain spaces", key));
+                }
+                match key.as_str() {
+                    "method" => transformed.method = field,
+                    _ => transformed.target = field,
+                }
+            },
+            "set_headers" => for (name, value) in header_values(&key, value)? {
+                transformed.headers.set(name.as_str(), value);
+            },
+            "remove_headers" => for name in value.into_array().map_err(|_| String::from("remove_headers must be an array"))? {
+                transformed.headers.remove(string_value("remove_headers", name)?.as_str());
+            },
+            _ => return Err(format!("unknown key [{}] in a transform", key)),
+        }
+    }
+    *request = transformed;
+    Ok(())
+}
+
+fn string_value(key: &str, value: Dynamic) -> Result<String, String> {
+    let value: String = value.into_string().map_err(|type_name: &str| format!("{} must be a string, got a {}", key, type_name))?;
+    match value.contains(['\r', '\n']) {
+        true => Err(format!("{} cannot contain line breaks", key)),
+        false => Ok(value),
+    }
+}
+
+fn header_values(key: &str, value: Dynamic) -> Result<Vec<(String, String)>, String> {
+    let headers: Map = value.try_cast::<Map>().ok_or(format!("{} must be a map", key))?;
+    let mut values: Vec<(String, String)> = vec![];
+    for (name, value) in headers {
+        if !message::valid_header_name(name.as_str()) {
+            return Err(format!("invalid header name [{}] in {}", name, key));
+        }
+        values.push((name.to_string(), string_value(name.as_str(), value)?));
+    }
+    Ok(values)
+}
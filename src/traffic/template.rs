@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use regex::{Regex, Captures};
+use lazy_static::lazy_static;
+
+use crate::traffic::http::message::HttpRequestHead;
+use crate::traffic::http::url::RequestUrl;
+
+lazy_static! {
+    static ref TEMPLATE_VARIABLE: Regex = Regex::new(r"\$\{([A-Za-z][A-Za-z0-9_.\-]*)\}").unwrap();
+}
+
+pub type TemplateVariables = HashMap<String, String>;
+
+// Substitutes ${name} placeholders, unknown names are left as-is so regex groups such as ${1} survive
+pub fn render(template: &str, variables: &TemplateVariables) -> String {
+    render_with(template, variables, |value: &str| String::from(value))
+}
+
+// For templates that become regex replacements, a $ in a request's own values must not read as a capture group
+pub fn render_replacement(template: &str, variables: &TemplateVariables) -> String {
+    render_with(template, variables, |value: &str| value.replace('$', "$$"))
+}
+
+// Header values also drop line breaks from substituted values, a decoded ${query.x} of "a%0d%0aSet-Cookie: y" would split the head
+pub fn render_header_value(template: &str, variables: &TemplateVariables) -> String {
+    render_with(template, variables, without_line_breaks)
+}
+
+pub fn render_header_replacement(template: &str, variables: &TemplateVariables) -> String {
+    render_with(template, variables, |value: &str| without_line_breaks(value).replace('$', "$$"))
+}
+
+fn without_line_breaks(value: &str) -> String {
+    value.chars().filter(|c: &char| *c != '\r' && *c != '\n').collect()
+}
+
+fn render_with<F: Fn(&str) -> String>(template: &str, variables: &TemplateVariables, substitute: F) -> String {
+    if !template.contains("${") {
+        return String::from(template);
+    }
+    TEMPLATE_VARIABLE.replace_all(template, |captures: &Captures| {
+        match variables.get(&captures[1]) {
+            Some(value) => substitute(value.as_str()),
+            None => String::from(&captures[0]),
+        }
+    }).into_owned()
+}
+
+pub fn insert_request_variables(variables: &mut TemplateVariables, request: &HttpRequestHead) {
+    let (path, query) = match request.target.find('?') {
+        Some(index) => (&request.target[..index], &request.target[index + 1..]),
+        None => (request.target.as_str(), ""),
+    };
+    variables.insert(String::from("method"), request.method.clone());
+    variables.insert(String::from("target"), request.target.clone());
+    variables.insert(String::from("path"), String::from(path));
+    variables.insert(String::from("query"), String::from(query));
+    variables.insert(String::from("host"), String::from(request.headers.get("host").unwrap_or("")));
+}
+
+// ${header.<name>} with the name lowercased and ${query.<name>}, the first value wins when one is repeated
+pub fn insert_field_variables(variables: &mut TemplateVariables, request: &HttpRequestHead) {
+    for header in request.headers.iter() {
+        variables.entry(format!("header.{}", header.name.to_ascii_lowercase())).or_insert_with(|| header.value.clone());
+    }
+    for (name, value) in RequestUrl::new(request).query {
+        variables.entry(format!("query.{}", name)).or_insert(value);
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+
+use wasmi::{Caller, Engine, Extern, ExternType, FuncType, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+use wasmi::core::ValType;
+use slog::Logger;
+
+use crate::traffic::bindingset::WasmFilterRule;
+use crate::traffic::filter::{Filter, FilterAction, FilterConnection};
+use crate::traffic::http::message::{self, HttpRequestHead, HttpResponseHead};
+use crate::if_let_some;
+
+type Byte = u8;
+
+static HOST_MODULE: &'static str = "proxy";
+static MEMORY_EXPORT: &'static str = "memory";
+static ON_CONNECT: &'static str = "on_connect";
+static ON_REQUEST_HEADERS: &'static str = "on_request_headers";
+static ON_REQUEST_BODY: &'static str = "on_request_body";
+static ON_RESPONSE_HEADERS: &'static str = "on_response_headers";
+static ON_RESPONSE_BODY: &'static str = "on_response_body";
+static NOT_FOUND: i32 = -1;
+static REFUSED: i32 = -1;
+
+// ---- Invocation ----
+
+// Which message a hook was given, the other one is only readable
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum HookMessage {
+    CONNECTION,
+    REQUEST,
+    RESPONSE,
+}
+
+// Everything one hook call can see, moved into a fresh store and taken back out once the hook returns
+struct Invocation {
+    limits: StoreLimits,
+    message: HookMessage,
+    editable: bool, // Heads can only be changed from the header hooks
+    binding: String,
+    client: SocketAddr,
+    upstream: Option<SocketAddr>,
+    request: Option<HttpRequestHead>,
+    response: Option<HttpResponseHead>,
+    body: Option<Vec<Byte>>, // The chunk, only set in the body hooks
+    respond_body: Vec<Byte>, // Sent when the hook answers with a status
+    logger: Logger,
+}
+
+impl Invocation {
+    fn new(filter: &WasmFilter, connection: &FilterConnection, message: HookMessage) -> Invocation {
+        Invocation {
+            limits: StoreLimitsBuilder::new().memory_size(filter.rule.max_memory_bytes).instances(1).build(),
+            message,
+            editable: false,
+            binding: connection.binding.clone(),
+            client: connection.client,
+            upstream: connection.upstream,
+            request: None,
+            response: None,
+            body: None,
+            respond_body: vec![],
+            logger: filter.logger.clone(),
+        }
+    }
+    fn get_header(&self, name: &str) -> Option<String> {
+        let value: Option<&str> = match self.message {
+            HookMessage::REQUEST => self.request.as_ref().and_then(|request: &HttpRequestHead| request.headers.get(name)),
+            HookMessage::RESPONSE => self.response.as_ref().and_then(|response: &HttpResponseHead| response.headers.get(name)),
+            HookMessage::CONNECTION => None,
+        };
+        value.map(String::from)
+    }
+    fn set_header(&mut self, name: &str, value: Option<String>) -> bool {
+        if !self.editable || !message::valid_header_name(name) || value.as_ref().is_some_and(|value: &String| value.contains(['\r', '\n'])) {
+            return false;
+        }
+        let headers: &mut message::HttpHeaders = match (self.message, self.request.as_mut(), self.response.as_mut()) {
+            (HookMessage::REQUEST, Some(request), _) => &mut request.headers,
+            (HookMessage::RESPONSE, _, Some(response)) => &mut response.headers,
+            _ => return false,
+        };
+        match value {
+            Some(value) => headers.set(name, value),
+            None => { headers.remove(name); },
+        }
+        true
+    }
+    fn get_property(&self, key: &str) -> Option<String> {
+        match key {
+            "binding" => Some(self.binding.clone()),
+            "client" => Some(self.client.to_string()),
+            "upstream" => self.upstream.map(|upstream: SocketAddr| upstream.to_string()),
+            "method" => self.request.as_ref().map(|request: &HttpRequestHead| request.method.clone()),
+            "target" => self.request.as_ref().map(|request: &HttpRequestHead| request.target.clone()),
+            "status" => self.response.as_ref().map(|response: &HttpResponseHead| response.status.to_string()),
+            "reason" => self.response.as_ref().map(|response: &HttpResponseHead| response.reason.clone()),
+            "version" => match self.message {
+                HookMessage::REQUEST => self.request.as_ref().map(|request: &HttpRequestHead| request.version.clone()),
+                HookMessage::RESPONSE => self.response.as_ref().map(|response: &HttpResponseHead| response.version.clone()),
+                HookMessage::CONNECTION => None,
+            },
+            _ => None,
+        }
+    }
+    fn set_property(&mut self, key: &str, value: String) -> bool {
+        if !self.editable || value.contains(['\r', '\n']) {
+            return false;
+        }
+        match (self.message, key, self.request.as_mut(), self.response.as_mut()) {
+            (HookMessage::REQUEST, "method", Some(request), _) if !value.is_empty() && !value.contains(' ') => request.method = value,
+            (HookMessage::REQUEST, "target", Some(request), _) if !value.is_empty() && !value.contains(' ') => request.target = value,
+            (HookMessage::RESPONSE, "status", _, Some(response)) => match value.parse::<u16>() {
+                Ok(status) if (100..=599).contains(&status) => {
+                    response.status = status;
+                    response.reason = String::from(message::reason_phrase(status));
+                },
+                _ => return false,
+            },
+            (HookMessage::RESPONSE, "reason", _, Some(response)) => response.reason = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+// ---- Guest memory ----
+
+fn guest_memory(caller: &Caller<'_, Invocation>) -> Result<Memory, wasmi::Error> {
+    caller.get_export(MEMORY_EXPORT).and_then(Extern::into_memory).ok_or(wasmi::Error::new("module exports no memory"))
+}
+
+fn read_guest(caller: &Caller<'_, Invocation>, pointer: i32, length: i32) -> Result<Vec<Byte>, wasmi::Error> {
+    let memory: Memory = guest_memory(caller)?;
+    let start: usize = pointer as u32 as usize;
+    let end: usize = start.checked_add(length as u32 as usize).ok_or(wasmi::Error::new("guest range overflows"))?;
+    memory.data(caller).get(start..end).map(<[Byte]>::to_vec).ok_or(wasmi::Error::new("guest range out of bounds"))
+}
+
+fn read_guest_str(caller: &Caller<'_, Invocation>, pointer: i32, length: i32) -> Result<Option<String>, wasmi::Error> {
+    Ok(String::from_utf8(read_guest(caller, pointer, length)?).ok())
+}
+
+// Copies as much of the value as fits, returning its full length so the module can retry with a larger buffer
+fn write_guest(caller: &mut Caller<'_, Invocation>, value: Option<Vec<Byte>>, pointer: i32, capacity: i32) -> Result<i32, wasmi::Error> {
+    if_let_some!{value = value, Ok(NOT_FOUND)};
+    let memory: Memory = guest_memory(caller)?;
+    let copied: usize = value.len().min(capacity as u32 as usize);
+    memory.write(caller, pointer as u32 as usize, &value[..copied]).map_err(|_| wasmi::Error::new("guest range out of bounds"))?;
+    Ok(value.len() as i32)
+}
+
+// The imports a module can take from the "proxy" namespace. Getters return the value's length or -1 when there is none,
+// setters return 0 or -1 when the value cannot be changed from the hook
+fn host_functions(engine: &Engine) -> Linker<Invocation> {
+    let mut linker: Linker<Invocation> = Linker::new(engine);
+    linker.func_wrap(HOST_MODULE, "get_header", |mut caller: Caller<'_, Invocation>, name: i32, name_length: i32, out: i32, capacity: i32| -> Result<i32, wasmi::Error> {
+        let value: Option<Vec<Byte>> = read_guest_str(&caller, name, name_length)?
+            .and_then(|name: String| caller.data().get_header(name.as_str()))
+            .map(String::into_bytes);
+        write_guest(&mut caller, value, out, capacity)
+    }).unwrap();
+    linker.func_wrap(HOST_MODULE, "set_header", |mut caller: Caller<'_, Invocation>, name: i32, name_length: i32, value: i32, value_length: i32| -> Result<i32, wasmi::Error> {
+        let (name, value): (Option<String>, Option<String>) = (read_guest_str(&caller, name, name_length)?, read_guest_str(&caller, value, value_length)?);
+        let changed: bool = match (name, value) {
+            (Some(name), Some(value)) => caller.data_mut().set_header(name.as_str(), Some(value)),
+            _ => false,
+        };
+        Ok(if changed { 0 } else { REFUSED })
+    }).unwrap();
+    linker.func_wrap(HOST_MODULE, "remove_header", |mut caller: Caller<'_, Invocation>, name: i32, name_length: i32| -> Result<i32, wasmi::Error> {
+        let changed: bool = match read_guest_str(&caller, name, name_length)? {
+            Some(name) => caller.data_mut().set_header(name.as_str(), None),
+            None => false,
+        };
+        Ok(if changed { 0 } else { REFUSED })
+    }).unwrap();
+    linker.func_wrap(HOST_MODULE, "get_property", |mut caller: Caller<'_, Invocation>, key: i32, key_length: i32, out: i32, capacity: i32| -> Result<i32, wasmi::Error> {
+        let value: Option<Vec<Byte>> = read_guest_str(&caller, key, key_length)?
+            .and_then(|key: String| caller.data().get_property(key.as_str()))
+            .map(String::into_bytes);
+        write_guest(&mut caller, value, out, capacity)
+    }).unwrap();
+    linker.func_wrap(HOST_MODULE, "set_property", |mut caller: Caller<'_, Invocation>, key: i32, key_length: i32, value: i32, value_length: i32| -> Result<i32, wasmi::Error> {
+        let (key, value): (Option<String>, Option<String>) = (read_guest_str(&caller, key, key_length)?, read_guest_str(&caller, value, value_length)?);
+        let changed: bool = match (key, value) {
+            (Some(key), Some(value)) => caller.data_mut().set_property(key.as_str(), value),
+            _ => false,
+        };
+        Ok(if changed { 0 } else { REFUSED })
+    }).unwrap();
+    linker.func_wrap(HOST_MODULE, "get_body", |mut caller: Caller<'_, Invocation>, out: i32, capacity: i32| -> Result<i32, wasmi::Error> {
+        let body: Option<Vec<Byte>> = caller.data().body.clone();
+        write_guest(&mut caller, body, out, capacity)
+    }).unwrap();
+    linker.func_wrap(HOST_MODULE, "set_body", |mut caller: Caller<'_, Invocation>, body: i32, length: i32| -> Result<i32, wasmi::Error> {
+        let body: Vec<Byte> = read_guest(&caller, body, length)?;
+        match caller.data_mut().body.as_mut() {
+            Some(chunk) => {
+                *chunk = body;
+                Ok(0)
+            },
+            None => Ok(REFUSED),
+        }
+    }).unwrap();
+    linker.func_wrap(HOST_MODULE, "set_response_body", |mut caller: Caller<'_, Invocation>, body: i32, length: i32| -> Result<(), wasmi::Error> {
+        caller.data_mut().respond_body = read_guest(&caller, body, length)?;
+        Ok(())
+    }).unwrap();
+    linker.func_wrap(HOST_MODULE, "log", |caller: Caller<'_, Invocation>, text: i32, length: i32| -> Result<(), wasmi::Error> {
+        let text: Vec<Byte> = read_guest(&caller, text, length)?;
+        info!(caller.data().logger, "WASM [{}] {}", caller.data().binding, String::from_utf8_lossy(text.as_slice()));
+        Ok(())
+    }).unwrap();
+    linker
+}
+
+// ---- WasmFilter ----
+
+// A filter loaded from a WebAssembly module. Every hook call runs in a new instance with its own memory and fuel,
+// so nothing the module keeps survives between calls and one connection cannot starve or corrupt another.
+// Hooks return 0 to continue, a status from 100 to 599 to answer with it, anything else closes the connection
+pub struct WasmFilter {
+    rule: WasmFilterRule,
+    engine: Engine,
+    module: Module,
+    linker: Linker<Invocation>,
+    hooks: HashSet<String>, // Exported hooks, the rest are skipped without instantiating the module
+    logger: Logger,
+}
+
+impl WasmFilter {
+    // Compiled once, checked against the host functions and the hook signatures before any traffic reaches it
+    pub fn load(binding: &str, rule: &WasmFilterRule, logger: &Logger) -> io::Result<WasmFilter> {
+        let invalid = |e: String| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unable to load WASM filter [{}] for binding [{}]: {}", rule.module, binding, e)
+        );
+        let bytes: Vec<Byte> = fs::read(rule.module.as_str()).map_err(|e: io::Error| io::Error::new(
+            e.kind(),
+            format!("Unable to read WASM filter [{}] for binding [{}]: {}", rule.module, binding, e)
+        ))?;
+        let mut config: wasmi::Config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine: Engine = Engine::new(&config);
+        let module: Module = Module::new(&engine, bytes.as_slice()).map_err(|e: wasmi::Error| invalid(e.to_string()))?;
+        let mut hooks: HashSet<String> = HashSet::new();
+        let mut exports_memory: bool = false;
+        for export in module.exports() {
+            match (export.name(), export.ty()) {
+                (name, ExternType::Memory(_)) if name == MEMORY_EXPORT => exports_memory = true,
+                (name, ExternType::Func(signature)) if WasmFilter::hook_signature(name).is_some() => {
+                    if Some(signature) != WasmFilter::hook_signature(name).as_ref() {
+                        return Err(invalid(format!("{} has the wrong signature {:?}", name, signature)));
+                    }
+                    hooks.insert(String::from(name));
+                },
+                _ => {},
+            }
+        }
+        if !exports_memory {
+            return Err(invalid(String::from("module exports no memory")));
+        }
+        let filter: WasmFilter = WasmFilter {
+            rule: rule.clone(),
+            linker: host_functions(&engine),
+            engine,
+            module,
+            hooks,
+            logger: logger.clone(),
+        };
+        let connection: FilterConnection = FilterConnection {
+            id: uuid::Uuid::nil(),
+            binding: String::new(),
+            client: SocketAddr::from(([127, 0, 0, 1], 0)),
+            upstream: None,
+        };
+        filter.instantiate(Invocation::new(&filter, &connection, HookMessage::CONNECTION)).map_err(|e: wasmi::Error| invalid(e.to_string()))?;
+        Ok(filter)
+    }
+    fn hook_signature(name: &str) -> Option<FuncType> {
+        match name {
+            _ if name == ON_CONNECT || name == ON_REQUEST_HEADERS || name == ON_RESPONSE_HEADERS => Some(FuncType::new([], [ValType::I32])),
+            _ if name == ON_REQUEST_BODY || name == ON_RESPONSE_BODY => Some(FuncType::new([ValType::I32], [ValType::I32])),
+            _ => None,
+        }
+    }
+    fn instantiate(&self, invocation: Invocation) -> Result<(Store<Invocation>, wasmi::Instance), wasmi::Error> {
+        let mut store: Store<Invocation> = Store::new(&self.engine, invocation);
+        store.limiter(|invocation: &mut Invocation| &mut invocation.limits);
+        store.set_fuel(self.rule.max_fuel)?;
+        let instance: wasmi::Instance = self.linker.instantiate(&mut store, &self.module)?.start(&mut store)?;
+        Ok((store, instance))
+    }
+    // The invocation comes back with whatever the hook changed, even when it failed part way
+    fn invoke(&self, hook: &str, end_of_body: Option<bool>, invocation: Invocation) -> (Result<i32, wasmi::Error>, Option<Invocation>) {
+        let (mut store, instance): (Store<Invocation>, wasmi::Instance) = match self.instantiate(invocation) {
+            Ok(instantiated) => instantiated,
+            Err(e) => return (Err(e), None),
+        };
+        let result: Result<i32, wasmi::Error> = match end_of_body {
+            None => instance.get_typed_func::<(), i32>(&store, hook)
+                .and_then(|function: TypedFunc<(), i32>| function.call(&mut store, ())),
+            Some(end_of_body) => instance.get_typed_func::<i32, i32>(&store, hook)
+                .and_then(|function: TypedFunc<i32, i32>| function.call(&mut store, end_of_body as i32)),
+        };
+        (result, Some(store.into_data()))
+    }
+    // A module that traps, runs out of fuel or outgrows its memory fails closed
+    fn decide(&self, hook: &str, connection: &FilterConnection, result: Result<i32, wasmi::Error>, respond_body: Vec<Byte>) -> FilterAction {
+        match result {
+            Ok(0) => FilterAction::CONTINUE,
+            Ok(status) if (100..=599).contains(&status) => FilterAction::RESPOND(HttpResponseHead::local(status as u16), respond_body),
+            Ok(_) => FilterAction::CLOSE,
+            Err(e) => {
+                info!(self.logger, "WASM filter [{}] failed in {} on binding [{}]: {}", self.rule.module, hook, connection.binding, e);
+                FilterAction::RESPOND(HttpResponseHead::local(500), vec![])
+            },
+        }
+    }
+    fn on_head(&self, hook: &str, connection: &FilterConnection, invocation: Invocation) -> (FilterAction, Option<Invocation>) {
+        let (result, invocation): (Result<i32, wasmi::Error>, Option<Invocation>) = self.invoke(hook, None, invocation);
+        let succeeded: bool = result.is_ok();
+        let respond_body: Vec<Byte> = invocation.as_ref().map_or(vec![], |invocation: &Invocation| invocation.respond_body.clone());
+        (self.decide(hook, connection, result, respond_body), invocation.filter(|_| succeeded))
+    }
+    fn on_body(&self, hook: &str, connection: &FilterConnection, mut invocation: Invocation, chunk: &mut Vec<Byte>, end_of_body: bool) -> FilterAction {
+        invocation.body = Some(std::mem::take(chunk));
+        let (result, invocation): (Result<i32, wasmi::Error>, Option<Invocation>) = self.invoke(hook, Some(end_of_body), invocation);
+        match invocation {
+            Some(invocation) => {
+                *chunk = invocation.body.unwrap_or_default();
+                self.decide(hook, connection, result, invocation.respond_body)
+            },
+            None => self.decide(hook, connection, result, vec![]),
+        }
+    }
+}
+
+impl Filter for WasmFilter {
+    fn on_connect(&self, connection: &FilterConnection) -> FilterAction {
+        if !self.hooks.contains(ON_CONNECT) {
+            return FilterAction::CONTINUE;
+        }
+        self.on_head(ON_CONNECT, connection, Invocation::new(self, connection, HookMessage::CONNECTION)).0
+    }
+    fn on_request_headers(&self, connection: &FilterConnection, request: &mut HttpRequestHead) -> FilterAction {
+        if !self.hooks.contains(ON_REQUEST_HEADERS) {
+            return FilterAction::CONTINUE;
+        }
+        let mut invocation: Invocation = Invocation::new(self, connection, HookMessage::REQUEST);
+        invocation.editable = true;
+        invocation.request = Some(request.clone());
+        let (action, invocation): (FilterAction, Option<Invocation>) = self.on_head(ON_REQUEST_HEADERS, connection, invocation);
+        if let Some(edited) = invocation.and_then(|invocation: Invocation| invocation.request) {
+            *request = edited;
+        }
+        action
+    }
+    fn on_request_body_chunk(&self, connection: &FilterConnection, request: &HttpRequestHead, chunk: &mut Vec<Byte>, end_of_body: bool) -> FilterAction {
+        if !self.hooks.contains(ON_REQUEST_BODY) {
+            return FilterAction::CONTINUE;
+        }
+        let mut invocation: Invocation = Invocation::new(self, connection, HookMessage::REQUEST);
+        invocation.request = Some(request.clone());
+        self.on_body(ON_REQUEST_BODY, connection, invocation, chunk, end_of_body)
+    }
+    fn on_response_headers(&self, connection: &FilterConnection, request: Option<&HttpRequestHead>, response: &mut HttpResponseHead) -> FilterAction {
+        if !self.hooks.contains(ON_RESPONSE_HEADERS) {
+            return FilterAction::CONTINUE;
+        }
+        let mut invocation: Invocation = Invocation::new(self, connection, HookMessage::RESPONSE);
+        invocation.editable = true;
+        invocation.request = request.cloned();
+        invocation.response = Some(response.clone());
+        let (action, invocation): (FilterAction, Option<Invocation>) = self.on_head(ON_RESPONSE_HEADERS, connection, invocation);
+        if let Some(edited) = invocation.and_then(|invocation: Invocation| invocation.response) {
+            *response = edited;
+        }
+        action
+    }
+    fn on_response_body_chunk(&self, connection: &FilterConnection, response: &HttpResponseHead, chunk: &mut Vec<Byte>, end_of_body: bool) -> FilterAction {
+        if !self.hooks.contains(ON_RESPONSE_BODY) {
+            return FilterAction::CONTINUE;
+        }
+        let mut invocation: Invocation = Invocation::new(self, connection, HookMessage::RESPONSE);
+        invocation.response = Some(response.clone());
+        self.on_body(ON_RESPONSE_BODY, connection, invocation, chunk, end_of_body)
+    }
+}